@@ -0,0 +1,483 @@
+//! # archives
+//!
+//! ZIP and TAR archives (`.zip`, `.tar`, `.tgz`, `.tar.gz`) of the source directories are
+//! read in place : their entries are streamed, one at a time, through the same reading and
+//! sorting as the other files, without extracting the archive anywhere. An entry is named
+//! `archive.zip!/path/in/archive.jpg`.
+//!
+//! The readers of metadata open their files with `open` : the entries loaded in memory are
+//! read from there, the other files from the disk. To bound the memory, only the beginning
+//! and the end of the large entries are loaded to read their metadata. The copies of the
+//! entries not in memory are reserved by `copy`, then written when the archive is streamed
+//! again, each entry straight to its destinations.
+
+use chrono::NaiveDateTime;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+const ENTRY_SEPARATOR: &str = "!/";
+// the metadata are at the beginning of the files, but the movie atom of a video may be at
+// its end
+const LOADED_PREFIX_SIZE: u64 = 16 * 1024 * 1024;
+const LOADED_SUFFIX_SIZE: usize = 16 * 1024 * 1024;
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Entries loaded in memory, by path
+static LOADED_ENTRIES: Lazy<RwLock<HashMap<PathBuf, Arc<LoadedEntry>>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// Destinations of the copies of entries not yet written, by path of the entry
+static PENDING_COPIES: Lazy<RwLock<HashMap<PathBuf, Vec<PathBuf>>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+
+pub trait ReadSeek: Read + Seek + Send {}
+impl<T: Read + Seek + Send> ReadSeek for T {}
+
+/// An entry of an archive, being read
+pub struct ArchiveEntry<'a> {
+    /// `archive.zip!/path/in/archive.jpg`
+    pub path: PathBuf,
+    pub size: u64,
+    /// Last modification time, in the local time zone
+    pub modified: Option<NaiveDateTime>,
+    pub reader: &'a mut dyn Read,
+}
+
+/// The beginning and the end of an entry (the whole entry when `suffix` is empty)
+struct LoadedEntry {
+    prefix: Vec<u8>,
+    suffix: Vec<u8>,
+    size: u64,
+    modified: Option<NaiveDateTime>,
+}
+
+impl LoadedEntry {
+    /// The whole entry, when it is in memory
+    fn complete_data(&self) -> std::io::Result<&[u8]> {
+        if self.suffix.is_empty() {
+            Ok(&self.prefix)
+        } else {
+            Err(std::io::Error::other("archive entry partially loaded"))
+        }
+    }
+}
+
+/// Reader of an entry loaded in memory, shared by its readers : the part between the
+/// prefix and the suffix of a large entry can't be read
+struct LoadedReader {
+    entry: Arc<LoadedEntry>,
+    position: u64,
+}
+
+impl Read for LoadedReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let LoadedEntry { prefix, suffix, size, .. } = self.entry.as_ref();
+        if self.position >= *size {
+            return Ok(0);
+        }
+        let suffix_start = size - suffix.len() as u64;
+        let available = if self.position < prefix.len() as u64 {
+            &prefix[self.position as usize..]
+        } else if self.position >= suffix_start {
+            &suffix[(self.position - suffix_start) as usize..]
+        } else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "middle of a large archive entry not loaded",
+            ));
+        };
+        let count = available.len().min(buf.len());
+        buf[..count].copy_from_slice(&available[..count]);
+        self.position += count as u64;
+        Ok(count)
+    }
+}
+
+impl Seek for LoadedReader {
+    fn seek(&mut self, position: SeekFrom) -> std::io::Result<u64> {
+        let position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.entry.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "seek before the beginning of the entry")
+        })?;
+        Ok(self.position)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+fn archive_kind(path: &Path) -> Option<ArchiveKind> {
+    let name = path.file_name()?.to_string_lossy().to_lowercase();
+    if name.ends_with(".zip") {
+        Some(ArchiveKind::Zip)
+    } else if name.ends_with(".tar") {
+        Some(ArchiveKind::Tar)
+    } else if name.ends_with(".tgz") || name.ends_with(".tar.gz") {
+        Some(ArchiveKind::TarGz)
+    } else {
+        None
+    }
+}
+
+/// Is the file a ZIP or TAR archive (by its extension) ?
+pub fn is_archive(path: &Path) -> bool {
+    archive_kind(path).is_some()
+}
+
+/// Path of an entry of an archive
+pub fn entry_path(archive: &Path, name: &str) -> PathBuf {
+    let name = name.trim_start_matches("./").trim_start_matches('/');
+    PathBuf::from(format!("{}{}{}", archive.display(), ENTRY_SEPARATOR, name))
+}
+
+fn is_entry(path: &Path) -> bool {
+    path.to_string_lossy().contains(ENTRY_SEPARATOR)
+}
+
+/// Size of the files of an archive once expanded, read from the headers of its entries
+pub fn expanded_size(archive: &Path) -> std::io::Result<u64> {
+    let mut size = 0;
+    for_each_entry(archive, |entry| {
+        size += entry.size;
+        Ok(())
+    })?;
+    Ok(size)
+}
+
+/// Call `f` for each file of an archive, in the order of the archive
+pub fn for_each_entry(
+    archive: &Path,
+    mut f: impl FnMut(ArchiveEntry) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    log::trace!("for_each_entry of {:?}", archive);
+    let file = std::io::BufReader::new(std::fs::File::open(archive)?);
+    match archive_kind(archive) {
+        Some(ArchiveKind::Zip) => {
+            let mut zip = zip::ZipArchive::new(file).map_err(std::io::Error::other)?;
+            for index in 0..zip.len() {
+                let mut entry = zip.by_index(index).map_err(std::io::Error::other)?;
+                if !entry.is_file() {
+                    continue;
+                }
+                // MS-DOS time, in the local time zone
+                let modified = entry.last_modified().and_then(|m| {
+                    chrono::NaiveDate::from_ymd_opt(m.year() as i32, m.month() as u32, m.day() as u32)?
+                        .and_hms_opt(m.hour() as u32, m.minute() as u32, m.second() as u32)
+                });
+                let path = entry_path(archive, entry.name());
+                f(ArchiveEntry {
+                    path,
+                    size: entry.size(),
+                    modified,
+                    reader: &mut entry,
+                })?;
+            }
+            Ok(())
+        }
+        Some(ArchiveKind::Tar) => for_each_tar_entry(archive, file, f),
+        Some(ArchiveKind::TarGz) => for_each_tar_entry(archive, flate2::bufread::GzDecoder::new(file), f),
+        None => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "not an archive")),
+    }
+}
+
+fn for_each_tar_entry(
+    archive: &Path,
+    reader: impl Read,
+    mut f: impl FnMut(ArchiveEntry) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let mut tar = tar::Archive::new(reader);
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path()?.to_string_lossy().to_string();
+        let modified = entry
+            .header()
+            .mtime()
+            .ok()
+            .and_then(|mtime| chrono::DateTime::from_timestamp(mtime as i64, 0))
+            .map(|mtime| mtime.with_timezone(&chrono::Local).naive_local());
+        f(ArchiveEntry {
+            path: entry_path(archive, &name),
+            size: entry.size(),
+            modified,
+            reader: &mut entry,
+        })?;
+    }
+    Ok(())
+}
+
+/// Read an entry in memory, until `unload` : the whole entry if it is small, else its
+/// beginning and its end, where the metadata are. The size written in the header of the
+/// entry is not trusted.
+pub fn load(entry: ArchiveEntry) -> std::io::Result<()> {
+    let mut prefix = Vec::new();
+    (&mut *entry.reader).take(LOADED_PREFIX_SIZE).read_to_end(&mut prefix)?;
+    let mut size = prefix.len() as u64;
+    // the last bytes, read by chunks
+    let mut suffix = Vec::new();
+    let mut chunk = vec![0u8; READ_CHUNK_SIZE];
+    loop {
+        let count = match entry.reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(count) => count,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        size += count as u64;
+        suffix.extend_from_slice(&chunk[..count]);
+        if suffix.len() >= 2 * LOADED_SUFFIX_SIZE {
+            suffix.drain(..suffix.len() - LOADED_SUFFIX_SIZE);
+        }
+    }
+    if suffix.len() > LOADED_SUFFIX_SIZE {
+        suffix.drain(..suffix.len() - LOADED_SUFFIX_SIZE);
+    }
+    if !suffix.is_empty() {
+        log::debug!("{:?} partially loaded ({} bytes)", entry.path, size);
+    }
+    let loaded = LoadedEntry {
+        prefix,
+        suffix,
+        size,
+        modified: entry.modified,
+    };
+    LOADED_ENTRIES.write().unwrap().insert(entry.path, Arc::new(loaded));
+    Ok(())
+}
+
+pub fn is_loaded(path: &Path) -> bool {
+    LOADED_ENTRIES.read().unwrap().contains_key(path)
+}
+
+/// Is a copy of the entry waiting for the entry to be streamed ?
+pub fn is_pending(path: &Path) -> bool {
+    PENDING_COPIES.read().unwrap().contains_key(path)
+}
+
+pub fn unload(path: &Path) {
+    LOADED_ENTRIES.write().unwrap().remove(path);
+}
+
+/// Forget all the loaded entries of an archive, and the copies of its entries not written :
+/// their reserved destinations are removed
+pub fn unload_archive(archive: &Path) {
+    let prefix = format!("{}{}", archive.display(), ENTRY_SEPARATOR);
+    LOADED_ENTRIES.write().unwrap().retain(|path, _| !path.to_string_lossy().starts_with(&prefix));
+    PENDING_COPIES.write().unwrap().retain(|path, destinations| {
+        let kept = !path.to_string_lossy().starts_with(&prefix);
+        if !kept {
+            for destination in destinations.iter() {
+                log::warn!("{:?} not written in {:?}", path, destination);
+                if let Err(e) = std::fs::remove_file(destination) {
+                    log::warn!("Error {:?} when removing {:?}", e, destination);
+                }
+            }
+        }
+        kept
+    });
+}
+
+fn loaded(path: &Path) -> Option<Arc<LoadedEntry>> {
+    LOADED_ENTRIES.read().unwrap().get(path).cloned()
+}
+
+/// Open a loaded entry, or a file
+pub fn open(path: &Path) -> std::io::Result<Box<dyn ReadSeek>> {
+    match loaded(path) {
+        Some(entry) => Ok(Box::new(LoadedReader { entry, position: 0 })),
+        None => Ok(Box::new(std::fs::File::open(path)?)),
+    }
+}
+
+pub fn read(path: &Path) -> std::io::Result<Vec<u8>> {
+    match loaded(path) {
+        Some(entry) => Ok(entry.complete_data()?.to_vec()),
+        None => std::fs::read(path),
+    }
+}
+
+/// Names of the files of a directory : the loaded entries of a directory of an archive, or
+/// the files on the disk
+pub fn file_names(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = LOADED_ENTRIES
+        .read()
+        .unwrap()
+        .keys()
+        .filter(|path| path.parent() == Some(dir))
+        .filter_map(|path| Some(path.file_name()?.to_string_lossy().to_string()))
+        .collect();
+    if let Ok(entries) = std::fs::read_dir(dir) {
+        names.extend(
+            entries
+                .flatten()
+                .filter(|entry| entry.file_type().is_ok_and(|t| t.is_file()))
+                .map(|entry| entry.file_name().to_string_lossy().to_string()),
+        );
+    }
+    names
+}
+
+pub fn is_file(path: &Path) -> bool {
+    is_loaded(path) || path.is_file()
+}
+
+pub fn len(path: &Path) -> std::io::Result<u64> {
+    match loaded(path) {
+        Some(entry) => Ok(entry.size),
+        None => Ok(std::fs::metadata(path)?.len()),
+    }
+}
+
+/// Last modification time, in the local time zone
+pub fn modified(path: &Path) -> std::io::Result<NaiveDateTime> {
+    match loaded(path) {
+        Some(entry) => entry
+            .modified
+            .ok_or_else(|| std::io::Error::other("no modification time in the archive")),
+        None => {
+            let modified = std::fs::metadata(path)?.modified()?;
+            Ok(chrono::DateTime::<chrono::Local>::from(modified).naive_local())
+        }
+    }
+}
+
+/// Copy a whole entry loaded in memory, or a file, and return the number of bytes copied.
+/// The copy of an entry not in memory is only reserved, with an empty file, until the entry
+/// is written by `write_pending` : None is returned.
+pub fn copy(from: &Path, to: &Path) -> std::io::Result<Option<u64>> {
+    match loaded(from) {
+        Some(entry) => {
+            let data = entry.complete_data()?;
+            std::fs::write(to, data)?;
+            Ok(Some(data.len() as u64))
+        }
+        None if is_entry(from) => {
+            std::fs::File::create(to)?;
+            log::trace!("Copy of {:?} in {:?} pending", from, to);
+            PENDING_COPIES.write().unwrap().entry(from.to_path_buf()).or_default().push(to.to_path_buf());
+            Ok(None)
+        }
+        None => std::fs::copy(from, to).map(Some),
+    }
+}
+
+/// Write an entry in the destinations reserved by `copy`, as it is read. Return the
+/// destinations written, and the number of bytes written in each.
+pub fn write_pending(entry: ArchiveEntry) -> std::io::Result<(Vec<PathBuf>, u64)> {
+    let Some(destinations) = PENDING_COPIES.write().unwrap().remove(&entry.path) else {
+        return Ok((Vec::new(), 0));
+    };
+    // the first destination is written from the archive, the others from the first one
+    let written = std::fs::File::create(&destinations[0]).and_then(|file| {
+        let mut file = std::io::BufWriter::new(file);
+        let size = std::io::copy(entry.reader, &mut file)?;
+        std::io::Write::flush(&mut file)?;
+        for destination in &destinations[1..] {
+            std::fs::copy(&destinations[0], destination)?;
+        }
+        Ok(size)
+    });
+    match written {
+        Ok(size) => Ok((destinations, size)),
+        Err(e) => {
+            for destination in &destinations {
+                let _ = std::fs::remove_file(destination);
+            }
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    #[test]
+    fn test_for_each_entry() {
+        init();
+        let current_dir = std::env::current_dir().unwrap();
+        let dir = Path::new("./test_for_each_entry");
+        std::fs::create_dir(dir).unwrap();
+        let image = std::fs::read("data_4_tests/DSCN0025.jpg").unwrap();
+
+        let zip_path = dir.join("backup.zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&zip_path).unwrap());
+        let options = zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        zip.add_directory("DCIM/", options).unwrap();
+        zip.start_file("DCIM/DSCN0025.jpg", options).unwrap();
+        std::io::Write::write_all(&mut zip, &image).unwrap();
+        zip.finish().unwrap();
+
+        let tgz_path = dir.join("backup.tar.gz");
+        let encoder = flate2::write::GzEncoder::new(std::fs::File::create(&tgz_path).unwrap(), flate2::Compression::fast());
+        let mut tar = tar::Builder::new(encoder);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(image.len() as u64);
+        header.set_mtime(1224686601);
+        header.set_cksum();
+        tar.append_data(&mut header, "./DCIM/DSCN0025.jpg", image.as_slice()).unwrap();
+        tar.into_inner().unwrap().finish().unwrap();
+
+        for archive in [&zip_path, &tgz_path] {
+            assert!(is_archive(archive));
+            let mut paths = Vec::new();
+            for_each_entry(archive, |entry| {
+                paths.push(entry.path.clone());
+                load(entry)
+            })
+            .unwrap();
+            assert_eq!(paths, vec![entry_path(archive, "DCIM/DSCN0025.jpg")]);
+            let path = &paths[0];
+            assert_eq!(path.to_string_lossy(), format!("{}!/DCIM/DSCN0025.jpg", archive.display()));
+            // read from memory
+            assert!(is_file(path));
+            assert_eq!(file_names(path.parent().unwrap()), vec!["DSCN0025.jpg"]);
+            assert_eq!(len(path).unwrap(), image.len() as u64);
+            let mut read = Vec::new();
+            open(path).unwrap().read_to_end(&mut read).unwrap();
+            assert_eq!(read, image);
+            unload_archive(archive);
+            assert!(!is_file(path));
+
+            // copied when streamed again
+            let target = dir.join("DSCN0025.jpg");
+            assert_eq!(copy(path, &target).unwrap(), None);
+            assert_eq!(std::fs::metadata(&target).unwrap().len(), 0);
+            for_each_entry(archive, |entry| {
+                assert_eq!(write_pending(entry)?, (vec![target.clone()], image.len() as u64));
+                Ok(())
+            })
+            .unwrap();
+            assert_eq!(std::fs::read(&target).unwrap(), image);
+            std::fs::remove_file(&target).unwrap();
+            assert_eq!(expanded_size(archive).unwrap(), image.len() as u64);
+
+            // the reserved destinations of the entries not written are removed
+            copy(path, &target).unwrap();
+            assert!(target.exists());
+            unload_archive(archive);
+            assert!(!target.exists());
+        }
+
+        // ensure we are in the good directory before cleanup
+        assert_eq!(current_dir, std::env::current_dir().unwrap());
+        // cleanup
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,314 @@
+//! # calendar
+//!
+//! Named events of a user calendar (birthdays, holidays...), read from an iCalendar file
+//! (`.ics`) or a CSV file :
+//!
+//! ```text
+//! name,start,end
+//! Christmas,2023-12-24,2023-12-26
+//! Birthday of Alice,2024-03-02 14:00,2024-03-02 20:00
+//! ```
+//!
+//! In the CSV file, an end date without time is inclusive (the whole day). In the iCalendar
+//! file, the yearly recurring events (birthdays...) are repeated up to the next year.
+
+use crate::exif::parse_datetime;
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime};
+use std::path::Path;
+
+const CSV_HEADER: &str = "name,start,end";
+
+#[derive(thiserror::Error, Debug)]
+pub enum CalendarError {
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+    #[error("line {line} of the calendar : {reason}")]
+    Parse { line: usize, reason: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalendarEvent {
+    pub name: String,
+    pub start: NaiveDateTime,
+    /// Exclusive
+    pub end: NaiveDateTime,
+}
+
+impl CalendarEvent {
+    pub fn contains(&self, time: NaiveDateTime) -> bool {
+        self.start <= time && time < self.end
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.end - self.start
+    }
+
+    pub fn overlaps(&self, other: &CalendarEvent) -> bool {
+        self.start < other.end && other.start < self.end
+    }
+}
+
+/// Read a calendar, in iCalendar format if the extension is `.ics`, in CSV otherwise
+pub fn read_calendar(path: &Path) -> Result<Vec<CalendarEvent>, CalendarError> {
+    log::trace!("read_calendar {:?}", path);
+    let content = std::fs::read_to_string(path)?;
+    let is_ics = path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("ics"));
+    let events = if is_ics {
+        parse_ics(&content)?
+    } else {
+        parse_csv(&content)?
+    };
+    log::debug!("{} calendar events read", events.len());
+    Ok(events)
+}
+
+/// The event used for a capture time : among the events containing it, the shortest (the
+/// most specific) one, then the first one of the calendar
+pub fn find_event(events: &[CalendarEvent], time: NaiveDateTime) -> Option<&CalendarEvent> {
+    events
+        .iter()
+        .filter(|e| e.contains(time))
+        .min_by_key(|e| e.duration())
+}
+
+/// Pairs of overlapping events
+pub fn find_overlaps(events: &[CalendarEvent]) -> Vec<(&CalendarEvent, &CalendarEvent)> {
+    let mut overlaps = Vec::new();
+    for (i, a) in events.iter().enumerate() {
+        for b in &events[i + 1..] {
+            if a.overlaps(b) {
+                overlaps.push((a, b));
+            }
+        }
+    }
+    overlaps
+}
+
+fn parse_csv(content: &str) -> Result<Vec<CalendarEvent>, CalendarError> {
+    let mut events = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line == CSV_HEADER {
+            continue;
+        }
+        let error = |reason: String| CalendarError::Parse {
+            line: index + 1,
+            reason,
+        };
+        // the name may contain commas, the dates don't
+        let mut fields = line.rsplitn(3, ',');
+        let (Some(end), Some(start), Some(name)) = (fields.next(), fields.next(), fields.next()) else {
+            return Err(error(format!("expected {}", CSV_HEADER)));
+        };
+        let date = |value: &str| -> Result<NaiveDateTime, CalendarError> {
+            parse_datetime(value.trim())
+                .map_err(error)?
+                .ok_or_else(|| error(String::from("empty date")))
+        };
+        let end_has_time = end.trim().len() > "2023-12-24".len();
+        let (start, mut end) = (date(start)?, date(end)?);
+        if !end_has_time {
+            // the whole last day
+            end += Duration::days(1);
+        }
+        if end <= start {
+            return Err(error(format!("event {} ends before it starts", name)));
+        }
+        events.push(CalendarEvent {
+            name: name.trim().trim_matches('"').to_string(),
+            start,
+            end,
+        });
+    }
+    Ok(events)
+}
+
+/// VEVENT being read
+#[derive(Default)]
+struct PendingEvent {
+    name: Option<String>,
+    /// The start, and whether it is a whole day
+    start: Option<(NaiveDateTime, bool)>,
+    end: Option<NaiveDateTime>,
+    /// RRULE
+    recurrence: Option<String>,
+}
+
+fn parse_ics(content: &str) -> Result<Vec<CalendarEvent>, CalendarError> {
+    // long lines are folded : the continuation lines start with a space or a tab
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continuation), Some((_, last))) => last.push_str(continuation),
+            _ => lines.push((index + 1, line.to_string())),
+        }
+    }
+
+    let mut events = Vec::new();
+    let mut current: Option<PendingEvent> = None;
+    for (line_number, line) in lines {
+        let error = |reason: String| CalendarError::Parse {
+            line: line_number,
+            reason,
+        };
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        // properties may have parameters : DTSTART;VALUE=DATE:20231224
+        let (name, parameters) = key.split_once(';').unwrap_or((key, ""));
+        match (name, current.as_mut()) {
+            ("BEGIN", _) if value == "VEVENT" => current = Some(PendingEvent::default()),
+            ("SUMMARY", Some(event)) => event.name = Some(unescape_ics(value)),
+            ("DTSTART", Some(event)) => {
+                event.start = Some(parse_ics_datetime(value, parameters).ok_or_else(|| error(format!("invalid date {}", value)))?)
+            }
+            ("RRULE", Some(event)) => event.recurrence = Some(value.to_string()),
+            ("DTEND", Some(event)) => {
+                event.end = Some(parse_ics_datetime(value, parameters).ok_or_else(|| error(format!("invalid date {}", value)))?.0)
+            }
+            ("END", Some(_)) if value == "VEVENT" => {
+                // unwrap() is ok here, checked by the match guard
+                let PendingEvent { name, start, end, recurrence } = current.take().unwrap();
+                let (Some(name), Some((start, whole_day))) = (name, start) else {
+                    log::warn!("calendar event ending line {} without name or start, ignored", line_number);
+                    continue;
+                };
+                // without end, the event lasts the whole day
+                let end = end.unwrap_or_else(|| {
+                    if whole_day {
+                        start + Duration::days(1)
+                    } else {
+                        start.date().and_hms_opt(0, 0, 0).unwrap() + Duration::days(1)
+                    }
+                });
+                let event = CalendarEvent { name, start, end };
+                match recurrence {
+                    Some(rule) => events.extend(repeat_event(&event, &rule)),
+                    None => events.push(event),
+                }
+            }
+            _ => (),
+        }
+    }
+    Ok(events)
+}
+
+/// Occurrences of a recurring event, up to the next year. Only the yearly recurrences
+/// (FREQ=YEARLY, with INTERVAL, COUNT or UNTIL) are repeated, the other events keep their first
+/// occurrence only.
+fn repeat_event(event: &CalendarEvent, rule: &str) -> Vec<CalendarEvent> {
+    let parts: Vec<(&str, &str)> = rule.split(';').filter_map(|part| part.split_once('=')).collect();
+    let part = |key: &str| parts.iter().find(|(k, _)| *k == key).map(|(_, v)| *v);
+    // BYMONTH, BYDAY... would move the occurrences away from the date of the first one
+    let unsupported = parts.iter().any(|(key, _)| key.starts_with("BY"));
+    if part("FREQ") != Some("YEARLY") || unsupported {
+        log::warn!(
+            "recurrence {} of the calendar event {} not supported, only its first occurrence is used",
+            rule,
+            event.name
+        );
+        return vec![event.clone()];
+    }
+    let interval = part("INTERVAL").and_then(|i| i.parse::<i32>().ok()).filter(|i| *i > 0).unwrap_or(1);
+    let count = part("COUNT").and_then(|c| c.parse::<usize>().ok());
+    let until = part("UNTIL").and_then(|u| parse_ics_datetime(u, "")).map(|(until, _)| until);
+    let last_year = chrono::Local::now().year() + 1;
+    let duration = event.duration();
+    (event.start.year()..=last_year)
+        .step_by(interval as usize)
+        // February 29 is skipped in the other years
+        .filter_map(|year| event.start.with_year(year))
+        .take_while(|start| until.is_none_or(|until| *start <= until))
+        .take(count.unwrap_or(usize::MAX))
+        .map(|start| CalendarEvent {
+            name: event.name.clone(),
+            start,
+            end: start + duration,
+        })
+        .collect()
+}
+
+/// Parse `20231224` (a whole day), `20231224T180000` (local time) or `20231224T170000Z`
+/// (UTC, converted to the local time zone). Return the date and whether it is a whole day.
+fn parse_ics_datetime(value: &str, parameters: &str) -> Option<(NaiveDateTime, bool)> {
+    let value = value.trim();
+    if parameters.contains("VALUE=DATE") && !parameters.contains("VALUE=DATE-TIME") || value.len() == 8 {
+        let date = NaiveDate::parse_from_str(value, "%Y%m%d").ok()?;
+        return Some((date.and_hms_opt(0, 0, 0)?, true));
+    }
+    match value.strip_suffix('Z') {
+        Some(utc) => {
+            let utc = NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").ok()?;
+            let local = chrono::DateTime::<chrono::Local>::from(utc.and_utc()).naive_local();
+            Some((local, false))
+        }
+        None => Some((NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()?, false)),
+    }
+}
+
+fn unescape_ics(value: &str) -> String {
+    value
+        .replace("\\n", " ")
+        .replace("\\N", " ")
+        .replace("\\,", ",")
+        .replace("\\;", ";")
+        .replace("\\\\", "\\")
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    #[test]
+    fn test_calendar() {
+        init();
+        let at = |m, d, h| NaiveDate::from_ymd_opt(2023, m, d).unwrap().and_hms_opt(h, 0, 0).unwrap();
+        let ics = "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nSUMMARY:Christmas\r\n\
+            DTSTART;VALUE=DATE:20231224\r\nDTEND;VALUE=DATE:20231226\r\nEND:VEVENT\r\n\
+            BEGIN:VEVENT\r\nSUMMARY:Dinner with the Smiths\\, Paul and\r\n  Mary\r\n\
+            DTSTART;TZID=Europe/Paris:20231224T190000\r\nDTEND;TZID=Europe/Paris:20231224T230000\r\n\
+            END:VEVENT\r\nEND:VCALENDAR\r\n";
+        let events = parse_ics(ics).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].name, "Christmas");
+        assert_eq!((events[0].start, events[0].end), (at(12, 24, 0), at(12, 26, 0)));
+        assert_eq!(events[1].name, "Dinner with the Smiths, Paul and Mary");
+
+        // the shortest event wins
+        assert_eq!(find_event(&events, at(12, 24, 20)).unwrap().name, events[1].name);
+        assert_eq!(find_event(&events, at(12, 25, 20)).unwrap().name, "Christmas");
+        assert_eq!(find_event(&events, at(12, 26, 0)), None);
+        assert_eq!(find_overlaps(&events).len(), 1);
+
+        let ics = "BEGIN:VEVENT\nSUMMARY:Birthday of Alice\nDTSTART;VALUE=DATE:20200302\n\
+            RRULE:FREQ=YEARLY;COUNT=4\nEND:VEVENT\n\
+            BEGIN:VEVENT\nSUMMARY:Yoga\nDTSTART:20230301T180000\nDTEND:20230301T190000\n\
+            RRULE:FREQ=WEEKLY\nEND:VEVENT\n";
+        let events = parse_ics(ics).unwrap();
+        assert_eq!(events.len(), 5);
+        assert_eq!(find_event(&events, at(3, 2, 12)).unwrap().name, "Birthday of Alice");
+        assert_eq!(events[3].end, at(3, 3, 0));
+        // not repeated
+        assert_eq!(events[4].name, "Yoga");
+
+        let csv = "name,start,end\nHolidays, in Italy,2023-07-01,2023-07-14\n\
+            Birthday,2023-03-02 14:00,2023-03-02 20:00\n";
+        let events = parse_csv(csv).unwrap();
+        assert_eq!(events[0].name, "Holidays, in Italy");
+        assert_eq!(events[0].end, at(7, 15, 0));
+        assert_eq!(events[1].end, at(3, 2, 20));
+        assert!(find_overlaps(&events).is_empty());
+        assert!(matches!(
+            parse_csv("Birthday,2023-03-02 14:00"),
+            Err(CalendarError::Parse { line: 1, .. })
+        ));
+    }
+}
@@ -0,0 +1,247 @@
+//! # classifier
+//!
+//! Images which are not photos of a camera : screenshots, images received with a messaging
+//! app, scans and exports of photo editors. They are recognized by their name and their
+//! metadata, and copied in their own bucket (`Screenshots/`...) instead of the dated tree or
+//! `Unsorted/`.
+
+use crate::dimensions;
+use crate::exif::ExifData;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::path::{Path, PathBuf};
+
+// Screenshot_20210304-101112.png, Screen Shot 2021-03-04 at 10.11.12.png,
+// Capture d’écran 2021-03-04 à 10.11.12.png...
+static SCREENSHOT_NAME_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)^(screenshot|screen shot|capture d.[ée]cran|bildschirmfoto|schermata|captura de pantalla)")
+        .unwrap()
+});
+
+// WhatsApp : IMG-20210304-WA0001.jpg, WhatsApp Image 2021-03-04 at 10.11.12.jpeg
+// Telegram : photo_2021-03-04_10-11-12.jpg
+// Signal : signal-2021-03-04-101112.jpg, signal-2021-03-04-10-11-12-345.jpg
+static MESSENGER_NAME_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?i)^((img|vid)-\d{8}-wa\d+|whatsapp (image|video) \d{4}-\d{2}-\d{2}|(photo|video)_\d{4}-\d{2}-\d{2}_\d{2}-\d{2}-\d{2}|signal-\d{4}-\d{2}-\d{2}-\d)",
+    )
+    .unwrap()
+});
+
+// Software of scanners, or Make / Model of the scanner itself
+static SCANNER_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)scan|silverfast|naps2|perfection").unwrap());
+
+// Software of photo editors
+static EDITOR_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?i)photoshop|lightroom|gimp|affinity|capture one|darktable|rawtherapee|snapseed|pixelmator|luminar|dxo|acdsee|paint\.net|picasa",
+    )
+    .unwrap()
+});
+
+// Sizes of common screens of phones, tablets and computers (short side, long side)
+const SCREEN_SIZES: [(u32, u32); 28] = [
+    (720, 1280),
+    (750, 1334),
+    (768, 1024),
+    (768, 1366),
+    (800, 1280),
+    (828, 1792),
+    (864, 1536),
+    (900, 1440),
+    (900, 1600),
+    (1050, 1680),
+    (1080, 1920),
+    (1080, 2340),
+    (1080, 2400),
+    (1125, 2436),
+    (1170, 2532),
+    (1179, 2556),
+    (1200, 1920),
+    (1242, 2208),
+    (1242, 2688),
+    (1284, 2778),
+    (1290, 2796),
+    (1440, 2560),
+    (1440, 3200),
+    (1536, 2048),
+    (1600, 2560),
+    (1668, 2388),
+    (1800, 2880),
+    (2160, 3840),
+];
+
+/// Kinds of images which are not photos of a camera
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum MediaClass {
+    /// Named `Screenshot...`, or PNG of the size of a screen without camera model
+    Screenshot,
+    /// Named by WhatsApp, Telegram or Signal, without camera model
+    Messenger,
+    /// Software, make or model of a scanner
+    Scan,
+    /// Software of a photo editor
+    Edit,
+}
+
+impl MediaClass {
+    fn default_directory(&self) -> &'static str {
+        match self {
+            MediaClass::Screenshot => "Screenshots/",
+            MediaClass::Messenger => "Messenger/",
+            MediaClass::Scan => "Scans/",
+            MediaClass::Edit => "Edits/",
+        }
+    }
+
+    /// Does the file belong to this class ? `exif_data` is None when no metadata was found.
+    pub fn matches(&self, path: &Path, exif_data: Option<&ExifData>) -> bool {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let no_camera = exif_data.is_none_or(|e| e.model.is_none());
+        let software = exif_data.and_then(|e| e.software.as_deref());
+        match self {
+            MediaClass::Screenshot => {
+                let is_png = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("png"));
+                // without metadata, the size is read in the header ; an unknown size is not the
+                // one of a screen
+                let screen_sized = || {
+                    exif_data
+                        .and_then(|e| e.dimensions)
+                        .or_else(|| dimensions::read_dimensions(path).ok().flatten())
+                        .is_some_and(is_screen_size)
+                };
+                SCREENSHOT_NAME_REGEX.is_match(&name) || (is_png && no_camera && screen_sized())
+            }
+            MediaClass::Messenger => no_camera && MESSENGER_NAME_REGEX.is_match(&name),
+            MediaClass::Scan => exif_data.is_some_and(|e| {
+                [&e.software, &e.make, &e.model]
+                    .iter()
+                    .any(|field| field.as_deref().is_some_and(|f| SCANNER_REGEX.is_match(f)))
+            }),
+            MediaClass::Edit => software.is_some_and(|s| EDITOR_REGEX.is_match(s)),
+        }
+    }
+}
+
+/// Directory where the images of a class are copied
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bucket {
+    pub class: MediaClass,
+    /// Relative to the sorted images directory, until it is created
+    pub directory: PathBuf,
+}
+
+/// Parse a bucket written `class` or `class=directory` (`screenshot`, `messenger=WhatsApp`)
+pub fn parse_bucket(value: &str) -> Result<Bucket, String> {
+    let (class, directory) = match value.split_once('=') {
+        Some((class, directory)) => (class, Some(directory)),
+        None => (value, None),
+    };
+    let class = <MediaClass as clap::ValueEnum>::from_str(class.trim(), true)
+        .map_err(|_| format!("unknown class '{}' (screenshot, messenger, scan or edit)", class))?;
+    let directory = match directory.map(str::trim) {
+        Some("") => return Err(format!("empty directory for the class '{}'", value)),
+        Some(directory) => PathBuf::from(directory),
+        None => PathBuf::from(class.default_directory()),
+    };
+    // inside the sorted images directory
+    if !directory.components().all(|c| matches!(c, std::path::Component::Normal(_))) {
+        return Err(format!("the folder of the class '{}' must be a relative path", value));
+    }
+    Ok(Bucket { class, directory })
+}
+
+/// First bucket (in the configured order) whose class the file belongs to
+pub fn find_bucket<'a>(path: &Path, exif_data: Option<&ExifData>, buckets: &'a [Bucket]) -> Option<&'a Bucket> {
+    let bucket = buckets.iter().find(|bucket| bucket.class.matches(path, exif_data));
+    if let Some(bucket) = bucket {
+        log::debug!("{:?} classified as {:?}", path, bucket.class);
+    }
+    bucket
+}
+
+fn is_screen_size((width, height): (u32, u32)) -> bool {
+    SCREEN_SIZES.contains(&(width.min(height), width.max(height)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    #[test]
+    fn test_find_bucket() {
+        init();
+        let buckets: Vec<Bucket> = ["edit", "screenshot", "messenger=WhatsApp", "scan"]
+            .iter()
+            .map(|b| parse_bucket(b).unwrap())
+            .collect();
+        assert_eq!(buckets[2].directory, PathBuf::from("WhatsApp"));
+        assert_eq!(buckets[3].directory, PathBuf::from("Scans/"));
+        assert!(parse_bucket("meme").is_err());
+        assert!(parse_bucket("scan=").is_err());
+        assert!(parse_bucket("scan=../Scans").is_err());
+
+        let class = |path: &str, exif_data: Option<&ExifData>| {
+            find_bucket(Path::new(path), exif_data, &buckets).map(|b| b.class)
+        };
+        let camera = ExifData {
+            make: Some(String::from("NIKON")),
+            model: Some(String::from("COOLPIX P6000")),
+            software: Some(String::from("COOLPIX P6000V1.0")),
+            dimensions: Some((4224, 3168)),
+            ..Default::default()
+        };
+        assert_eq!(class("DCIM/DSCN0025.JPG", Some(&camera)), None);
+        assert_eq!(class("Screenshot_20210304-101112.jpg", None), Some(MediaClass::Screenshot));
+        let phone_screen = ExifData {
+            dimensions: Some((1170, 2532)),
+            ..Default::default()
+        };
+        assert_eq!(class("IMG_0001.PNG", Some(&phone_screen)), Some(MediaClass::Screenshot));
+        let drawing = ExifData {
+            dimensions: Some((640, 480)),
+            ..Default::default()
+        };
+        assert_eq!(class("drawing.png", Some(&drawing)), None);
+        assert_eq!(class("missing.png", None), None);
+        let current_dir = std::env::current_dir().unwrap();
+        let path = Path::new("./test_find_bucket.png");
+        let mut png = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n', 0, 0, 0, 13];
+        png.extend(b"IHDR");
+        png.extend(1170u32.to_be_bytes());
+        png.extend(2532u32.to_be_bytes());
+        std::fs::write(path, &png).unwrap();
+        // no metadata : the size of the header
+        assert_eq!(class("./test_find_bucket.png", None), Some(MediaClass::Screenshot));
+        // ensure we are in the good directory before cleanup
+        assert_eq!(current_dir, std::env::current_dir().unwrap());
+        // cleanup
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(class("IMG-20210304-WA0001.jpg", None), Some(MediaClass::Messenger));
+        assert_eq!(class("photo_2021-03-04_10-11-12.jpg", None), Some(MediaClass::Messenger));
+        // a camera photo named by WhatsApp keeps its metadata when sent as a document
+        assert_eq!(class("IMG-20210304-WA0001.jpg", Some(&camera)), None);
+        let scan = ExifData {
+            make: Some(String::from("EPSON")),
+            model: Some(String::from("Perfection V600")),
+            ..Default::default()
+        };
+        assert_eq!(class("scan0001.jpg", Some(&scan)), Some(MediaClass::Scan));
+        let edited = ExifData {
+            software: Some(String::from("Adobe Photoshop Lightroom Classic 12.0 (Windows)")),
+            ..camera.clone()
+        };
+        assert_eq!(class("DSCN0025.jpg", Some(&edited)), Some(MediaClass::Edit));
+        // the first bucket of the list wins
+        let edited_screenshot = ExifData {
+            software: Some(String::from("GIMP 2.10")),
+            ..Default::default()
+        };
+        assert_eq!(class("Screenshot_1.png", Some(&edited_screenshot)), Some(MediaClass::Edit));
+    }
+}
@@ -0,0 +1,305 @@
+//! # clock_offsets
+//!
+//! Correction of the clock of devices that were wrongly set (never switched to summer time,
+//! wrong year...). The corrections are read from a CSV table, one line per device :
+//!
+//! ```text
+//! # make,model,serial,from,to,offset
+//! NIKON,COOLPIX P6000,,,2009-03-29,+1h
+//! Canon,,1234567,2015-01-01,2015-12-31 23:59:59,-365d
+//! ```
+//!
+//! Empty fields match any value, `from` and `to` (inclusive) are compared to the capture time
+//! as recorded by the device, and the offset is added to it.
+
+use crate::exif::{parse_datetime, ExifData};
+use chrono::{Duration, NaiveDateTime};
+use std::collections::HashMap;
+use std::path::Path;
+
+const TABLE_HEADER: &str = "make,model,serial,from,to,offset";
+// width of the bins of the histogram of time differences (see estimate_offset)
+const ESTIMATE_BIN_SECONDS: i64 = 10 * 60;
+// photos of each device compared by estimate_offset : the pairs grow with the product of the
+// numbers of photos, larger sets are sampled
+const MAX_ESTIMATE_PHOTOS: usize = 1000;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ClockOffsetError {
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+    #[error("line {line} of the clock offsets table : {reason}")]
+    Parse { line: usize, reason: String },
+}
+
+/// Correction of the clock of a device, during an optional period
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClockCorrection {
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub serial: Option<String>,
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+    pub offset: Duration,
+}
+
+impl ClockCorrection {
+    fn applies_to(&self, exif_data: &ExifData, capture_time: NaiveDateTime) -> bool {
+        let matches = |expected: &Option<String>, actual: &Option<String>| match expected {
+            None => true,
+            Some(expected) => actual
+                .as_ref()
+                .is_some_and(|a| a.eq_ignore_ascii_case(expected)),
+        };
+        matches(&self.make, &exif_data.make)
+            && matches(&self.model, &exif_data.model)
+            && matches(&self.serial, &exif_data.serial)
+            && self.from.is_none_or(|from| capture_time >= from)
+            && self.to.is_none_or(|to| capture_time <= to)
+    }
+}
+
+/// Read the table of clock corrections
+pub fn read_table(path: &Path) -> Result<Vec<ClockCorrection>, ClockOffsetError> {
+    log::trace!("clock_offsets::read_table {:?}", path);
+    parse_table(&std::fs::read_to_string(path)?)
+}
+
+fn parse_table(content: &str) -> Result<Vec<ClockCorrection>, ClockOffsetError> {
+    let mut corrections = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line == TABLE_HEADER {
+            continue;
+        }
+        let error = |reason: String| ClockOffsetError::Parse {
+            line: index + 1,
+            reason,
+        };
+        let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+        let [make, model, serial, from, to, offset] = fields[..] else {
+            return Err(error(format!("expected 6 fields ({}), found {}", TABLE_HEADER, fields.len())));
+        };
+        let text = |f: &str| Some(f.to_string()).filter(|f| !f.is_empty());
+        let date = |f: &str| -> Result<Option<NaiveDateTime>, ClockOffsetError> {
+            if f.is_empty() {
+                Ok(None)
+            } else {
+                parse_datetime(f)
+                    .map_err(error)?
+                    .ok_or_else(|| error(format!("empty date '{}'", f)))
+                    .map(Some)
+            }
+        };
+        corrections.push(ClockCorrection {
+            make: text(make),
+            model: text(model),
+            serial: text(serial),
+            from: date(from)?,
+            to: date(to)?,
+            offset: parse_offset(offset).ok_or_else(|| error(format!("invalid or out of range offset '{}'", offset)))?,
+        });
+    }
+    log::debug!("{} clock corrections read", corrections.len());
+    Ok(corrections)
+}
+
+/// Apply the first matching correction to the capture time. Return the applied offset.
+pub fn correct(exif_data: &mut ExifData, corrections: &[ClockCorrection]) -> Option<Duration> {
+    let capture_time = exif_data.capture_time?;
+    let correction = corrections
+        .iter()
+        .find(|c| c.applies_to(exif_data, capture_time))?;
+    let Some(corrected) = capture_time.checked_add_signed(correction.offset) else {
+        log::warn!(
+            "capture time {} out of range once corrected by {}, left uncorrected",
+            capture_time,
+            format_offset(correction.offset)
+        );
+        return None;
+    };
+    log::debug!(
+        "capture time {} corrected by {} ({:?})",
+        capture_time,
+        format_offset(correction.offset),
+        correction
+    );
+    exif_data.capture_time = Some(corrected);
+    Some(correction.offset)
+}
+
+/// Parse an offset written `+1h`, `-365d`, `+1d2h30m15s`... None if it is invalid or out of
+/// range
+pub fn parse_offset(value: &str) -> Option<Duration> {
+    let (sign, mut rest) = match value.chars().next()? {
+        '+' => (1, &value[1..]),
+        '-' => (-1, &value[1..]),
+        _ => (1, value),
+    };
+    if rest.is_empty() {
+        return None;
+    }
+    let mut seconds = 0i64;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let number: i64 = rest[..digits].parse().ok()?;
+        let unit = match rest[digits..].chars().next()? {
+            'd' => 24 * 3600,
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+        seconds = seconds.checked_add(number.checked_mul(unit)?)?;
+        rest = &rest[digits + 1..];
+    }
+    Duration::try_seconds(sign * seconds)
+}
+
+/// Write an offset the way parse_offset() reads it
+pub fn format_offset(offset: Duration) -> String {
+    let seconds = offset.num_seconds();
+    let mut text = String::from(if seconds < 0 { "-" } else { "+" });
+    let mut rest = seconds.abs();
+    for (unit, name) in [(24 * 3600, 'd'), (3600, 'h'), (60, 'm'), (1, 's')] {
+        if rest >= unit {
+            text.push_str(&format!("{}{}", rest / unit, name));
+            rest %= unit;
+        }
+    }
+    if seconds == 0 {
+        text.push_str("0s");
+    }
+    text
+}
+
+/// Estimated correction of a device clock
+#[derive(Debug, PartialEq)]
+pub struct Estimate {
+    pub offset: Duration,
+    /// Number of pairs of photos supporting the estimate
+    pub pairs: usize,
+}
+
+/// Estimate the correction to add to the capture times of a device, by comparing them to the
+/// capture times of a reference device (e.g. a phone) used at the same events : the time
+/// differences between photos of the same event pile up around the clock offset, while the
+/// other ones are spread.
+pub fn estimate_offset(times: &[NaiveDateTime], reference_times: &[NaiveDateTime]) -> Option<Estimate> {
+    let (times, reference_times) = (sample(times), sample(reference_times));
+    let differences = || {
+        times
+            .iter()
+            .flat_map(|t| reference_times.iter().map(move |r| (*r - *t).num_seconds()))
+    };
+    let mut histogram: HashMap<i64, usize> = HashMap::new();
+    for d in differences() {
+        *histogram.entry(d.div_euclid(ESTIMATE_BIN_SECONDS)).or_default() += 1;
+    }
+    // the most populated bin, and its neighbours to catch the events across a bin edge
+    let (best_bin, _) = histogram
+        .iter()
+        .max_by_key(|(bin, count)| (**count, std::cmp::Reverse(bin.abs())))?;
+    let mut close: Vec<i64> = differences()
+        .filter(|d| (d.div_euclid(ESTIMATE_BIN_SECONDS) - best_bin).abs() <= 1)
+        .collect();
+    close.sort_unstable();
+    let median = close[close.len() / 2];
+    log::debug!("offset estimated from {} pairs, median {}s", close.len(), median);
+    Some(Estimate {
+        // to the minute : the clocks of the photos of an event are not more precise
+        offset: Duration::minutes((median as f64 / 60.0).round() as i64),
+        pairs: close.len(),
+    })
+}
+
+/// At most MAX_ESTIMATE_PHOTOS times, evenly spread over the sorted times
+fn sample(times: &[NaiveDateTime]) -> Vec<NaiveDateTime> {
+    let mut sorted = times.to_vec();
+    sorted.sort_unstable();
+    if sorted.len() <= MAX_ESTIMATE_PHOTOS {
+        return sorted;
+    }
+    log::debug!("{} photos sampled to {} to estimate the offset", sorted.len(), MAX_ESTIMATE_PHOTOS);
+    (0..MAX_ESTIMATE_PHOTOS)
+        .map(|i| sorted[i * sorted.len() / MAX_ESTIMATE_PHOTOS])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    #[test]
+    fn test_clock_correction() {
+        init();
+        assert_eq!(parse_offset("+1h"), Some(Duration::hours(1)));
+        assert_eq!(parse_offset("-365d"), Some(Duration::days(-365)));
+        assert_eq!(parse_offset("1d2h30m15s"), Some(Duration::seconds(95415)));
+        assert_eq!(parse_offset("+1x"), None);
+        assert_eq!(parse_offset("+"), None);
+        assert_eq!(parse_offset("+999999999999d"), None);
+        assert_eq!(parse_offset("+9999999999999999999s"), None);
+        assert_eq!(format_offset(Duration::seconds(-95415)), "-1d2h30m15s");
+
+        let table = "make,model,serial,from,to,offset\n\
+            # summer time never set\n\
+            NIKON,COOLPIX P6000,,,2009-03-29,+1h\n\
+            ,,1234567,,,-365d\n";
+        let corrections = parse_table(table).unwrap();
+        assert_eq!(corrections.len(), 2);
+        let at = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap().and_hms_opt(16, 43, 21);
+        let mut exif_data = ExifData {
+            capture_time: at(2008, 10, 22),
+            make: Some(String::from("Nikon")),
+            model: Some(String::from("COOLPIX P6000")),
+            ..Default::default()
+        };
+        assert_eq!(correct(&mut exif_data, &corrections), Some(Duration::hours(1)));
+        assert_eq!(exif_data.capture_time, at(2008, 10, 22).map(|t| t + Duration::hours(1)));
+        exif_data.capture_time = at(2010, 1, 1);
+        assert_eq!(correct(&mut exif_data, &corrections), None);
+
+        assert!(matches!(
+            parse_table("NIKON,,,,+1h"),
+            Err(ClockOffsetError::Parse { line: 1, .. })
+        ));
+        assert!(parse_table(",,,2009-13-01,,+1h").is_err());
+        assert!(matches!(
+            parse_table(",,,,,+999999999999d"),
+            Err(ClockOffsetError::Parse { line: 1, .. })
+        ));
+        // a valid offset, but out of the range of the dates
+        let corrections = parse_table(",,1234567,,,+100000000d").unwrap();
+        exif_data.serial = Some(String::from("1234567"));
+        assert_eq!(correct(&mut exif_data, &corrections), None);
+        assert_eq!(exif_data.capture_time, at(2010, 1, 1));
+    }
+
+    #[test]
+    fn test_estimate_offset() {
+        init();
+        let at = |d, h, mi| NaiveDate::from_ymd_opt(2019, 8, d).unwrap().and_hms_opt(h, mi, 0).unwrap();
+        // the camera is 1h03 late, photos taken at two events and one alone
+        let reference = vec![at(3, 10, 0), at(3, 10, 5), at(3, 10, 20), at(10, 18, 0), at(10, 18, 2)];
+        let late = Duration::minutes(63);
+        let camera: Vec<NaiveDateTime> = [at(3, 10, 1), at(3, 10, 19), at(10, 18, 1), at(20, 9, 0)]
+            .iter()
+            .map(|t| *t - late)
+            .collect();
+        let estimate = estimate_offset(&camera, &reference).unwrap();
+        assert!((estimate.offset - late).num_minutes().abs() <= 2, "{:?}", estimate);
+        assert!(estimate.pairs >= 5);
+        assert_eq!(estimate_offset(&camera, &[]), None);
+        // large sets are sampled
+        let many: Vec<NaiveDateTime> = (0..5000).map(|i| at(1, 0, 0) + Duration::minutes(i * 7)).collect();
+        let sampled = sample(&many);
+        assert_eq!(sampled.len(), MAX_ESTIMATE_PHOTOS);
+        assert_eq!((sampled[0], sampled[1]), (many[0], many[5]));
+    }
+}
@@ -0,0 +1,219 @@
+//! # companions
+//!
+//! Files of a directory sharing the base name of an image travel with it : its twins (the
+//! JPEG of a RAW+JPEG pair) and its sidecars (`IMG_0001.xmp`, `IMG_0001.CR2.xmp`,
+//! `IMG_0001.AAE`...). The video of a Live Photo is a twin of its still, whatever its name.
+
+use crate::exif::{ExifData, ExifError, MediaKind};
+use crate::plan::ScannedFile;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Darktable / Lightroom / Capture One (xmp), Apple edits (aae), video thumbnails (thm, JPEG
+/// files but not photos), RawTherapee (pp3), DxO (dop)
+pub const SIDECAR_EXTENSIONS: [&str; 5] = ["xmp", "aae", "thm", "pp3", "dop"];
+
+/// A file and the files travelling with it
+#[derive(Debug)]
+pub struct FileGroup<'a> {
+    /// The file whose metadata decide where the group goes
+    pub primary: &'a ScannedFile,
+    /// Other images or videos with the same base name
+    pub twins: Vec<&'a ScannedFile>,
+    pub sidecars: Vec<&'a ScannedFile>,
+}
+
+impl<'a> FileGroup<'a> {
+    pub fn len(&self) -> usize {
+        1 + self.twins.len() + self.sidecars.len()
+    }
+
+    pub fn members(&self) -> impl Iterator<Item = &'a ScannedFile> + '_ {
+        std::iter::once(self.primary)
+            .chain(self.twins.iter().copied())
+            .chain(self.sidecars.iter().copied())
+    }
+}
+
+pub fn is_sidecar(path: &Path) -> bool {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .is_some_and(|e| SIDECAR_EXTENSIONS.contains(&e.as_str()))
+}
+
+/// The part of the name of a companion following the base name of its primary file
+/// (`.CR2.xmp` for `IMG_0001.CR2.xmp`), to rename it like the primary file
+pub fn companion_suffix(companion: &Path, primary: &Path) -> String {
+    let name = companion.file_name().unwrap_or_default().to_string_lossy();
+    let stem = primary.file_stem().unwrap_or_default().to_string_lossy();
+    match name.get(..stem.len()) {
+        Some(prefix) if prefix.eq_ignore_ascii_case(&stem) => name[stem.len()..].to_string(),
+        _ => format!(".{}", companion.extension().unwrap_or_default().to_string_lossy()),
+    }
+}
+
+/// Group the files of a directory by base name (ignoring the case). The primary file of a
+/// group is the one with the best metadata, the RAW file of a RAW+JPEG pair ; files which
+/// are not images stay alone, except the sidecars.
+pub fn group_companions<'a>(files: &'a [ScannedFile]) -> Vec<FileGroup<'a>> {
+    let lowercase = |value: Option<&std::ffi::OsStr>| value.unwrap_or_default().to_string_lossy().to_lowercase();
+
+    let mut groups: Vec<Vec<&'a ScannedFile>> = Vec::new();
+    let mut by_stem: HashMap<String, usize> = HashMap::new();
+    let mut alone = Vec::new();
+    let mut sidecars = Vec::new();
+    for file in files {
+        if is_sidecar(&file.path) {
+            sidecars.push(file);
+        } else if matches!(file.exif_data, Err(ExifError::NotImageFile(_))) {
+            alone.push(file);
+        } else {
+            let index = *by_stem.entry(lowercase(file.path.file_stem())).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+            groups[index].push(file);
+        }
+    }
+
+    // Live Photos renamed apart (IMG_0001.HEIC and IMG_0001_1.MOV...)
+    let mut by_content_identifier: HashMap<&str, usize> = HashMap::new();
+    for index in 0..groups.len() {
+        let content_identifier = groups[index]
+            .iter()
+            .copied()
+            .find_map(|file: &'a ScannedFile| file.exif_data.as_ref().ok()?.content_identifier.as_deref());
+        let Some(content_identifier) = content_identifier else {
+            continue;
+        };
+        match by_content_identifier.get(content_identifier) {
+            Some(&first) => {
+                let members = std::mem::take(&mut groups[index]);
+                groups[first].extend(members);
+                by_stem.values_mut().filter(|i| **i == index).for_each(|i| *i = first);
+            }
+            None => {
+                by_content_identifier.insert(content_identifier, index);
+            }
+        }
+    }
+
+    let mut sidecars_of: HashMap<usize, Vec<&ScannedFile>> = HashMap::new();
+    for sidecar in sidecars {
+        // IMG_0001.CR2.xmp, then IMG_0001.xmp
+        let stem = lowercase(sidecar.path.file_stem());
+        let inner_stem = lowercase(Path::new(&stem).file_stem());
+        match by_stem.get(&stem).or_else(|| by_stem.get(&inner_stem)) {
+            Some(index) => sidecars_of.entry(*index).or_default().push(sidecar),
+            None => alone.push(sidecar),
+        }
+    }
+
+    let mut file_groups: Vec<FileGroup> = groups
+        .into_iter()
+        .enumerate()
+        .filter(|(_, members)| !members.is_empty())
+        .map(|(index, mut members)| {
+            members.sort_by_key(|file| (primary_rank(file), file.path.clone()));
+            let primary = members.remove(0);
+            let sidecars = sidecars_of.remove(&index).unwrap_or_default();
+            if !members.is_empty() || !sidecars.is_empty() {
+                log::debug!("{:?} travels with {:?} and {:?}", primary.path, members, sidecars);
+            }
+            FileGroup {
+                primary,
+                twins: members,
+                sidecars,
+            }
+        })
+        .collect();
+    file_groups.extend(alone.into_iter().map(|file| FileGroup {
+        primary: file,
+        twins: Vec::new(),
+        sidecars: Vec::new(),
+    }));
+    file_groups
+}
+
+/// The video of a Live Photo, which stays next to its still
+pub fn is_live_photo_video(still: &ExifData, video: &ExifData) -> bool {
+    video.media_kind == MediaKind::Video
+        && still.media_kind != MediaKind::Video
+        && still.content_identifier.is_some()
+        && still.content_identifier == video.content_identifier
+}
+
+/// Dated files first, then RAW files, other images and videos
+fn primary_rank(file: &ScannedFile) -> (bool, u8) {
+    match &file.exif_data {
+        Ok(exif_data) => (
+            exif_data.capture_time.is_none(),
+            match exif_data.media_kind {
+                MediaKind::Raw => 0,
+                MediaKind::Image => 1,
+                MediaKind::Video => 2,
+            },
+        ),
+        Err(_) => (true, 3),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    fn scanned(name: &str, media_kind: Option<MediaKind>) -> ScannedFile {
+        let exif_data = match media_kind {
+            Some(media_kind) => Ok(ExifData {
+                capture_time: chrono::NaiveDate::from_ymd_opt(2021, 3, 4).unwrap().and_hms_opt(10, 11, 12),
+                media_kind,
+                content_identifier: name.starts_with("LIVE").then(|| String::from("4B3E4E5A")),
+                ..Default::default()
+            }),
+            None => Err(ExifError::NotImageFile(String::from("Unknown image format"))),
+        };
+        ScannedFile {
+            path: PathBuf::from("photos").join(name),
+            exif_data,
+        }
+    }
+
+    #[test]
+    fn test_group_companions() {
+        init();
+        let files = vec![
+            scanned("IMG_0001.JPG", Some(MediaKind::Image)),
+            scanned("IMG_0001.CR2.xmp", None),
+            scanned("IMG_0001.CR2", Some(MediaKind::Raw)),
+            scanned("img_0001.aae", None),
+            scanned("IMG_0002.jpg", Some(MediaKind::Image)),
+            scanned("IMG_0003.xmp", None),
+            scanned("notes.txt", None),
+            scanned("LIVE_0004_1.MOV", Some(MediaKind::Video)),
+            scanned("LIVE_0004.HEIC", Some(MediaKind::Image)),
+        ];
+        let groups = group_companions(&files);
+        assert_eq!(groups.len(), 5);
+        let pair = &groups[0];
+        assert_eq!(pair.primary.path, PathBuf::from("photos/IMG_0001.CR2"));
+        assert_eq!(pair.twins.len(), 1);
+        assert_eq!(pair.sidecars.len(), 2);
+        assert_eq!(pair.len(), 4);
+        // the still of a Live Photo is its primary file
+        let live_photo = &groups[2];
+        assert_eq!(live_photo.primary.path, PathBuf::from("photos/LIVE_0004.HEIC"));
+        let (still, video) = (live_photo.primary.exif_data.as_ref().unwrap(), live_photo.twins[0].exif_data.as_ref().unwrap());
+        assert!(is_live_photo_video(still, video));
+        // orphan sidecars and other files stay alone
+        assert!(groups[3..].iter().all(|g| g.len() == 1));
+
+        let primary = Path::new("photos/IMG_0001.CR2");
+        assert_eq!(companion_suffix(Path::new("photos/IMG_0001.CR2.xmp"), primary), ".CR2.xmp");
+        assert_eq!(companion_suffix(Path::new("photos/img_0001.aae"), primary), ".aae");
+    }
+}
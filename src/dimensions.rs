@@ -0,0 +1,119 @@
+//! # dimensions
+//!
+//! Size in pixels of the images whose metadata don't tell it, read from the header of the
+//! image itself : the SOF segment of a JPEG file, the IHDR chunk of a PNG file.
+
+use crate::archives;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+const JPEG_SIGNATURE: [u8; 2] = [0xFF, 0xD8];
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+// start of scan : the compressed data follows, there is no SOF after it
+const JPEG_SOS: u8 = 0xDA;
+
+/// Width and height in pixels, None if the file is neither a JPEG nor a PNG image
+pub fn read_dimensions(path: &Path) -> std::io::Result<Option<(u32, u32)>> {
+    log::trace!("read_dimensions of {:?}", path);
+    let mut file = std::io::BufReader::new(archives::open(path)?);
+    let mut signature = [0u8; 8];
+    if file.read_exact(&mut signature).is_err() {
+        return Ok(None);
+    }
+    let dimensions = if signature == PNG_SIGNATURE {
+        read_png_ihdr(&mut file)?
+    } else if signature[..2] == JPEG_SIGNATURE {
+        file.seek(SeekFrom::Start(2))?;
+        read_jpeg_sof(&mut file)?
+    } else {
+        None
+    };
+    log::debug!("dimensions of {:?} in the header : {:?}", path, dimensions);
+    Ok(dimensions)
+}
+
+/// The IHDR chunk is the first one : length, type, width, height
+fn read_png_ihdr(file: &mut impl Read) -> std::io::Result<Option<(u32, u32)>> {
+    let mut ihdr = [0u8; 16];
+    file.read_exact(&mut ihdr)?;
+    if &ihdr[4..8] != b"IHDR" {
+        return Ok(None);
+    }
+    let width = u32::from_be_bytes(ihdr[8..12].try_into().unwrap());
+    let height = u32::from_be_bytes(ihdr[12..16].try_into().unwrap());
+    Ok(Some((width, height)))
+}
+
+/// Skip the segments up to a start of frame (SOF0 to SOF15, but DHT, JPG and DAC) :
+/// length, precision, height, width
+fn read_jpeg_sof(file: &mut (impl Read + Seek)) -> std::io::Result<Option<(u32, u32)>> {
+    let mut marker = [0u8; 2];
+    loop {
+        file.read_exact(&mut marker)?;
+        if marker[0] != 0xFF {
+            return Ok(None);
+        }
+        match marker[1] {
+            // fill byte
+            0xFF => {
+                file.seek(SeekFrom::Current(-1))?;
+                continue;
+            }
+            // markers without segment
+            0x01 | 0xD0..=0xD7 => continue,
+            JPEG_SOS | 0xD9 => return Ok(None),
+            _ => (),
+        }
+        let mut length = [0u8; 2];
+        file.read_exact(&mut length)?;
+        let length = u16::from_be_bytes(length) as i64;
+        if length < 2 {
+            return Ok(None);
+        }
+        if matches!(marker[1], 0xC0..=0xCF) && !matches!(marker[1], 0xC4 | 0xC8 | 0xCC) {
+            let mut sof = [0u8; 5];
+            file.read_exact(&mut sof)?;
+            let height = u16::from_be_bytes([sof[1], sof[2]]) as u32;
+            let width = u16::from_be_bytes([sof[3], sof[4]]) as u32;
+            return Ok(Some((width, height)));
+        }
+        file.seek(SeekFrom::Current(length - 2))?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    #[test]
+    fn test_read_dimensions() {
+        init();
+        let current_dir = std::env::current_dir().unwrap();
+        // the SOF after the EXIF segment and its thumbnail
+        assert_eq!(
+            read_dimensions(Path::new("data_4_tests/DSCN0025.jpg")).unwrap(),
+            Some((640, 480))
+        );
+
+        let path = Path::new("./test_read_dimensions.png");
+        let mut png = PNG_SIGNATURE.to_vec();
+        png.extend(13u32.to_be_bytes());
+        png.extend(b"IHDR");
+        png.extend(1170u32.to_be_bytes());
+        png.extend(2532u32.to_be_bytes());
+        png.extend([8, 6, 0, 0, 0]);
+        std::fs::write(path, &png).unwrap();
+        assert_eq!(read_dimensions(path).unwrap(), Some((1170, 2532)));
+        std::fs::write(path, b"not an image").unwrap();
+        assert_eq!(read_dimensions(path).unwrap(), None);
+
+        // ensure we are in the good directory before cleanup
+        assert_eq!(current_dir, std::env::current_dir().unwrap());
+        // cleanup
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,9 +1,12 @@
 //! # directories
 //!
 //! Functions to manage interactions with the filesystem.
+use crate::archives;
+use crate::manifest::MANIFEST_FILENAME;
 use crate::performance::{PerformanceMetrics, Timer};
 use eyre::Result;
 use once_cell::sync::Lazy;
+use regex::Regex;
 use std::{
     collections::HashSet,
     fs::{self, DirBuilder},
@@ -15,6 +18,18 @@ use std::{
 const SORTED_IMAGES_DIRNAME_PREFIX: &str = "Images-";
 const UNSORTED_IMAGES_SUBDIR_NAME: &str = "Unsorted/";
 const NOT_IMAGES_SUBDIR_NAME: &str = "Not_Images/";
+const SUSPICIOUS_DATES_SUBDIR_NAME: &str = "Suspicious_Dates/";
+// File written at the root of each sorted images directory, so that it is never sorted again
+pub const SORTED_IMAGES_MARKER_FILENAME: &str = ".images_sort_library";
+// Lockfile taken at the root of the destination directory during a run
+pub const LOCK_FILENAME: &str = ".images_sort.lock";
+// Files written by images_sort itself, never sorted nor counted
+const INTERNAL_FILENAMES: [&str; 3] =
+    [SORTED_IMAGES_MARKER_FILENAME, LOCK_FILENAME, MANIFEST_FILENAME];
+
+// Directories created before the marker file existed are recognized by their name
+static LEGACY_SORTED_IMAGES_DIRNAME: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^Images-\d{8}-\d{6}$").unwrap());
 
 // Cache of already created directories to avoid redundant mkdir calls
 static CREATED_DIRS_CACHE: Lazy<Mutex<HashSet<PathBuf>>> = Lazy::new(|| Mutex::new(HashSet::new()));
@@ -32,15 +47,39 @@ pub fn get_subdirectories_recursive(top_directory: &Path) -> Result<Vec<PathBuf>
     Ok(directories)
 }
 
+/// Get the subdirectories of a directory, skipping the ones created by images_sort
 fn get_subdirectories(top_directory: &Path) -> Result<Vec<PathBuf>> {
     log::trace!("get_subdirectories of {:?}", top_directory);
     Ok(fs::read_dir(top_directory)?
         .filter(|r| r.is_ok())
         .map(|r| r.unwrap().path())
         .filter(|r| r.is_dir())
+        .filter(|r| {
+            let sorted = is_sorted_images_dir(r);
+            if sorted {
+                log::info!("{:?} has been created by images_sort, skipped", r);
+            }
+            !sorted
+        })
         .collect())
 }
 
+/// Is this directory a sorted images directory (created by images_sort) ?
+pub fn is_sorted_images_dir(dir: &Path) -> bool {
+    dir.join(SORTED_IMAGES_MARKER_FILENAME).is_file()
+        || dir
+            .file_name()
+            .is_some_and(|n| LEGACY_SORTED_IMAGES_DIRNAME.is_match(&n.to_string_lossy()))
+}
+
+/// Return the sorted images directory containing `path` (or being `path`), if any.
+/// `path` is expected to be canonicalized.
+pub fn find_enclosing_sorted_images_dir(path: &Path) -> Option<PathBuf> {
+    path.ancestors()
+        .find(|a| is_sorted_images_dir(a))
+        .map(|a| a.to_path_buf())
+}
+
 /// Create the directory where the sorted images will be copied.
 /// The name will embed info of the timestamp of the creation.
 pub fn create_sorted_images_dir(top_directory: &Path) -> Result<PathBuf> {
@@ -53,6 +92,10 @@ pub fn create_sorted_images_dir(top_directory: &Path) -> Result<PathBuf> {
     let path = top_directory.join(dirname);
     log::debug!("path of target directory to be created : {:?}", path);
     DirBuilder::new().recursive(false).create(&path)?;
+    fs::write(
+        path.join(SORTED_IMAGES_MARKER_FILENAME),
+        format!("Sorted by images_sort on {}\n", now.to_rfc3339()),
+    )?;
     Ok(path)
 }
 
@@ -81,6 +124,19 @@ pub fn create_not_images_dir(parent_directory: &Path) -> Result<PathBuf> {
     Ok(not_images_dir)
 }
 
+/// Create the directory where images whose date has been rejected (placeholder, impossible
+/// date...) will be copied
+pub fn create_suspicious_dates_dir(parent_directory: &Path) -> Result<PathBuf> {
+    log::trace!("create_suspicious_dates_dir in {:?}", parent_directory);
+    let suspicious_dates_dir = parent_directory.join(std::path::Path::new(&String::from(
+        SUSPICIOUS_DATES_SUBDIR_NAME,
+    )));
+    DirBuilder::new()
+        .recursive(true)
+        .create(&suspicious_dates_dir)?;
+    Ok(suspicious_dates_dir)
+}
+
 pub fn create_subdir(parent_directory: &Path, sub_dir: &Path) -> Result<PathBuf> {
     log::trace!("create_subdir in {:?}", parent_directory);
     let new_dir = parent_directory.join(sub_dir);
@@ -111,31 +167,70 @@ pub fn create_subdir(parent_directory: &Path, sub_dir: &Path) -> Result<PathBuf>
     Ok(new_dir)
 }
 
-/// Return a Vec containing all FILES contained in a directory
+/// Create a symbolic link `link` pointing to `target` (usually a relative path)
+pub fn create_symlink(target: &Path, link: &Path) -> Result<()> {
+    log::trace!("create_symlink {:?} -> {:?}", link, target);
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(target, link)?;
+    #[cfg(windows)]
+    std::os::windows::fs::symlink_file(target, link)?;
+    Ok(())
+}
+
+/// Return a Vec containing all FILES contained in a directory (except images_sort own files)
 pub fn get_files_from_dir(dir: &Path) -> Result<Vec<PathBuf>> {
     log::trace!("get_images_from_dir in {:?}", dir);
     Ok(fs::read_dir(dir)?
         .filter(|r| r.is_ok())
         .map(|r| r.unwrap().path())
         .filter(|r| r.is_file())
+        .filter(|r| !is_internal_file(r))
         .collect())
 }
 
-/// Count all files recursively in a directory
+fn is_internal_file(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|n| INTERNAL_FILENAMES.iter().any(|i| n == *i))
+}
+
+/// Sum the size (in bytes) of all the files directly contained in the given directories.
+/// With `expand_archives`, an archive counts for the size of its expanded entries.
+pub fn sum_files_size(dirs: &[PathBuf], expand_archives: bool) -> Result<u64> {
+    log::trace!("sum_files_size of {} directories", dirs.len());
+    let mut size: u64 = 0;
+    for dir in dirs {
+        for file in get_files_from_dir(dir)? {
+            // an unreadable archive is copied as is
+            let expanded_size = if expand_archives && archives::is_archive(&file) {
+                archives::expanded_size(&file).ok()
+            } else {
+                None
+            };
+            size += match expanded_size {
+                Some(expanded_size) => expanded_size,
+                None => fs::metadata(&file)?.len(),
+            };
+        }
+    }
+    Ok(size)
+}
+
+/// Count all files recursively in a directory (symbolic links are not followed nor counted).
+/// Sorted images directories found below `dir` and their marker files are not counted.
 pub fn count_files_recursive(dir: &Path) -> Result<u64> {
     log::trace!("count_files_recursive in {:?}", dir);
 
     let mut count: u64 = 0;
 
     let entries = fs::read_dir(dir)?;
-    for entry in entries {
-        if let Ok(entry) = entry {
-            let path = entry.path();
-            if path.is_file() {
+    for entry in entries.flatten() {
+        let file_type = entry.file_type()?;
+        if file_type.is_file() {
+            if !is_internal_file(&entry.path()) {
                 count += 1;
-            } else if path.is_dir() {
-                count += count_files_recursive(&path)?;
             }
+        } else if file_type.is_dir() && !is_sorted_images_dir(&entry.path()) {
+            count += count_files_recursive(&entry.path())?;
         }
     }
 
@@ -153,7 +248,7 @@ mod tests {
     #[test]
     fn test_create_subdir() {
         init();
-        assert_eq!(Path::new("./test_create").try_exists().unwrap(), false);
+        assert!(!Path::new("./test_create").try_exists().unwrap());
         let result = create_subdir(
             std::path::Path::new(&String::from("./")),
             std::path::Path::new(&String::from("test_create")),
@@ -173,6 +268,7 @@ mod tests {
         std::fs::File::create("./test_get/foo1.txt").unwrap();
         std::fs::File::create("./test_get/foo2.txt").unwrap();
         std::fs::File::create("./test_get/foo3.txt").unwrap();
+        std::fs::File::create("./test_get/.images_sort.lock").unwrap();
         let files = get_files_from_dir(test_path).unwrap();
         assert_eq!(files.len(), 3);
 
@@ -182,6 +278,38 @@ mod tests {
         std::fs::remove_dir_all(test_path).unwrap();
     }
 
+    #[test]
+    fn test_sum_files_size() {
+        init();
+        let current_dir = std::env::current_dir().unwrap();
+        let test_path = std::path::Path::new("./test_sum_size");
+        std::fs::create_dir_all("./test_sum_size/sub").unwrap();
+        std::fs::write("./test_sum_size/foo1.txt", "12345").unwrap();
+        std::fs::write("./test_sum_size/sub/foo2.txt", "123").unwrap();
+        let dirs = vec![
+            PathBuf::from("./test_sum_size"),
+            PathBuf::from("./test_sum_size/sub"),
+        ];
+        assert_eq!(8, sum_files_size(&dirs, false).unwrap());
+
+        // an archive counts for its expanded entries
+        let mut tar = tar::Builder::new(std::fs::File::create("./test_sum_size/sub/backup.tar").unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(10);
+        header.set_cksum();
+        tar.append_data(&mut header, "foo3.txt", "0123456789".as_bytes()).unwrap();
+        tar.finish().unwrap();
+        drop(tar);
+        let tar_size = std::fs::metadata("./test_sum_size/sub/backup.tar").unwrap().len();
+        assert_eq!(8 + tar_size, sum_files_size(&dirs, false).unwrap());
+        assert_eq!(18, sum_files_size(&dirs, true).unwrap());
+
+        // ensure we are in the good directory before cleanup
+        assert_eq!(current_dir, std::env::current_dir().unwrap());
+        // cleanup
+        std::fs::remove_dir_all(test_path).unwrap();
+    }
+
     #[test]
     fn test_get_subdirectories() {
         init();
@@ -194,7 +322,7 @@ mod tests {
         let r = get_subdirectories(test_path);
         match r {
             Ok(v) => {
-                assert_eq!(3, v.iter().count());
+                assert_eq!(3, v.len());
                 assert!(v.contains(&PathBuf::from("./test_get_sub/1first")));
                 assert!(v.contains(&PathBuf::from("./test_get_sub/2second")));
                 assert!(v.contains(&PathBuf::from("./test_get_sub/3third")));
@@ -208,6 +336,39 @@ mod tests {
         std::fs::remove_dir_all(test_path).unwrap();
     }
 
+    #[test]
+    fn test_sorted_images_dir_skipped() {
+        init();
+        let current_dir = std::env::current_dir().unwrap();
+        let test_path = std::path::Path::new("./test_skip_sorted");
+        std::fs::create_dir_all("./test_skip_sorted/photos").unwrap();
+        std::fs::write("./test_skip_sorted/photos/a.jpg", "a").unwrap();
+        let sorted = create_sorted_images_dir(test_path).unwrap();
+        std::fs::create_dir_all(sorted.join("2008 10")).unwrap();
+        std::fs::write(sorted.join("2008 10/a.jpg"), "a").unwrap();
+        std::fs::create_dir_all("./test_skip_sorted/old/Images-20200101-101010").unwrap();
+
+        let v = get_subdirectories_recursive(test_path).unwrap();
+        assert_eq!(2, v.len());
+        assert!(v.contains(&PathBuf::from("./test_skip_sorted/photos")));
+        assert!(v.contains(&PathBuf::from("./test_skip_sorted/old")));
+        assert_eq!(1, count_files_recursive(test_path).unwrap());
+        assert_eq!(1, count_files_recursive(&sorted).unwrap());
+
+        let inside = sorted.join("2008 10").canonicalize().unwrap();
+        assert_eq!(
+            find_enclosing_sorted_images_dir(&inside),
+            Some(sorted.canonicalize().unwrap())
+        );
+        let photos = Path::new("./test_skip_sorted/photos").canonicalize().unwrap();
+        assert_eq!(find_enclosing_sorted_images_dir(&photos), None);
+
+        // ensure we are in the good directory before cleanup
+        assert_eq!(current_dir, std::env::current_dir().unwrap());
+        // cleanup
+        std::fs::remove_dir_all(test_path).unwrap();
+    }
+
     #[test]
     fn test_get_subdirectories_recursive() {
         init();
@@ -220,7 +381,7 @@ mod tests {
         let r = get_subdirectories_recursive(test_path);
         match r {
             Ok(v) => {
-                assert_eq!(5, v.iter().count());
+                assert_eq!(5, v.len());
                 log::debug!("{:?}", v);
                 assert!(v.contains(&PathBuf::from("./test_get_sub_r/1first")));
                 assert!(v.contains(&PathBuf::from("./test_get_sub_r/2second/test1")));
@@ -0,0 +1,115 @@
+//! # events
+//!
+//! Grouping of the photos in events : ordered by capture time, a new event starts wherever
+//! the gap between two consecutive photos exceeds a threshold. Events are named after their
+//! first day and the place where most of their photos were taken.
+
+use crate::exif::GpsPosition;
+use crate::layout::{self, Directory};
+use crate::place_finder;
+use chrono::{Duration, NaiveDateTime};
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Photos taken without long interruption
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    /// Capture time of the first photo, in the time zone of the folders
+    pub start: NaiveDateTime,
+    pub folder: Directory,
+}
+
+// longest gap between events : a century, far from the limits of Duration
+const MAX_GAP_HOURS: f64 = 100.0 * 366.0 * 24.0;
+
+/// Parse a gap between events, in hours
+pub fn parse_gap_hours(value: &str) -> Result<f64, String> {
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|hours| hours.is_finite() && *hours > 0.0 && *hours <= MAX_GAP_HOURS)
+        .ok_or(format!("invalid gap '{}', a positive number of hours is expected", value))
+}
+
+/// Split capture times, in ascending order, wherever the gap between two of them exceeds
+/// `gap`. Return the ranges of indices of the events.
+pub fn split_in_events(times: &[NaiveDateTime], gap: Duration) -> Vec<Range<usize>> {
+    let mut events = Vec::new();
+    let mut start = 0;
+    for i in 1..times.len() {
+        if times[i] - times[i - 1] > gap {
+            events.push(start..i);
+            start = i;
+        }
+    }
+    if !times.is_empty() {
+        events.push(start..times.len());
+    }
+    events
+}
+
+/// Build the event of photos, named after the most frequent place of the photos having a
+/// GPS position
+pub fn name_event(start: NaiveDateTime, positions: &[GpsPosition]) -> Event {
+    let mut places: HashMap<String, (usize, usize)> = HashMap::new();
+    for (index, gps) in positions
+        .iter()
+        .filter(|gps| gps.latitude != 0.0 || gps.longitude != 0.0)
+        .enumerate()
+    {
+        if let Some(place) = place_finder::find_place(gps.latitude, gps.longitude) {
+            places.entry(place).or_insert((0, index)).0 += 1;
+        }
+    }
+    // the most frequent place, the first one seen on ties
+    let place = places
+        .into_iter()
+        .max_by_key(|(_, (count, first_seen))| (*count, std::cmp::Reverse(*first_seen)))
+        .map(|(place, _)| place);
+    log::debug!("event starting at {} : place {:?}", start, place);
+    Event {
+        start,
+        folder: layout::event_directory(start, place.as_deref()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    #[test]
+    fn test_events() {
+        init();
+        let at = |d, h| NaiveDate::from_ymd_opt(2008, 10, d).unwrap().and_hms_opt(h, 0, 0).unwrap();
+        let times = [at(12, 10), at(12, 15), at(12, 23), at(13, 4), at(20, 9)];
+        let gap = Duration::hours(6);
+        assert_eq!(split_in_events(&times, gap), vec![0..2, 2..4, 4..5]);
+        assert_eq!(split_in_events(&times, Duration::hours(12)), vec![0..4, 4..5]);
+        assert_eq!(split_in_events(&times, Duration::hours(2)), vec![0..1, 1..2, 2..3, 3..4, 4..5]);
+        assert!(split_in_events(&[], gap).is_empty());
+        assert_eq!(parse_gap_hours("6"), Ok(6.0));
+        for invalid in ["0", "-6", "NaN", "inf", "1e300", "six"] {
+            assert!(parse_gap_hours(invalid).is_err(), "{}", invalid);
+        }
+
+        let arezzo = GpsPosition {
+            latitude: 43.4667,
+            longitude: 11.8833,
+            altitude: None,
+        };
+        let null_island = GpsPosition {
+            latitude: 0.0,
+            longitude: 0.0,
+            altitude: None,
+        };
+        let event = name_event(at(12, 10), &[null_island, arezzo, null_island, arezzo]);
+        assert_eq!(event.folder.get(), "2008-10-12 Arezzo");
+        assert_eq!(name_event(at(20, 9), &[]).folder.get(), "2008-10-20");
+    }
+}
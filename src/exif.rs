@@ -3,19 +3,209 @@
 //! Getting the exif data needed to sort the images.
 //!
 
+use crate::archives;
+use crate::clock_offsets;
+use crate::dimensions;
+use crate::global_configuration::GlobalConfiguration;
+use crate::iptc::{self, IptcData};
+use crate::jpeg;
+use crate::live_photos::{self, MotionPhoto};
 use crate::performance::{PerformanceMetrics, Timer};
 use crate::place_finder;
+use crate::quicktime::{self, VideoData};
+use crate::raw;
+use crate::takeout::{self, TakeoutData};
+use crate::xmp::{self, XmpData, XmpPrecedence};
+use chrono::{Datelike, FixedOffset, NaiveDate, NaiveDateTime, Offset, TimeZone, Timelike};
 use exif::{Exif, Field, In, Tag, Value};
+use once_cell::sync::Lazy;
 use regex::Regex;
 use std::path::Path;
 
-#[derive(Debug)]
+// Niépce, "View from the Window at Le Gras"
+const FIRST_PHOTOGRAPH_YEAR: i32 = 1826;
+
+// Dates found in names of files : IMG_20190812_143055, PXL_20210304_101112345,
+// WhatsApp Image 2021-03-04 at 10.11.12, IMG-20210304-WA0001, 2021-03-04...
+static FILENAME_DATE_REGEXES: Lazy<Vec<Regex>> = Lazy::new(|| {
+    [
+        r"(?P<year>(?:19|20)\d{2})(?P<month>\d{2})(?P<day>\d{2})[_\-T ]?(?P<hour>\d{2})(?P<minute>\d{2})(?P<second>\d{2})",
+        r"(?P<year>(?:19|20)\d{2})-(?P<month>\d{2})-(?P<day>\d{2})(?: at |[ _T])(?P<hour>\d{2})[.:\-](?P<minute>\d{2})[.:\-](?P<second>\d{2})",
+        r"(?:^|\D)(?P<year>(?:19|20)\d{2})[\-_]?(?P<month>\d{2})[\-_]?(?P<day>\d{2})(?:\D|$)",
+    ]
+    .iter()
+    .map(|re| Regex::new(re).unwrap())
+    .collect()
+});
+
+// Dates found in names of folders : "2019-08-12 Holidays", "2019_08", "2019/08"
+static FOLDER_DATE_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?:^|\D)(?P<year>(?:18|19|20)\d{2})[\-_ ./](?P<month>\d{2})(?:[\-_ ./](?P<day>\d{2}))?(?:\D|$)").unwrap()
+});
+
+// year, month, day, then optional hour, minute, second, fraction and time zone
+// offset at the end of an ISO 8601 date (XMP, QuickTime) : `2008-10-22T16:43:21+02:00`
+static WRITTEN_OFFSET_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"T[\d:.]+(Z|[+\-]\d{2}:?\d{2})$").unwrap());
+static DATETIME_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(\d{4})[:\-/.](\d{2})[:\-/.](\d{2})(?:[ T](\d{2}):(\d{2})(?::(\d{2}))?(?:[.,]\d+)?)?\s*(?:Z|[+\-]\d{2}:?\d{2})?$").unwrap()
+});
+
+/// Metadata of an image, as found in the file. Missing values are None : the names of the
+/// folders are only computed when laying out the sorted tree (see `layout`).
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct ExifData {
-    pub year_month: Directory,
-    pub gps_lat: f64,
-    pub gps_long: f64,
-    pub place: Directory,
-    pub device: Directory,
+    /// Date and time of capture, as recorded by the device
+    pub capture_time: Option<NaiveDateTime>,
+    /// Fraction of second of the capture time, in nanoseconds (EXIF SubSecTime***)
+    pub capture_subsec: Option<u32>,
+    /// Offset from UTC of the capture time, when it is known or can be guessed
+    pub capture_offset: Option<FixedOffset>,
+    /// Correction added to the capture time, when the clock of the device was wrong
+    pub clock_correction: Option<chrono::Duration>,
+    pub gps: Option<GpsPosition>,
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub lens: Option<String>,
+    pub serial: Option<String>,
+    /// Software which wrote the file (firmware of the device, or editor)
+    pub software: Option<String>,
+    /// EXIF orientation (1 to 8)
+    pub orientation: Option<u32>,
+    /// Width and height in pixels, as stored (see displayed_dimensions)
+    pub dimensions: Option<(u32, u32)>,
+    /// XMP rating (0 to 5, -1 for rejected)
+    pub rating: Option<i32>,
+    /// XMP keywords (dc:subject), or IPTC keywords
+    pub keywords: Vec<String>,
+    /// IPTC City
+    pub city: Option<String>,
+    /// IPTC Country-PrimaryLocationName
+    pub country: Option<String>,
+    pub media_kind: MediaKind,
+    /// Apple ContentIdentifier, shared by the still and the video of a Live Photo
+    pub content_identifier: Option<String>,
+    /// Google Motion Photo, with a video appended to the still
+    pub motion_photo: Option<MotionPhoto>,
+    /// Where the capture time has been found
+    pub date_source: Option<DateSource>,
+    /// Why the date found in the file was rejected, when no valid date has been found
+    pub date_issue: Option<String>,
+}
+
+/// Kind of media of a file
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MediaKind {
+    #[default]
+    Image,
+    /// QuickTime / MP4 video
+    Video,
+    /// Camera RAW file
+    Raw,
+}
+
+/// Sources of the capture time, tried in the configured order until a valid date is found
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum DateSource {
+    /// EXIF DateTimeOriginal, then DateTimeDigitized
+    Exif,
+    /// EXIF DateTime (last change of the file by the device or a software)
+    ExifDatetime,
+    /// XMP exif:DateTimeOriginal, xmp:CreateDate or photoshop:DateCreated (embedded or
+    /// sidecar)
+    Xmp,
+    /// IPTC-IIM DateCreated and TimeCreated
+    Iptc,
+    /// QuickTime / MP4 videos : Apple creation date, then creation time of the movie header
+    Quicktime,
+    /// Google Takeout JSON sidecar : photoTakenTime, in UTC
+    Takeout,
+    /// Name of the file (user-defined patterns, then built-in ones)
+    Filename,
+    /// Name of the parent folder(s)
+    Folder,
+    /// Last modification time of the file
+    Mtime,
+}
+
+impl DateSource {
+    /// The file mtime is opt-in : copies and transfers often reset it
+    pub const DEFAULT_CHAIN: [DateSource; 8] = [
+        DateSource::Exif,
+        DateSource::ExifDatetime,
+        DateSource::Xmp,
+        DateSource::Iptc,
+        DateSource::Quicktime,
+        DateSource::Takeout,
+        DateSource::Filename,
+        DateSource::Folder,
+    ];
+}
+
+impl std::fmt::Display for DateSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use clap::ValueEnum;
+        // unwrap() is ok here, no variant is skipped
+        write!(f, "{}", self.to_possible_value().unwrap().get_name())
+    }
+}
+
+impl ExifData {
+    /// Capture time converted to UTC, when the offset is known
+    pub fn capture_time_utc(&self) -> Option<NaiveDateTime> {
+        let offset = self.capture_offset?;
+        Some(self.capture_time? - offset)
+    }
+
+    /// Capture time with its fraction of second, to order the photos of a burst
+    pub fn capture_time_precise(&self) -> Option<NaiveDateTime> {
+        let capture_time = self.capture_time?;
+        Some(
+            self.capture_subsec
+                .and_then(|nanos| capture_time.with_nanosecond(nanos))
+                .unwrap_or(capture_time),
+        )
+    }
+
+    /// Width and height of the image as displayed : the EXIF orientations 5 to 8 turn it by
+    /// a quarter
+    pub fn displayed_dimensions(&self) -> Option<(u32, u32)> {
+        let (width, height) = self.dimensions?;
+        match self.orientation {
+            Some(5..=8) => Some((height, width)),
+            _ => Some((width, height)),
+        }
+    }
+
+    pub fn megapixels(&self) -> Option<f64> {
+        let (width, height) = self.dimensions?;
+        Some(width as f64 * height as f64 / 1_000_000.0)
+    }
+
+    /// Ratio of the long side to the short side (1 for a square image)
+    pub fn aspect_ratio(&self) -> Option<f64> {
+        let (width, height) = self.dimensions?;
+        if width == 0 || height == 0 {
+            return None;
+        }
+        Some(width.max(height) as f64 / width.min(height) as f64)
+    }
+
+    /// Is the image smaller than the minimum number of megapixels (a thumbnail) ?
+    pub fn is_below_megapixels(&self, min_megapixels: Option<f64>) -> bool {
+        self.media_kind == MediaKind::Image
+            && min_megapixels.is_some_and(|min| self.megapixels().is_some_and(|megapixels| megapixels < min))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GpsPosition {
+    /// Decimal degrees, negative in the southern hemisphere
+    pub latitude: f64,
+    /// Decimal degrees, negative west of Greenwich
+    pub longitude: f64,
+    /// Meters, negative below sea level
+    pub altitude: Option<f64>,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -30,165 +220,648 @@ pub enum ExifError {
     Decoding(String),
 }
 
-/// Directory Struct to ensure that only authorized characters in directories names.
-///
-/// # Examples
-/// ```
-/// let dir = Directory::parse(String::from("Cool @name"));
-/// assert_eq!(dir.get(), "Cool  name");
-///
-/// ```
-#[derive(Debug, PartialEq)]
-#[derive(Clone)]
-pub struct Directory(String);
-impl Directory {
-    pub fn parse(s: String) -> Directory {
-        log::trace!("parse {}", s);
-        let re = Regex::new(r"[^\w]").unwrap();
-        let clean_string = re.replace_all(&s, " ");
-        log::debug!("clean_string = {}", clean_string);
-        Self(clean_string.to_string())
-    }
-
-    pub fn get(&self) -> &String {
-        &self.0
-    }
-}
-
 /// get the exif data needed to sort the file
-pub fn get_exif_data(path: &Path) -> Result<ExifData, ExifError> {
+pub fn get_exif_data(
+    path: &Path,
+    configuration: &GlobalConfiguration,
+) -> Result<ExifData, ExifError> {
     let timer = Timer::new();
 
     log::trace!("get_exif_data of {:?}", &path);
-    let file = std::fs::File::open(path)?;
+    let file = archives::open(path)?;
     let mut bufreader = std::io::BufReader::new(file);
     let exifreader = exif::Reader::new();
 
-    //let exif = exifreader.read_from_container(&mut bufreader)?;
-    let exif = match exifreader.read_from_container(&mut bufreader) {
-        Ok(exif) => exif,
+    let mut video_data = None;
+    let is_raw = raw::is_raw(path);
+    // RAW files not readable as TIFF files, then all the other containers
+    let read = if is_raw { raw::read_raw_exif(path).transpose() } else { None };
+    let exif = match read.unwrap_or_else(|| exifreader.read_from_container(&mut bufreader)) {
+        Ok(exif) => Some(exif),
         Err(e) => match e {
             exif::Error::Io(io) => return Err(ExifError::IO(io)),
+            exif::Error::InvalidFormat(_) if quicktime::is_quicktime(path)? => {
+                video_data = Some(quicktime::read_video_metadata(path)?.unwrap_or_default());
+                None
+            }
             exif::Error::InvalidFormat(s) => return Err(ExifError::NotImageFile(s.to_string())),
-            _ => return Err(ExifError::NoExifData),
+            _ => None,
         },
     };
 
-    let exif_data = analyze_exif_data(exif)?;
+    let mut exif_data = match (&exif, &video_data) {
+        (Some(exif), _) => analyze_exif_data(exif)?,
+        (None, Some(video_data)) => ExifData {
+            gps: video_data.gps,
+            make: video_data.make.clone(),
+            model: video_data.model.clone(),
+            content_identifier: video_data.content_identifier.clone(),
+            media_kind: MediaKind::Video,
+            ..Default::default()
+        },
+        (None, None) => ExifData::default(),
+    };
+    if is_raw {
+        exif_data.media_kind = MediaKind::Raw;
+    }
+    if exif_data.dimensions.is_none() && exif_data.media_kind == MediaKind::Image {
+        exif_data.dimensions = dimensions::read_dimensions(path).unwrap_or_else(|e| {
+            log::warn!("Error {:?} when reading the dimensions of {:?}", e, path);
+            None
+        });
+    }
+    let jpeg_segments = jpeg::read_segments(path, &[jpeg::JPEG_APP1, jpeg::JPEG_APP13]).unwrap_or_else(|e| {
+        log::warn!("Error {:?} when reading the segments of {:?}", e, path);
+        None
+    });
+    let precedence = *configuration.xmp_precedence();
+    let exif_date = exif.as_ref().is_some_and(|e| e.get_field(Tag::DateTimeOriginal, In::PRIMARY).is_some());
+    let embedded_xmp = match &jpeg_segments {
+        Some(segments) => xmp::find_segments_packet(segments),
+        // in the other containers, the packet is searched in the first megabytes of the file :
+        // unless EXIF wins and is complete
+        None if precedence == XmpPrecedence::Xmp || !exif_date || exif_data.gps.is_none() => xmp::read_xmp_packet(path).unwrap_or_else(|e| {
+            log::warn!("Error {:?} when reading the XMP packet of {:?}", e, path);
+            None
+        }),
+        None => None,
+    };
+    let xmp_data = xmp::read_xmp(path, embedded_xmp).unwrap_or_else(|e| {
+        log::warn!("Error {:?} when reading XMP of {:?}", e, path);
+        None
+    });
+    if let Some(xmp_data) = &xmp_data {
+        merge_xmp_data(&mut exif_data, xmp_data, precedence);
+    }
+    if exif_data.media_kind == MediaKind::Image {
+        let xmp_flag = xmp_data.as_ref().is_some_and(|x| x.motion_photo);
+        let xmp_video_length = xmp_data.as_ref().and_then(|x| x.motion_video_length);
+        exif_data.motion_photo = live_photos::find_motion_photo(path, xmp_flag, xmp_video_length);
+    }
+    // IPTC records are only read from JPEG files, in the same pass as XMP
+    let iptc_data = jpeg_segments.as_deref().and_then(iptc::find_iptc);
+    if let Some(iptc_data) = &iptc_data {
+        exif_data.city = iptc_data.city.clone();
+        exif_data.country = iptc_data.country.clone();
+        if exif_data.keywords.is_empty() {
+            exif_data.keywords = iptc_data.keywords.clone();
+        }
+    }
+    let takeout_data = takeout::read_takeout(path).unwrap_or_else(|e| {
+        log::warn!("Error {:?} when reading Takeout sidecar of {:?}", e, path);
+        None
+    });
+    if let Some(takeout_data) = &takeout_data {
+        exif_data.gps = exif_data.gps.or(takeout_data.gps);
+    }
+    let metadata = Metadata {
+        exif: exif.as_ref(),
+        xmp: xmp_data.as_ref(),
+        iptc: iptc_data.as_ref(),
+        video: video_data.as_ref(),
+        takeout: takeout_data.as_ref(),
+    };
+    find_capture_time(path, &metadata, &mut exif_data, configuration);
+    // the folder and the mtime of the file are not set by the clock of the device
+    if !matches!(exif_data.date_source, Some(DateSource::Folder | DateSource::Mtime)) {
+        exif_data.clock_correction =
+            clock_offsets::correct(&mut exif_data, configuration.clock_corrections());
+    }
+    exif_data.capture_offset = find_capture_offset(&metadata, &exif_data);
 
     // Record performance metrics
     PerformanceMetrics::record_exif_read(timer.elapsed());
 
+    if exif.is_none() && video_data.is_none() && exif_data.capture_time.is_none() {
+        return Err(ExifError::NoExifData);
+    }
     Ok(exif_data)
 }
 
-fn analyze_exif_data(exif: Exif) -> Result<ExifData, ExifError> {
-    log::trace!("analyze_exif_data ...");
+/// Metadata blocks found in a file
+struct Metadata<'a> {
+    exif: Option<&'a Exif>,
+    xmp: Option<&'a XmpData>,
+    iptc: Option<&'a IptcData>,
+    video: Option<&'a VideoData>,
+    takeout: Option<&'a TakeoutData>,
+}
 
-    let mut exif_data = ExifData {
-        year_month: Directory::parse(String::from("Unknown Date")),
-        place: Directory::parse(String::from("Unknown Place")),
-        device: Directory::parse(String::from("Unknown Device")),
-        gps_lat: 0.0,
-        gps_long: 0.0,
+/// Everything but the date (see find_capture_time) : GPS position following the precedence,
+/// rating and keywords
+fn merge_xmp_data(exif_data: &mut ExifData, xmp_data: &XmpData, precedence: XmpPrecedence) {
+    exif_data.gps = match precedence {
+        XmpPrecedence::Xmp => xmp_data.gps.or(exif_data.gps),
+        XmpPrecedence::Exif => exif_data.gps.or(xmp_data.gps),
     };
+    exif_data.rating = xmp_data.rating;
+    exif_data.keywords = xmp_data.keywords.clone();
+}
 
-    let date_time_original = exif.get_field(Tag::DateTimeOriginal, In::PRIMARY);
-    let date_time_digitized = exif.get_field(Tag::DateTimeDigitized, In::PRIMARY);
-    if let Some(timestamp) = analyze_exif_datetime(date_time_original) {
-        exif_data.year_month = timestamp;
-    } else {
-        log::warn!("EXIF DateTimeOriginal tag is missing - trying DateTimeDigitized");
-        if let Some(timestamp) = analyze_exif_datetime(date_time_digitized) {
-            exif_data.year_month = timestamp;
-        } else {
-            log::warn!("both EXIF DateTimeOriginal and DateTimeDigitized tag are missing");
+/// Try the configured date sources in order, XMP first if it has the precedence ; the first
+/// valid date wins
+fn find_capture_time(
+    path: &Path,
+    metadata: &Metadata,
+    exif_data: &mut ExifData,
+    configuration: &GlobalConfiguration,
+) {
+    let exif = metadata.exif;
+    let mut sources = configuration.date_sources().clone();
+    if *configuration.xmp_precedence() == XmpPrecedence::Xmp {
+        // stable sort : only the XMP source moves
+        sources.sort_by_key(|source| *source != DateSource::Xmp);
+    }
+    for source in &sources {
+        let found = match source {
+            DateSource::Exif => date_from_exif(exif, &[Tag::DateTimeOriginal, Tag::DateTimeDigitized]),
+            DateSource::ExifDatetime => date_from_exif(exif, &[Tag::DateTime]),
+            DateSource::Xmp => date_from_xmp(metadata.xmp),
+            DateSource::Iptc => date_from_iptc(metadata.iptc),
+            DateSource::Quicktime => date_from_quicktime(metadata.video, exif_data.gps),
+            DateSource::Takeout => date_from_takeout(metadata.takeout, exif_data.gps),
+            DateSource::Filename => date_from_filename(path, configuration.filename_date_patterns()),
+            DateSource::Folder => date_from_folder(path),
+            DateSource::Mtime => date_from_mtime(path),
+        };
+        match found {
+            Ok(Some(timestamp)) => {
+                log::debug!("capture time {} found in {}", timestamp, source);
+                exif_data.capture_time = Some(timestamp);
+                exif_data.capture_subsec = match (exif, source) {
+                    (Some(exif), DateSource::Exif) => {
+                        analyze_exif_subsec(exif, &[Tag::SubSecTimeOriginal, Tag::SubSecTimeDigitized])
+                    }
+                    (Some(exif), DateSource::ExifDatetime) => analyze_exif_subsec(exif, &[Tag::SubSecTime]),
+                    _ => None,
+                };
+                exif_data.date_source = Some(*source);
+                exif_data.date_issue = None;
+                return;
+            }
+            Ok(None) => log::debug!("no capture time found in {}", source),
+            Err(reason) => {
+                log::warn!("date found in {} of {:?} rejected : {}", source, path, reason);
+                exif_data.date_issue.get_or_insert(format!("{} : {}", source, reason));
+            }
         }
     }
+    log::warn!("no valid capture time found for {:?}", path);
+}
 
-    let device = exif.get_field(Tag::Model, In::PRIMARY);
-    if let Some(model) = device {
-        log::debug!("EXIF Model = {}", model.display_value());
-        exif_data.device = Directory::parse(model.display_value().to_string());
-    } else {
+/// Find the offset from UTC of the capture time : offset written with the XMP or IPTC date or
+/// OffsetTime*** tags, then the difference with the GPS UTC timestamp, then the time zone at
+/// the GPS position, then the nautical time zone of the GPS longitude. The file mtime is in
+/// the local time zone of this computer.
+fn find_capture_offset(metadata: &Metadata, exif_data: &ExifData) -> Option<FixedOffset> {
+    let capture_time = exif_data.capture_time?;
+    let exif = metadata.exif;
+    let written_offset = match exif_data.date_source {
+        Some(DateSource::Xmp) => metadata.xmp.and_then(|x| x.date_offset()),
+        // HHMMSS±HHMM
+        Some(DateSource::Iptc) => metadata
+            .iptc
+            .and_then(|i| i.time_created.as_deref())
+            .and_then(|t| t.get(6..)),
+        Some(DateSource::Quicktime) => metadata.video.and_then(|v| v.date_offset()),
+        _ => None,
+    };
+    if let Some(offset) = written_offset.and_then(parse_offset) {
+        log::debug!("offset {} written with the date", offset);
+        return Some(offset);
+    }
+    let from_exif = matches!(
+        exif_data.date_source,
+        Some(DateSource::Exif | DateSource::ExifDatetime)
+    );
+    if exif_data.date_source == Some(DateSource::Mtime) {
+        return chrono::Local
+            .offset_from_local_datetime(&capture_time)
+            .earliest();
+    }
+    // the creation time of the movie header and the Takeout photoTakenTime are in UTC
+    let utc = match exif_data.date_source {
+        Some(DateSource::Quicktime) => metadata.video.and_then(|v| v.creation_time_utc),
+        Some(DateSource::Takeout) => metadata.takeout.and_then(|t| t.photo_taken_time),
+        _ => None,
+    };
+    if let Some(utc) = utc {
+        return Some(local_offset(utc, exif_data.gps));
+    }
+    if let Some(exif) = exif.filter(|_| from_exif) {
+        let tags: &[Tag] = match exif_data.date_source {
+            Some(DateSource::Exif) => &[Tag::OffsetTimeOriginal, Tag::OffsetTimeDigitized],
+            _ => &[Tag::OffsetTime],
+        };
+        if let Some(offset) = tags
+            .iter()
+            .filter_map(|tag| analyze_exif_ascii(exif, *tag))
+            .find_map(|value| parse_offset(&value))
+        {
+            log::debug!("offset {} found in EXIF OffsetTime***", offset);
+            return Some(offset);
+        }
+        if let Some(offset) = analyze_exif_gps_utc(exif).and_then(|utc| offset_between(capture_time, utc)) {
+            log::debug!("offset {} found with the GPS UTC timestamp", offset);
+            return Some(offset);
+        }
+    }
+    let gps = exif_data
+        .gps
+        .filter(|gps| gps.latitude != 0.0 || gps.longitude != 0.0)?;
+    match place_finder::find_timezone(gps.latitude, gps.longitude) {
+        Some(tz) => tz
+            .offset_from_local_datetime(&capture_time)
+            .earliest()
+            .map(|offset| offset.fix()),
+        None => Some(place_finder::nautical_offset(gps.longitude)),
+    }
+}
+
+/// Offset of the local time at an instant : time zone at the GPS position, or of this
+/// computer without GPS position
+fn local_offset(utc: NaiveDateTime, gps: Option<GpsPosition>) -> FixedOffset {
+    let gps = gps.filter(|gps| gps.latitude != 0.0 || gps.longitude != 0.0);
+    match gps {
+        Some(gps) => match place_finder::find_timezone(gps.latitude, gps.longitude) {
+            Some(tz) => tz.offset_from_utc_datetime(&utc).fix(),
+            None => place_finder::nautical_offset(gps.longitude),
+        },
+        None => chrono::Local.offset_from_utc_datetime(&utc).fix(),
+    }
+}
+
+/// Offset written at the end of an ISO 8601 date, if any
+pub fn written_offset(date: &str) -> Option<&str> {
+    Some(WRITTEN_OFFSET_REGEX.captures(date)?.get(1)?.as_str())
+}
+
+/// Parse an offset written `+02:00`, `-0530` or `Z`
+fn parse_offset(value: &str) -> Option<FixedOffset> {
+    let value = value.trim();
+    if value == "Z" {
+        return FixedOffset::east_opt(0);
+    }
+    let (sign, digits) = match value.chars().next()? {
+        '+' => (1, &value[1..]),
+        '-' => (-1, &value[1..]),
+        _ => return None,
+    };
+    let digits = digits.replace(':', "");
+    if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i32 = digits[..2].parse().ok()?;
+    let minutes: i32 = digits[2..].parse().ok()?;
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Offset between a local time and the same instant in UTC, rounded to the quarter of hour
+/// (clocks and GPS fixes are never exactly in sync). None if it is not a real offset.
+fn offset_between(local: NaiveDateTime, utc: NaiveDateTime) -> Option<FixedOffset> {
+    const QUARTER: i64 = 15 * 60;
+    let seconds = (local - utc).num_seconds();
+    let quarters = (seconds as f64 / QUARTER as f64).round() as i64;
+    if quarters.abs() > 14 * 4 {
+        log::warn!("GPS UTC timestamp {} too far from capture time {}", utc, local);
+        return None;
+    }
+    FixedOffset::east_opt((quarters * QUARTER) as i32)
+}
+
+/// Return the first valid date, or the first error if none is valid
+fn first_valid_date(
+    candidates: impl Iterator<Item = Result<Option<NaiveDateTime>, String>>,
+) -> Result<Option<NaiveDateTime>, String> {
+    let mut first_error = None;
+    for candidate in candidates {
+        match candidate {
+            Ok(Some(timestamp)) => return Ok(Some(timestamp)),
+            Ok(None) => (),
+            Err(reason) => {
+                first_error.get_or_insert(reason);
+            }
+        }
+    }
+    match first_error {
+        Some(reason) => Err(reason),
+        None => Ok(None),
+    }
+}
+
+fn date_from_exif(exif: Option<&Exif>, tags: &[Tag]) -> Result<Option<NaiveDateTime>, String> {
+    let Some(exif) = exif else {
+        return Ok(None);
+    };
+    first_valid_date(tags.iter().map(|tag| {
+        analyze_exif_datetime(exif.get_field(*tag, In::PRIMARY))
+            .map_err(|reason| format!("{} {}", tag, reason))
+    }))
+}
+
+fn date_from_xmp(xmp_data: Option<&XmpData>) -> Result<Option<NaiveDateTime>, String> {
+    match xmp_data.and_then(|x| x.date.as_deref()) {
+        Some(value) => parse_datetime(value),
+        None => Ok(None),
+    }
+}
+
+/// Date of a video : the Apple creation date is local, the creation time of the movie header
+/// is in UTC and is converted to the local time of the capture
+fn date_from_quicktime(video_data: Option<&VideoData>, gps: Option<GpsPosition>) -> Result<Option<NaiveDateTime>, String> {
+    let Some(video_data) = video_data else {
+        return Ok(None);
+    };
+    let creation_date = video_data.creation_date.as_deref().map(parse_datetime);
+    let creation_time = video_data
+        .creation_time_utc
+        .map(|utc| check_datetime(utc + local_offset(utc, gps)).map(Some));
+    first_valid_date(creation_date.into_iter().chain(creation_time))
+}
+
+/// Date of the Takeout sidecar, converted from UTC to the local time of the capture
+fn date_from_takeout(takeout_data: Option<&TakeoutData>, gps: Option<GpsPosition>) -> Result<Option<NaiveDateTime>, String> {
+    match takeout_data.and_then(|t| t.photo_taken_time) {
+        Some(utc) => check_datetime(utc + local_offset(utc, gps)).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Date from IPTC DateCreated (`CCYYMMDD`) and TimeCreated (`HHMMSS±HHMM`). Archives often
+/// know only the year or the month of old photos : an unknown (`00`) month or day is the
+/// first one.
+fn date_from_iptc(iptc_data: Option<&IptcData>) -> Result<Option<NaiveDateTime>, String> {
+    let Some(date) = iptc_data.and_then(|i| i.date_created.as_deref()) else {
+        return Ok(None);
+    };
+    let number = |value: &str, range: std::ops::Range<usize>| -> Option<u32> {
+        value.get(range)?.parse().ok()
+    };
+    let (Some(year), Some(month), Some(day)) = (number(date, 0..4), number(date, 4..6), number(date, 6..8)) else {
+        return Err(format!("malformed IPTC date '{}'", date));
+    };
+    let time = iptc_data.and_then(|i| i.time_created.as_deref()).unwrap_or("");
+    let datetime = NaiveDate::from_ymd_opt(year as i32, month.max(1), day.max(1))
+        .and_then(|d| {
+            d.and_hms_opt(
+                number(time, 0..2).unwrap_or(0),
+                number(time, 2..4).unwrap_or(0),
+                number(time, 4..6).unwrap_or(0),
+            )
+        })
+        .ok_or(format!("impossible IPTC date '{} {}'", date, time))?;
+    check_datetime(datetime).map(Some)
+}
+
+fn date_from_filename(path: &Path, user_patterns: &[Regex]) -> Result<Option<NaiveDateTime>, String> {
+    let Some(stem) = path.file_stem() else {
+        return Ok(None);
+    };
+    let stem = stem.to_string_lossy();
+    first_valid_date(
+        user_patterns
+            .iter()
+            .chain(FILENAME_DATE_REGEXES.iter())
+            .filter_map(|re| re.captures(&stem))
+            .map(|captures| date_from_captures(&captures)),
+    )
+}
+
+/// Look for a date in the name of the parent folder, then of the two last folders
+/// (`2019/08/IMG_0001.JPG`)
+fn date_from_folder(path: &Path) -> Result<Option<NaiveDateTime>, String> {
+    let mut folders = path.parent().map(|p| p.components().rev()).into_iter().flatten();
+    let Some(parent) = folders.next() else {
+        return Ok(None);
+    };
+    let parent = parent.as_os_str().to_string_lossy().to_string();
+    let mut candidates = vec![parent.clone()];
+    if let Some(grandparent) = folders.next() {
+        candidates.push(format!("{}/{}", grandparent.as_os_str().to_string_lossy(), parent));
+    }
+    first_valid_date(
+        candidates
+            .iter()
+            .filter_map(|c| FOLDER_DATE_REGEX.captures(c))
+            .map(|captures| date_from_captures(&captures)),
+    )
+}
+
+fn date_from_mtime(path: &Path) -> Result<Option<NaiveDateTime>, String> {
+    let modified = archives::modified(path).map_err(|e| format!("mtime can't be read ({})", e))?;
+    check_datetime(modified).map(Some)
+}
+
+/// Build a date from the named groups year, month, day, hour, minute, second of a regex.
+/// Missing day is the first of the month, missing time is midnight.
+fn date_from_captures(captures: &regex::Captures) -> Result<Option<NaiveDateTime>, String> {
+    let number = |name: &str, default: u32| -> u32 {
+        captures
+            .name(name)
+            .and_then(|m| m.as_str().parse().ok())
+            .unwrap_or(default)
+    };
+    let (year, month, day) = (number("year", 0), number("month", 1), number("day", 1));
+    let datetime = NaiveDate::from_ymd_opt(year as i32, month, day)
+        .and_then(|d| d.and_hms_opt(number("hour", 0), number("minute", 0), number("second", 0)))
+        .ok_or(format!("impossible date in '{}'", &captures[0]))?;
+    check_datetime(datetime).map(Some)
+}
+
+/// analyze everything but the capture time (see find_capture_time)
+fn analyze_exif_data(exif: &Exif) -> Result<ExifData, ExifError> {
+    log::trace!("analyze_exif_data ...");
+
+    let width = analyze_exif_uint(exif, Tag::PixelXDimension)
+        .or_else(|| analyze_exif_uint(exif, Tag::ImageWidth));
+    let height = analyze_exif_uint(exif, Tag::PixelYDimension)
+        .or_else(|| analyze_exif_uint(exif, Tag::ImageLength));
+    let mut exif_data = ExifData {
+        make: analyze_exif_ascii(exif, Tag::Make),
+        model: analyze_exif_ascii(exif, Tag::Model),
+        lens: analyze_exif_ascii(exif, Tag::LensModel),
+        serial: analyze_exif_ascii(exif, Tag::BodySerialNumber),
+        software: analyze_exif_ascii(exif, Tag::Software),
+        orientation: analyze_exif_uint(exif, Tag::Orientation),
+        dimensions: width.zip(height),
+        content_identifier: exif
+            .get_field(Tag::MakerNote, In::PRIMARY)
+            .and_then(|field| match field.value {
+                Value::Undefined(ref maker_note, _) => live_photos::apple_content_identifier(maker_note),
+                _ => None,
+            }),
+        ..Default::default()
+    };
+    if exif_data.model.is_none() {
         log::warn!("EXIF Model tag is missing");
     }
 
     // https://exiftool.org/TagNames/GPS.html
     let lat = exif.get_field(Tag::GPSLatitude, In::PRIMARY);
     let lat_ref = exif.get_field(Tag::GPSLatitudeRef, In::PRIMARY);
-
-    exif_data.gps_lat = analyze_exif_lat_long(lat, lat_ref)?;
+    let latitude = analyze_exif_lat_long(lat, lat_ref)?;
 
     let long = exif.get_field(Tag::GPSLongitude, In::PRIMARY);
     let long_ref = exif.get_field(Tag::GPSLongitudeRef, In::PRIMARY);
+    let longitude = analyze_exif_lat_long(long, long_ref)?;
 
-    exif_data.gps_long = analyze_exif_lat_long(long, long_ref)?;
-
-    if exif_data.gps_lat != 0.0 || exif_data.gps_long != 0.0 {
-        let place = place_finder::find_place(exif_data.gps_lat, exif_data.gps_long);
-        if let Some(place) = place {
-            log::debug!("EXIF Place from reverse geocoding = {}", place);
-            exif_data.place = Directory::parse(place);
-        } else {
-            log::warn!("EXIF no place found");
-            exif_data.place = Directory::parse(String::from("Unknown Place"));
-        }
-    } else {
-        exif_data.place = Directory::parse(String::from("Null_Island"));
-        // https://fr.wikipedia.org/wiki/Null_Island
+    if let (Some(latitude), Some(longitude)) = (latitude, longitude) {
+        let altitude = exif.get_field(Tag::GPSAltitude, In::PRIMARY);
+        let altitude_ref = exif.get_field(Tag::GPSAltitudeRef, In::PRIMARY);
+        exif_data.gps = Some(GpsPosition {
+            latitude,
+            longitude,
+            altitude: analyze_exif_altitude(altitude, altitude_ref),
+        });
     }
 
     Ok(exif_data)
 }
 
-fn analyze_exif_datetime(date_time: Option<&Field>) -> Option<Directory> {
+/// Parse a DateTime*** field. Ok(None) if the field is missing or blank, Err(reason) if its
+/// value can't be used as a capture time.
+fn analyze_exif_datetime(date_time: Option<&Field>) -> Result<Option<NaiveDateTime>, String> {
     log::trace!("analyze_exif_datetime {:?}", date_time);
-    if let Some(timestamp) = date_time {
-        log::debug!("EXIF DateTime*** = {}", timestamp.display_value());
-        let timestamp_value = timestamp.display_value().to_string();
-        Some(Directory::parse(String::from(&timestamp_value[0..7])))
+    let Some(timestamp) = date_time else {
+        return Ok(None);
+    };
+    log::debug!("EXIF DateTime*** = {}", timestamp.display_value());
+    match timestamp.value {
+        Value::Ascii(ref ascii) => match ascii.first() {
+            Some(bytes) => parse_datetime(&String::from_utf8_lossy(bytes)),
+            None => Ok(None),
+        },
+        _ => Err(String::from("not an ASCII value")),
+    }
+}
+
+/// Parse a date (and time) written in one of the variants found in metadata :
+/// `2008:10:22 16:43:21`, `2008-10-22T16:43:21`, `2008:10:22 16:43:21.50+02:00`,
+/// `2008/10/22 16:43`, `2008:10:22`...
+/// Ok(None) if the value is blank (the EXIF way of saying "unknown"), Err(reason) if it is
+/// malformed, a placeholder, or not plausible.
+pub fn parse_datetime(value: &str) -> Result<Option<NaiveDateTime>, String> {
+    let value = value.trim_matches(|c: char| c.is_whitespace() || c == '\0');
+    if value.chars().all(|c| c == ' ' || c == ':') {
+        return Ok(None);
+    }
+    let captures = DATETIME_REGEX
+        .captures(value)
+        .ok_or(format!("malformed date '{}'", value))?;
+    let number = |i: usize| -> u32 {
+        captures
+            .get(i)
+            .map_or(0, |m| m.as_str().parse().unwrap_or(0))
+    };
+    let (year, month, day) = (number(1), number(2), number(3));
+    if year == 0 || month == 0 || day == 0 {
+        return Err(format!("placeholder date '{}'", value));
+    }
+    let datetime = NaiveDate::from_ymd_opt(year as i32, month, day)
+        .and_then(|d| d.and_hms_opt(number(4), number(5), number(6)))
+        .ok_or(format!("impossible date '{}'", value))?;
+    check_datetime(datetime).map(Some)
+}
+
+/// Reject dates that are certainly wrong : before the invention of photography, the Unix
+/// epoch (default value of many devices), or in the future.
+pub fn check_datetime(datetime: NaiveDateTime) -> Result<NaiveDateTime, String> {
+    let date = datetime.date();
+    if date.year() < FIRST_PHOTOGRAPH_YEAR {
+        Err(format!("date {} is before the invention of photography", date))
+    } else if date == NaiveDate::from_ymd_opt(1970, 1, 1).unwrap() {
+        Err(format!("date {} is the Unix epoch", date))
+    } else if datetime > chrono::Local::now().naive_local() + chrono::Duration::days(1) {
+        Err(format!("date {} is in the future", date))
     } else {
-        None
+        Ok(datetime)
+    }
+}
+
+/// Get the (trimmed) value of an ASCII field, None if it is missing or empty
+fn analyze_exif_ascii(exif: &Exif, tag: Tag) -> Option<String> {
+    let field = exif.get_field(tag, In::PRIMARY)?;
+    let Value::Ascii(ref ascii) = field.value else {
+        return None;
+    };
+    let value = String::from_utf8_lossy(ascii.first()?)
+        .trim_matches(|c: char| c.is_whitespace() || c == '\0')
+        .to_string();
+    log::debug!("EXIF {} = {}", tag, value);
+    Some(value).filter(|v| !v.is_empty())
+}
+
+/// Get the UTC time of the GPS fix from GPSDateStamp (`2008:10:22`) and GPSTimeStamp
+/// (hour, minute, second as rationals)
+fn analyze_exif_gps_utc(exif: &Exif) -> Option<NaiveDateTime> {
+    let date = analyze_exif_ascii(exif, Tag::GPSDateStamp)?;
+    let date = NaiveDate::parse_from_str(&date, "%Y:%m:%d").ok()?;
+    let Value::Rational(ref time) = exif.get_field(Tag::GPSTimeStamp, In::PRIMARY)?.value else {
+        return None;
+    };
+    let [hour, minute, second] = time.as_slice() else {
+        return None;
+    };
+    let utc = date.and_hms_opt(hour.to_f64() as u32, minute.to_f64() as u32, second.to_f64() as u32)?;
+    log::debug!("EXIF GPS UTC timestamp = {}", utc);
+    Some(utc)
+}
+
+/// Get the fraction of second of SubSecTime*** (`45` is 0.45 s), in nanoseconds
+fn analyze_exif_subsec(exif: &Exif, tags: &[Tag]) -> Option<u32> {
+    let digits = tags.iter().find_map(|tag| analyze_exif_ascii(exif, *tag))?;
+    if !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let nanos: String = digits.chars().chain(std::iter::repeat('0')).take(9).collect();
+    nanos.parse().ok()
+}
+
+fn analyze_exif_uint(exif: &Exif, tag: Tag) -> Option<u32> {
+    exif.get_field(tag, In::PRIMARY)?.value.get_uint(0)
+}
+
+/// analyse fields GPSAltitude and GPSAltitudeRef and return the altitude in meters
+fn analyze_exif_altitude(altitude: Option<&Field>, altitude_ref: Option<&Field>) -> Option<f64> {
+    let Value::Rational(ref altitude) = altitude?.value else {
+        return None;
+    };
+    let altitude = altitude.first()?.to_f64();
+    // GPSAltitudeRef = 1 : below sea level
+    match altitude_ref.and_then(|r| r.value.get_uint(0)) {
+        Some(1) => Some(-altitude),
+        _ => Some(altitude),
     }
 }
 
 /// analyse field GPSLatitude / GPSLongitude and GPSLatitudeRef / GPSLongitudeRef and return
 /// a f64 value that represent the latitude in decimal degree.
-/// If not possible to get the latitude, return 0.0 (latitude of Null Island)
-fn analyze_exif_lat_long(l: Option<&Field>, l_ref: Option<&Field>) -> Result<f64, ExifError> {
-    if let Some(l) = l {
-        log::debug!("EXIF GPSL*** = {}", l.display_value());
-        let latitude_or_longitude = match &l.value {
-            Value::Rational(vec_rationals) => {
-                //let l = place_finder::convert_deg_min_sec_to_decimal_deg(vec_rationals)?;
-                let l = match place_finder::convert_deg_min_sec_to_decimal_deg(vec_rationals) {
-                    Ok(l) => l,
-                    Err(place_finder::PlaceFinderError::Decode(coords)) => {
-                        return Err(ExifError::Decoding(coords))
-                    }
-                };
+/// If the fields are missing, return None.
+fn analyze_exif_lat_long(
+    l: Option<&Field>,
+    l_ref: Option<&Field>,
+) -> Result<Option<f64>, ExifError> {
+    let (Some(l), Some(l_ref)) = (l, l_ref) else {
+        log::warn!("EXIF GPSLatitude/GPSLongitude tags are missing");
+        return Ok(None);
+    };
+    log::debug!("EXIF GPSL*** = {}", l.display_value());
+    let Value::Rational(ref vec_rationals) = l.value else {
+        return Ok(None);
+    };
+    let l = match place_finder::convert_deg_min_sec_to_decimal_deg(vec_rationals) {
+        Ok(l) => l,
+        Err(place_finder::PlaceFinderError::Decode(coords)) => {
+            return Err(ExifError::Decoding(coords))
+        }
+    };
 
-                match l_ref {
-                    Some(v) => {
-                        log::debug!("EXIF GPSL***Ref = {}", v.display_value());
-                        if v.display_value().to_string() == "N"
-                            || v.display_value().to_string() == "E"
-                        {
-                            l
-                        } else {
-                            -1.0 * l
-                        }
-                    }
-                    None => 0.0,
-                }
-            }
-            _ => 0.0,
-        };
-        Ok(latitude_or_longitude)
+    log::debug!("EXIF GPSL***Ref = {}", l_ref.display_value());
+    let l_ref = l_ref.display_value().to_string();
+    if l_ref == "N" || l_ref == "E" {
+        Ok(Some(l))
     } else {
-        log::warn!("EXIF GPSLatitude tag is missing");
-        Ok(0.0)
+        Ok(Some(-l))
     }
 }
 
@@ -204,16 +877,106 @@ mod tests {
     fn test_get_exif_data() {
         init();
         let path = std::path::Path::new("data_4_tests/DSCN0025.jpg");
-        let exif_data = get_exif_data(path).unwrap();
+        let exif_data = get_exif_data(path, &GlobalConfiguration::new()).unwrap();
         log::debug!("{:?}", exif_data);
         assert_eq!(
-            exif_data.year_month,
-            Directory::parse("2008 10".to_string())
+            exif_data.capture_time,
+            NaiveDate::from_ymd_opt(2008, 10, 22).unwrap().and_hms_opt(16, 43, 21)
         );
-        assert_eq!(exif_data.place, Directory::parse("Arezzo".to_string()));
+        assert_eq!(exif_data.date_source, Some(DateSource::Exif));
+        assert_eq!(exif_data.make.as_deref(), Some("NIKON"));
+        assert_eq!(exif_data.model.as_deref(), Some("COOLPIX P6000"));
+        let gps = exif_data.gps.unwrap();
+        assert!((gps.latitude - 43.467).abs() < 0.01);
+        assert!((gps.longitude - 11.885).abs() < 0.01);
+        // no OffsetTime*** tags : time zone of Italy, in summer time
+        assert_eq!(exif_data.capture_offset, FixedOffset::east_opt(2 * 3600));
         assert_eq!(
-            exif_data.device,
-            Directory::parse(" COOLPIX P6000 ".to_string())
+            exif_data.capture_time_utc(),
+            NaiveDate::from_ymd_opt(2008, 10, 22).unwrap().and_hms_opt(14, 43, 21)
         );
     }
+
+    #[test]
+    fn test_capture_offset() {
+        init();
+        assert_eq!(parse_offset("+02:00"), FixedOffset::east_opt(2 * 3600));
+        assert_eq!(parse_offset("-0530"), FixedOffset::west_opt(5 * 3600 + 30 * 60));
+        assert_eq!(parse_offset("Z"), FixedOffset::east_opt(0));
+        assert_eq!(parse_offset("   :  "), None);
+        let at = |d, h, mi, s| NaiveDate::from_ymd_opt(2019, 8, d).unwrap().and_hms_opt(h, mi, s).unwrap();
+        // just after midnight in Tokyo, still the previous day in UTC
+        assert_eq!(offset_between(at(13, 0, 10, 0), at(12, 15, 8, 57)), FixedOffset::east_opt(9 * 3600));
+        assert_eq!(offset_between(at(12, 10, 0, 0), at(12, 15, 31, 2)), FixedOffset::west_opt(5 * 3600 + 30 * 60));
+        assert_eq!(offset_between(at(13, 10, 0, 0), at(11, 10, 0, 0)), None);
+    }
+
+    #[test]
+    fn test_parse_datetime() {
+        init();
+        let expected = NaiveDate::from_ymd_opt(2008, 10, 22)
+            .unwrap()
+            .and_hms_opt(16, 43, 21);
+        for value in [
+            "2008:10:22 16:43:21",
+            "2008-10-22 16:43:21",
+            "2008-10-22T16:43:21",
+            "2008:10:22 16:43:21.50",
+            "2008:10:22 16:43:21+02:00",
+            "2008-10-22T16:43:21Z",
+            " 2008/10/22 16:43:21\0",
+        ] {
+            assert_eq!(parse_datetime(value), Ok(expected), "{}", value);
+        }
+        assert_eq!(
+            parse_datetime("2008:10:22"),
+            Ok(NaiveDate::from_ymd_opt(2008, 10, 22).unwrap().and_hms_opt(0, 0, 0))
+        );
+        assert_eq!(parse_datetime("    :  :     :  :  "), Ok(None));
+        assert_eq!(parse_datetime(""), Ok(None));
+
+        assert!(parse_datetime("0000:00:00 00:00:00").unwrap_err().contains("placeholder"));
+        assert!(parse_datetime("1970:01:01 00:00:00").unwrap_err().contains("epoch"));
+        assert!(parse_datetime("2008:02:30 10:00:00").unwrap_err().contains("impossible"));
+        assert!(parse_datetime("2008:10").unwrap_err().contains("malformed"));
+        assert!(parse_datetime("1815:06:18 11:00:00").is_err());
+        assert!(parse_datetime("2999:01:01 10:00:00").unwrap_err().contains("future"));
+    }
+
+    #[test]
+    fn test_date_fallbacks() {
+        init();
+        let at = |y, m, d, h, mi, s| NaiveDate::from_ymd_opt(y, m, d).unwrap().and_hms_opt(h, mi, s);
+        for (name, expected) in [
+            ("IMG_20190812_143055.jpg", at(2019, 8, 12, 14, 30, 55)),
+            ("PXL_20210304_101112345.jpg", at(2021, 3, 4, 10, 11, 12)),
+            ("WhatsApp Image 2021-03-04 at 10.11.12.jpeg", at(2021, 3, 4, 10, 11, 12)),
+            ("IMG-20210304-WA0001.jpg", at(2021, 3, 4, 0, 0, 0)),
+            ("DSCN0025.jpg", None),
+        ] {
+            assert_eq!(date_from_filename(Path::new(name), &[]), Ok(expected), "{}", name);
+        }
+        let user = vec![Regex::new(r"^(?P<day>\d{2})\.(?P<month>\d{2})\.(?P<year>\d{4})").unwrap()];
+        assert_eq!(
+            date_from_filename(Path::new("24.12.2023 christmas.jpg"), &user),
+            Ok(at(2023, 12, 24, 0, 0, 0))
+        );
+        assert!(date_from_filename(Path::new("IMG_20191399_101010.jpg"), &[]).is_err());
+
+        assert_eq!(
+            date_from_folder(Path::new("photos/2019-08-12 Holidays/DSCN0025.jpg")),
+            Ok(at(2019, 8, 12, 0, 0, 0))
+        );
+        assert_eq!(
+            date_from_folder(Path::new("photos/2019/08/DSCN0025.jpg")),
+            Ok(at(2019, 8, 1, 0, 0, 0))
+        );
+        assert_eq!(date_from_folder(Path::new("photos/holidays/DSCN0025.jpg")), Ok(None));
+
+        let iptc_data = IptcData {
+            date_created: Some(String::from("19650700")),
+            ..Default::default()
+        };
+        assert_eq!(date_from_iptc(Some(&iptc_data)), Ok(NaiveDate::from_ymd_opt(1965, 7, 1).unwrap().and_hms_opt(0, 0, 0)));
+    }
 }
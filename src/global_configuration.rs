@@ -1,5 +1,16 @@
 use std::path::{Path, PathBuf};
 
+use regex::Regex;
+
+use crate::calendar::CalendarEvent;
+use crate::clock_offsets::ClockCorrection;
+use crate::exif::{DateSource, GpsPosition};
+use crate::classifier::Bucket;
+use crate::layout::{DateGranularity, FolderTimezone, MonthNames};
+use crate::rename::{ExtensionCase, RenameTemplate};
+use crate::views::VirtualView;
+use crate::xmp::XmpPrecedence;
+
 #[derive(Debug)]
 pub struct GlobalConfiguration {
     use_device: bool,
@@ -8,6 +19,29 @@ pub struct GlobalConfiguration {
     sorted_images_directory: PathBuf,
     unsorted_images_directory: PathBuf,
     not_images_directory: PathBuf,
+    suspicious_dates_directory: PathBuf,
+    views: Vec<VirtualView>,
+    date_sources: Vec<DateSource>,
+    filename_date_patterns: Vec<Regex>,
+    folder_timezone: FolderTimezone,
+    clock_corrections: Vec<ClockCorrection>,
+    date_granularity: DateGranularity,
+    month_names: Option<MonthNames>,
+    event_gap: Option<chrono::Duration>,
+    home: Option<GpsPosition>,
+    home_radius_km: f64,
+    calendar: Vec<CalendarEvent>,
+    xmp_precedence: XmpPrecedence,
+    videos_subdir: bool,
+    raw_subdir: bool,
+    extract_motion_video: bool,
+    expand_archives: bool,
+    rename: Option<RenameTemplate>,
+    extension_case: ExtensionCase,
+    buckets: Vec<Bucket>,
+    orientation_subdir: bool,
+    panorama_ratio: Option<f64>,
+    min_megapixels: Option<f64>,
 }
 
 impl GlobalConfiguration {
@@ -19,6 +53,29 @@ impl GlobalConfiguration {
             sorted_images_directory: PathBuf::new(),
             unsorted_images_directory: PathBuf::new(),
             not_images_directory: PathBuf::new(),
+            suspicious_dates_directory: PathBuf::new(),
+            views: Vec::new(),
+            date_sources: DateSource::DEFAULT_CHAIN.to_vec(),
+            filename_date_patterns: Vec::new(),
+            folder_timezone: FolderTimezone::default(),
+            clock_corrections: Vec::new(),
+            date_granularity: DateGranularity::default(),
+            month_names: None,
+            event_gap: None,
+            home: None,
+            home_radius_km: 50.0,
+            calendar: Vec::new(),
+            xmp_precedence: XmpPrecedence::default(),
+            videos_subdir: false,
+            raw_subdir: false,
+            extract_motion_video: false,
+            expand_archives: false,
+            rename: None,
+            extension_case: ExtensionCase::default(),
+            buckets: Vec::new(),
+            orientation_subdir: false,
+            panorama_ratio: None,
+            min_megapixels: None,
         }
     }
 
@@ -89,6 +146,190 @@ impl GlobalConfiguration {
     pub fn not_images_directory_mut(&mut self) -> &mut PathBuf {
         &mut self.not_images_directory
     }
+
+    pub fn suspicious_dates_directory_as_path(&self) -> &Path {
+        self.suspicious_dates_directory.as_path()
+    }
+
+    pub fn suspicious_dates_directory_mut(&mut self) -> &mut PathBuf {
+        &mut self.suspicious_dates_directory
+    }
+
+    pub fn views(&self) -> &Vec<VirtualView> {
+        &self.views
+    }
+
+    pub fn views_mut(&mut self) -> &mut Vec<VirtualView> {
+        &mut self.views
+    }
+
+    pub fn date_sources(&self) -> &Vec<DateSource> {
+        &self.date_sources
+    }
+
+    pub fn date_sources_mut(&mut self) -> &mut Vec<DateSource> {
+        &mut self.date_sources
+    }
+
+    pub fn filename_date_patterns(&self) -> &Vec<Regex> {
+        &self.filename_date_patterns
+    }
+
+    pub fn filename_date_patterns_mut(&mut self) -> &mut Vec<Regex> {
+        &mut self.filename_date_patterns
+    }
+
+    pub fn folder_timezone(&self) -> &FolderTimezone {
+        &self.folder_timezone
+    }
+
+    pub fn folder_timezone_mut(&mut self) -> &mut FolderTimezone {
+        &mut self.folder_timezone
+    }
+
+    pub fn clock_corrections(&self) -> &Vec<ClockCorrection> {
+        &self.clock_corrections
+    }
+
+    pub fn clock_corrections_mut(&mut self) -> &mut Vec<ClockCorrection> {
+        &mut self.clock_corrections
+    }
+
+    pub fn date_granularity(&self) -> &DateGranularity {
+        &self.date_granularity
+    }
+
+    pub fn date_granularity_mut(&mut self) -> &mut DateGranularity {
+        &mut self.date_granularity
+    }
+
+    pub fn month_names(&self) -> &Option<MonthNames> {
+        &self.month_names
+    }
+
+    pub fn month_names_mut(&mut self) -> &mut Option<MonthNames> {
+        &mut self.month_names
+    }
+
+    pub fn event_gap(&self) -> &Option<chrono::Duration> {
+        &self.event_gap
+    }
+
+    pub fn event_gap_mut(&mut self) -> &mut Option<chrono::Duration> {
+        &mut self.event_gap
+    }
+
+    pub fn home(&self) -> &Option<GpsPosition> {
+        &self.home
+    }
+
+    pub fn home_mut(&mut self) -> &mut Option<GpsPosition> {
+        &mut self.home
+    }
+
+    pub fn home_radius_km(&self) -> &f64 {
+        &self.home_radius_km
+    }
+
+    pub fn home_radius_km_mut(&mut self) -> &mut f64 {
+        &mut self.home_radius_km
+    }
+
+    pub fn calendar(&self) -> &Vec<CalendarEvent> {
+        &self.calendar
+    }
+
+    pub fn calendar_mut(&mut self) -> &mut Vec<CalendarEvent> {
+        &mut self.calendar
+    }
+
+    pub fn xmp_precedence(&self) -> &XmpPrecedence {
+        &self.xmp_precedence
+    }
+
+    pub fn xmp_precedence_mut(&mut self) -> &mut XmpPrecedence {
+        &mut self.xmp_precedence
+    }
+
+    pub fn videos_subdir(&self) -> &bool {
+        &self.videos_subdir
+    }
+
+    pub fn videos_subdir_mut(&mut self) -> &mut bool {
+        &mut self.videos_subdir
+    }
+
+    pub fn raw_subdir(&self) -> &bool {
+        &self.raw_subdir
+    }
+
+    pub fn raw_subdir_mut(&mut self) -> &mut bool {
+        &mut self.raw_subdir
+    }
+
+    pub fn extract_motion_video(&self) -> &bool {
+        &self.extract_motion_video
+    }
+
+    pub fn extract_motion_video_mut(&mut self) -> &mut bool {
+        &mut self.extract_motion_video
+    }
+
+    pub fn expand_archives(&self) -> &bool {
+        &self.expand_archives
+    }
+
+    pub fn expand_archives_mut(&mut self) -> &mut bool {
+        &mut self.expand_archives
+    }
+
+    pub fn rename(&self) -> &Option<RenameTemplate> {
+        &self.rename
+    }
+
+    pub fn rename_mut(&mut self) -> &mut Option<RenameTemplate> {
+        &mut self.rename
+    }
+
+    pub fn extension_case(&self) -> &ExtensionCase {
+        &self.extension_case
+    }
+
+    pub fn extension_case_mut(&mut self) -> &mut ExtensionCase {
+        &mut self.extension_case
+    }
+
+    pub fn buckets(&self) -> &Vec<Bucket> {
+        &self.buckets
+    }
+
+    pub fn buckets_mut(&mut self) -> &mut Vec<Bucket> {
+        &mut self.buckets
+    }
+
+    pub fn orientation_subdir(&self) -> &bool {
+        &self.orientation_subdir
+    }
+
+    pub fn orientation_subdir_mut(&mut self) -> &mut bool {
+        &mut self.orientation_subdir
+    }
+
+    pub fn panorama_ratio(&self) -> &Option<f64> {
+        &self.panorama_ratio
+    }
+
+    pub fn panorama_ratio_mut(&mut self) -> &mut Option<f64> {
+        &mut self.panorama_ratio
+    }
+
+    pub fn min_megapixels(&self) -> &Option<f64> {
+        &self.min_megapixels
+    }
+
+    pub fn min_megapixels_mut(&mut self) -> &mut Option<f64> {
+        &mut self.min_megapixels
+    }
 }
 
 #[cfg(test)]
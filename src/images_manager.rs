@@ -1,27 +1,36 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
 use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
 use std::sync::Arc;
 
+use crate::archives;
+use crate::classifier;
+use crate::clock_offsets;
+use crate::companions::{self, FileGroup};
 use crate::directories;
 use crate::exif;
-use crate::exif::ExifData;
-use crate::exif::ExifError;
+use crate::exif::{ExifData, ExifError, MediaKind};
 use crate::global_configuration::GlobalConfiguration;
+use crate::layout::FolderNames;
+use crate::live_photos;
+use crate::manifest::Manifest;
+use crate::plan::{ScannedArchive, ScannedFile, SortPlan};
 use crate::performance::{PerformanceMetrics, Timer};
 use crate::reporting::Reporting;
+use crate::shutdown;
+use crate::takeout;
+use crate::views;
 use eyre::Result;
 use indicatif::{ProgressBar, ProgressStyle};
 use rayon::prelude::*;
 
-pub fn sort_images_in_dir(
-    dir: &std::path::Path,
-    configuration: &GlobalConfiguration,
-) -> Result<()> {
-    log::trace!("sort_images_of_dir in {:?}", dir);
+// sidecars kept in memory while an archive is read, the larger ones are not sidecars
+const MAX_KEPT_ENTRY_SIZE: u64 = 1024 * 1024;
 
-    let files = directories::get_files_from_dir(dir)?;
-    let bar = ProgressBar::new(files.len().try_into().unwrap());
+fn dir_progress_bar(len: usize) -> Arc<ProgressBar> {
+    let bar = ProgressBar::new(len.try_into().unwrap());
     bar.set_style(
         ProgressStyle::default_bar()
             .template("  {spinner:.blue} [{bar:30.cyan/blue}] {pos}/{len} {msg}")
@@ -30,156 +39,539 @@ pub fn sort_images_in_dir(
     );
 
     // Wrap progress bar in Arc for sharing across threads
-    let bar = Arc::new(bar);
+    Arc::new(bar)
+}
 
+fn configure_thread_pool() {
     // Configure rayon thread pool to use moderate parallelism (good for NAS HDD)
     // Limit to 4 threads to avoid disk thrashing
     rayon::ThreadPoolBuilder::new()
         .num_threads(2)
         .build_global()
         .ok(); // Ignore error if already initialized
+}
 
-    // Process files in parallel
-    files.par_iter().for_each(|file| {
-        bar.set_message(format!("{}", file.file_name().unwrap_or_default().to_string_lossy()));
-
-        let r_exif_data = exif::get_exif_data(file);
-        match r_exif_data {
-            Ok(exif_data) => {
-                // Collect statistics
-                Reporting::add_place(exif_data.place.get().to_string());
-                Reporting::add_device(exif_data.device.get().to_string());
-                Reporting::update_date_range(exif_data.year_month.get());
-
-                match sort_image_from_exif_data(file, &exif_data, configuration) {
-                    Ok(()) => {
-                        log::trace!("Image {:?} processed...", file);
-                        Reporting::image_processed_sorted();
-                    }
-                    Err(e) => {
-                        log::error!("Error {:?} when processing image {:?} ...", e, file);
-                        Reporting::error_on_image();
-                        Reporting::add_error(file.clone(), format!("{}", e));
-                        eprintln!("Error {} when processing image {:?} ...", e, file)
-                    }
+/// First phase : read the metadata of the files of a directory
+pub fn scan_dir(dir: &std::path::Path, configuration: &GlobalConfiguration) -> Result<Vec<ScannedFile>> {
+    log::trace!("scan_dir in {:?}", dir);
+
+    let mut files = directories::get_files_from_dir(dir)?;
+    if *configuration.expand_archives() {
+        files.retain(|file| !archives::is_archive(file));
+    }
+    let bar = dir_progress_bar(files.len());
+    configure_thread_pool();
+
+    let scanned = files
+        .into_par_iter()
+        // on interruption, stop scheduling new files
+        .filter(|_| !shutdown::is_interrupted())
+        .map(|file| {
+            bar.set_message(format!("{}", file.file_name().unwrap_or_default().to_string_lossy()));
+            let exif_data = exif::get_exif_data(&file, configuration);
+            bar.inc(1);
+            ScannedFile { path: file, exif_data }
+        })
+        .collect();
+
+    bar.finish_and_clear();
+    Ok(scanned)
+}
+
+/// Archives of a directory, whose entries are sorted instead of the archive itself
+pub fn find_archives(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = directories::get_files_from_dir(dir)?;
+    files.retain(|file| archives::is_archive(file));
+    Ok(files)
+}
+
+/// First phase for an archive : read the metadata of its entries, streamed one at a time.
+/// The small sidecars (XMP, Takeout JSON) are read first and kept in memory until the
+/// archive is sorted, to be found by the readers of the other entries.
+pub fn scan_archive(archive: &Path, configuration: &GlobalConfiguration) -> Result<ScannedArchive> {
+    log::trace!("scan_archive {:?}", archive);
+    read_archive_entries(archive, configuration).inspect_err(|_| archives::unload_archive(archive))
+}
+
+fn read_archive_entries(archive: &Path, configuration: &GlobalConfiguration) -> Result<ScannedArchive> {
+    archives::for_each_entry(archive, |entry| {
+        let is_json = entry.path.extension().is_some_and(|e| e.eq_ignore_ascii_case("json"));
+        if entry.size <= MAX_KEPT_ENTRY_SIZE && (is_json || companions::is_sidecar(&entry.path)) {
+            archives::load(entry)?;
+        }
+        Ok(())
+    })?;
+
+    let mut directories: BTreeMap<PathBuf, Vec<ScannedFile>> = BTreeMap::new();
+    archives::for_each_entry(archive, |entry| {
+        // on interruption, stop reading the archive
+        if shutdown::is_interrupted() {
+            return Err(std::io::Error::from(std::io::ErrorKind::Interrupted));
+        }
+        let path = entry.path.clone();
+        let kept = archives::is_loaded(&path);
+        if !kept {
+            archives::load(entry)?;
+        }
+        let exif_data = exif::get_exif_data(&path, configuration);
+        if !kept {
+            archives::unload(&path);
+        }
+        let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        directories.entry(dir).or_default().push(ScannedFile { path, exif_data });
+        Ok(())
+    })
+    .or_else(|e| match e.kind() {
+        std::io::ErrorKind::Interrupted => Ok(()),
+        _ => Err(e),
+    })?;
+    let directories: Vec<Vec<ScannedFile>> = directories.into_values().collect();
+    log::info!(
+        "{} entries in {} directories of {:?}",
+        directories.iter().map(Vec::len).sum::<usize>(),
+        directories.len(),
+        archive
+    );
+    Ok(ScannedArchive {
+        path: archive.to_path_buf(),
+        directories,
+    })
+}
+
+/// Second phase for an archive : sort its groups of files from their metadata, which
+/// reserves the destinations of the entries, then stream the archive again and write each
+/// entry straight to its destinations
+pub fn sort_scanned_archive(
+    archive: &ScannedArchive,
+    plan: &SortPlan,
+    configuration: &GlobalConfiguration,
+) -> Result<()> {
+    log::trace!("sort_scanned_archive {:?}", archive.path);
+    let groups: Vec<FileGroup> = archive
+        .directories
+        .iter()
+        .flat_map(|files| companions::group_companions(files))
+        .collect();
+    let primaries: HashMap<&Path, &ScannedFile> =
+        groups.iter().map(|group| (group.primary.path.as_path(), group.primary)).collect();
+    let members: HashSet<&Path> = groups.iter().flat_map(FileGroup::members).map(|file| file.path.as_path()).collect();
+    let bar = dir_progress_bar(members.len());
+    for group in &groups {
+        sort_group(group, plan, configuration);
+    }
+
+    let result = archives::for_each_entry(&archive.path, |entry| {
+        if shutdown::is_interrupted() {
+            return Err(std::io::Error::from(std::io::ErrorKind::Interrupted));
+        }
+        let path = entry.path.clone();
+        bar.set_message(format!("{}", path.file_name().unwrap_or_default().to_string_lossy()));
+        let timer = Timer::new();
+        match archives::write_pending(entry) {
+            Ok((destinations, _)) if destinations.is_empty() => (),
+            Ok((destinations, size)) => {
+                PerformanceMetrics::record_file_copy(timer.elapsed(), size * destinations.len() as u64);
+                if let Some(primary) = primaries.get(path.as_path()).filter(|_| *configuration.extract_motion_video()) {
+                    extract_motion_video(primary, &destinations[0]);
                 }
             }
-            Err(e) => match e {
-                ExifError::IO(io) => {
-                    log::error!("Error {:?} when processing image {:?} ...", io, file);
-                    Reporting::error_on_image();
-                    Reporting::add_error(file.clone(), format!("IO error: {}", io));
-                    eprintln!("Error {} when processing image {:?} ...", io, file)
+            Err(e) => {
+                log::error!("Error {:?} when writing {:?}", e, path);
+                Reporting::error_on_image();
+                Reporting::add_error(path.clone(), format!("{}", e));
+                eprintln!("Error {} when writing {:?}", e, path)
+            }
+        }
+        if members.contains(path.as_path()) {
+            bar.inc(1);
+        }
+        Ok(())
+    });
+    // the destinations of the entries not found again are removed
+    archives::unload_archive(&archive.path);
+    bar.finish_and_clear();
+    match result {
+        Err(e) if e.kind() == std::io::ErrorKind::Interrupted => Ok(()),
+        Err(e) => Err(e.into()),
+        Ok(()) => {
+            Reporting::archive_expanded(members.len() as u32);
+            Ok(())
+        }
+    }
+}
+
+/// Second phase : copy the scanned files of a directory where the plan and their metadata
+/// tell. The companions of a file (twins, sidecars) are copied with it.
+pub fn sort_scanned_files(
+    files: &[ScannedFile],
+    plan: &SortPlan,
+    configuration: &GlobalConfiguration,
+) -> Result<()> {
+    log::trace!("sort_scanned_files of {} files", files.len());
+    let bar = dir_progress_bar(files.len());
+    configure_thread_pool();
+
+    // Process groups in parallel : the files of a group are copied by the same thread
+    companions::group_companions(files).par_iter().for_each(|group| {
+        // on interruption, stop scheduling new files ; the ones in progress are finished
+        if shutdown::is_interrupted() {
+            return;
+        }
+        bar.set_message(format!("{}", group.primary.path.file_name().unwrap_or_default().to_string_lossy()));
+        sort_group(group, plan, configuration);
+        bar.inc(group.len() as u64);
+    });
+
+    bar.finish_and_clear();
+    Ok(())
+}
+
+/// Copy the primary file of a group, then the files travelling with it
+fn sort_group(group: &FileGroup, plan: &SortPlan, configuration: &GlobalConfiguration) {
+    let primary = group.primary;
+    let min_megapixels = *configuration.min_megapixels();
+    if primary.exif_data.as_ref().is_ok_and(|e| e.is_below_megapixels(min_megapixels)) {
+        log::info!("{:?} skipped, smaller than {} megapixels", primary.path, min_megapixels.unwrap_or_default());
+        Reporting::thumbnail_skipped();
+        // only the small image is skipped : its companions go with the first twin, or are
+        // skipped with it
+        match group.twins.split_first() {
+            Some((twin, twins)) => {
+                let group = FileGroup {
+                    primary: twin,
+                    twins: twins.to_vec(),
+                    sidecars: group.sidecars.clone(),
+                };
+                sort_group(&group, plan, configuration);
+            }
+            None => {
+                for sidecar in &group.sidecars {
+                    log::info!("{:?} skipped, sidecar of {:?}", sidecar.path, primary.path);
+                    Reporting::thumbnail_skipped();
                 }
-                ExifError::NotImageFile(s) => {
-                    log::warn!("{} is not an image. {}", file.display(), s);
-                    match copy_not_image_file(file, configuration.not_images_directory_as_path()) {
-                        Ok(()) => {
-                            Reporting::not_image_processed();
-                            log::trace!(
-                                "Non-image file {:?} copied to Not_Images/",
-                                file
-                            )
-                        }
-                        Err(e) => {
-                            log::error!("Error {:?} when copying non-image file {:?}", e, file);
-                            Reporting::error_on_image();
-                            Reporting::add_error(file.clone(), format!("{}", e));
-                            eprintln!("Error {} when copying non-image file {:?}", e, file)
-                        }
+            }
+        }
+        return;
+    }
+    let file_name = plan
+        .name(&primary.path)
+        .unwrap_or(primary.path.file_name().unwrap_or_default());
+    let target = sort_file(&primary.path, &primary.exif_data, &primary.path, file_name, plan, configuration);
+    match target {
+        Some(target) => {
+            // the still of an archive entry is read once written, see sort_scanned_archive
+            if *configuration.extract_motion_video() && !archives::is_pending(&primary.path) {
+                extract_motion_video(primary, &target);
+            }
+            sort_companions(group, &target, configuration)
+        }
+        None => {
+            for companion in group.twins.iter().chain(&group.sidecars) {
+                log::error!("{:?} not copied, as {:?} failed", companion.path, primary.path);
+                Reporting::error_on_image();
+                Reporting::add_error(companion.path.clone(), format!("not copied, as {:?} failed", primary.path));
+            }
+        }
+    }
+}
+
+/// Copy the twins and the sidecars of a file copied to `target`, renamed like it, in the
+/// directory of `target`
+fn sort_companions(group: &FileGroup, target: &Path, configuration: &GlobalConfiguration) {
+    let primary = group.primary;
+    let stem = target.file_stem().unwrap_or_default().to_string_lossy();
+    let case = configuration.extension_case();
+    let companion_name = |companion: &ScannedFile| {
+        let suffix = companions::companion_suffix(&companion.path, &primary.path);
+        OsString::from(format!("{}{}", stem, case.apply_to_suffix(&suffix)))
+    };
+    // unwrap() is ok here, the target is a file in a directory
+    let target_dir = target.parent().unwrap();
+    // next to their primary file, whatever the Videos or RAW folders : IMG_0001.HEIC and
+    // IMG_0001.MOV, IMG_0001.CR2 and IMG_0001.JPG
+    for twin in &group.twins {
+        match copy_companion(&twin.path, target_dir, &companion_name(twin)) {
+            Ok(twin_target) => {
+                let note = format!("twin of {}", primary.path.display());
+                Manifest::record(&twin.path, &twin_target, twin.exif_data.as_ref().ok(), &note);
+                Reporting::image_processed_sorted();
+                match (&primary.exif_data, &twin.exif_data) {
+                    (Ok(primary_data), Ok(twin_data)) if companions::is_live_photo_video(primary_data, twin_data) => {
+                        Reporting::video_sorted();
+                        Reporting::live_photo_paired();
                     }
+                    (_, Ok(twin_data)) if twin_data.media_kind == MediaKind::Video => Reporting::video_sorted(),
+                    (_, Ok(twin_data)) if twin_data.media_kind == MediaKind::Raw => Reporting::raw_sorted(),
+                    _ => (),
                 }
-                ExifError::Decoding(s) => {
-                    log::error!("Error {:?} when decoding exif_data of file {:?}", s, file);
-                    match copy_unsorted_image_in_specific_dir(file, configuration.unsorted_images_directory_as_path()) {
-                        Ok(()) => {
-                            Reporting::image_processed_unsorted();
-                            log::trace!(
-                                "Image {:?} processed (no Exif Data -> copied in unsorted dir)...",
-                                file
-                            )
-                        }
-                        Err(e) => {
-                            log::error!("Error {:?} when processing image {:?} ...", e, file);
-                            Reporting::error_on_image();
-                            Reporting::add_error(file.clone(), format!("{}", e));
-                            eprintln!("Error {} when processing image {:?} ...", e, file)
-                        }
-                    }
+                log::trace!("Twin {:?} copied with {:?}", twin.path, primary.path);
+            }
+            Err(e) => {
+                log::error!("Error {:?} when copying twin {:?}", e, twin.path);
+                Reporting::error_on_image();
+                Reporting::add_error(twin.path.clone(), format!("{}", e));
+                eprintln!("Error {} when copying twin {:?}", e, twin.path)
+            }
+        }
+    }
+    for sidecar in &group.sidecars {
+        match copy_companion(&sidecar.path, target_dir, &companion_name(sidecar)) {
+            Ok(companion_target) => {
+                let note = format!("sidecar of {}", primary.path.display());
+                Manifest::record(&sidecar.path, &companion_target, None, &note);
+                Reporting::sidecar_processed();
+                log::trace!("Sidecar {:?} copied with {:?}", sidecar.path, primary.path);
+            }
+            Err(e) => {
+                log::error!("Error {:?} when copying sidecar {:?}", e, sidecar.path);
+                Reporting::error_on_image();
+                Reporting::add_error(sidecar.path.clone(), format!("{}", e));
+                eprintln!("Error {} when copying sidecar {:?}", e, sidecar.path)
+            }
+        }
+    }
+}
+
+/// Write the video embedded in a Motion Photo copied to `target` next to it, as an MP4 file
+/// (`PXL_20210304_101112345.mp4` for `PXL_20210304_101112345.MP.jpg`)
+fn extract_motion_video(file: &ScannedFile, target: &Path) {
+    let Some(motion_photo) = file.exif_data.as_ref().ok().and_then(|e| e.motion_photo) else {
+        return;
+    };
+    let stem = target.file_stem().unwrap_or_default().to_string_lossy();
+    let stem = stem.strip_suffix(".MP").or_else(|| stem.strip_suffix(".mp")).unwrap_or(&stem);
+    // unwrap() is ok here, the target is a file in a directory
+    let video_path = target.parent().unwrap().join(format!("{}.mp4", stem));
+    let written = fs::read(target).map_err(eyre::Report::from).and_then(|still| {
+        let video = live_photos::find_motion_video(&still, &motion_photo)
+            .ok_or_else(|| eyre::eyre!("no video found in the motion photo"))?;
+        let video_path = check_for_duplicate_and_rename(&video_path)?.unwrap_or(video_path);
+        fs::write(&video_path, video)?;
+        Ok(video_path)
+    });
+    match written {
+        Ok(video_path) => {
+            Manifest::record(&file.path, &video_path, None, "video extracted from the motion photo");
+            Reporting::motion_video_extracted();
+            log::trace!("Video of {:?} extracted in {:?}", file.path, video_path);
+        }
+        Err(e) => log::warn!("Video of the motion photo {:?} not extracted : {}", file.path, e),
+    }
+}
+
+/// Copy a file where its metadata tell, named `file_name`. The plan is looked up with
+/// `plan_key` : the path of the primary file of its group. Return the target when the file
+/// has been copied.
+fn sort_file(
+    file: &Path,
+    exif_data: &std::result::Result<ExifData, ExifError>,
+    plan_key: &Path,
+    file_name: &OsStr,
+    plan: &SortPlan,
+    configuration: &GlobalConfiguration,
+) -> Option<PathBuf> {
+    let file = &file.to_path_buf();
+    let bucket = match exif_data {
+        Ok(exif_data) => classifier::find_bucket(file, Some(exif_data), configuration.buckets()),
+        Err(ExifError::NoExifData | ExifError::Decoding(_)) => {
+            classifier::find_bucket(file, None, configuration.buckets())
+        }
+        Err(_) => None,
+    };
+    let copied = match exif_data {
+        _ if bucket.is_some() => {
+            // unwrap() is ok here, checked by the match guard
+            let bucket = bucket.unwrap();
+            copy_unsorted_image_in_specific_dir(file, file_name, &bucket.directory).inspect(|target| {
+                let note = format!("classified as {:?}", bucket.class).to_lowercase();
+                Manifest::record(file, target, exif_data.as_ref().ok(), &note);
+                Reporting::image_processed_classified();
+                log::trace!("Image {:?} processed (classified -> copied in {:?})...", file, bucket.directory)
+            })
+        }
+        Ok(exif_data) if exif_data.capture_time.is_none() && exif_data.date_issue.is_some() => {
+            // unwrap() is ok here, checked by the match guard
+            let reason = exif_data.date_issue.clone().unwrap();
+            log::warn!("Date of {:?} rejected : {}", file, reason);
+            copy_unsorted_image_in_specific_dir(file, file_name, configuration.suspicious_dates_directory_as_path())
+                .inspect(|target| {
+                    Manifest::record(file, target, Some(exif_data), &reason);
+                    Reporting::image_processed_suspicious_date(file.clone(), reason);
+                    log::trace!(
+                        "Image {:?} processed (suspicious date -> copied in suspicious dates dir)...",
+                        file
+                    )
+                })
+        }
+        Ok(exif_data) => {
+            let mut folders = FolderNames::from_exif_data(exif_data, configuration);
+            if let Some(event) = plan.event(plan_key) {
+                folders = folders.with_event(event, configuration);
+            }
+            if let Some(trip) = plan.trip(plan_key) {
+                folders = folders.with_trip(trip);
+            }
+
+            // Collect statistics
+            Reporting::add_place(folders.place.get().to_string());
+            Reporting::add_device(folders.device.get().to_string());
+            if let Some(capture_time) = exif_data.capture_time {
+                Reporting::update_date_range(&capture_time.format("%Y-%m-%d").to_string());
+            }
+
+            sort_image_from_exif_data(file, file_name, &folders, configuration).inspect(|target| {
+                let note = exif_data
+                    .clock_correction
+                    .map(|c| format!("clock corrected by {}", clock_offsets::format_offset(c)))
+                    .unwrap_or_default();
+                Manifest::record(file, target, Some(exif_data), &note);
+                log::trace!("Image {:?} processed...", file);
+                Reporting::image_processed_sorted();
+                match exif_data.media_kind {
+                    MediaKind::Video => Reporting::video_sorted(),
+                    MediaKind::Raw => Reporting::raw_sorted(),
+                    MediaKind::Image => (),
                 }
-                ExifError::NoExifData => {
-                    log::warn!("Warning: {:?} when getting exif_data of file {:?}", e, file);
-                    match copy_unsorted_image_in_specific_dir(file, configuration.unsorted_images_directory_as_path()) {
-                        Ok(()) => {
-                            Reporting::image_processed_unsorted();
-                            log::trace!(
-                                "Image {:?} processed (no Exif Data -> copied in unsorted dir)...",
-                                file
-                            )
-                        }
-                        Err(e) => {
-                            log::error!("Error {:?} when processing image {:?} ...", e, file);
-                            Reporting::error_on_image();
-                            Reporting::add_error(file.clone(), format!("{}", e));
-                            eprintln!("Error {} when processing image {:?} ...", e, file)
-                        }
+            })
+        }
+        Err(e) => match e {
+            ExifError::IO(io) => {
+                log::error!("Error {:?} when processing image {:?} ...", io, file);
+                Reporting::error_on_image();
+                Reporting::add_error(file.clone(), format!("IO error: {}", io));
+                eprintln!("Error {} when processing image {:?} ...", io, file);
+                return None;
+            }
+            ExifError::NotImageFile(_) if takeout::is_takeout_sidecar(file) => {
+                log::debug!("Takeout sidecar {:?} not copied", file);
+                Reporting::takeout_sidecar_skipped();
+                return None;
+            }
+            ExifError::NotImageFile(s) => {
+                log::warn!("{} is not an image. {}", file.display(), s);
+                match copy_not_image_file(file, file_name, configuration.not_images_directory_as_path()) {
+                    Ok(target) => {
+                        Manifest::record(file, &target, None, "not an image");
+                        Reporting::not_image_processed();
+                        log::trace!(
+                            "Non-image file {:?} copied to Not_Images/",
+                            file
+                        );
+                        return Some(target);
+                    }
+                    Err(e) => {
+                        log::error!("Error {:?} when copying non-image file {:?}", e, file);
+                        Reporting::error_on_image();
+                        Reporting::add_error(file.clone(), format!("{}", e));
+                        eprintln!("Error {} when copying non-image file {:?}", e, file);
+                        return None;
                     }
                 }
-            },
+            }
+            ExifError::Decoding(s) => {
+                log::error!("Error {:?} when decoding exif_data of file {:?}", s, file);
+                copy_unsorted_image_in_specific_dir(file, file_name, configuration.unsorted_images_directory_as_path())
+                    .inspect(|target| {
+                        Manifest::record(file, target, None, &format!("GPS coords {} can't be decoded", s));
+                        Reporting::image_processed_unsorted();
+                        log::trace!(
+                            "Image {:?} processed (no Exif Data -> copied in unsorted dir)...",
+                            file
+                        )
+                    })
+            }
+            ExifError::NoExifData => {
+                log::warn!("Warning: {:?} when getting exif_data of file {:?}", e, file);
+                copy_unsorted_image_in_specific_dir(file, file_name, configuration.unsorted_images_directory_as_path())
+                    .inspect(|target| {
+                        Manifest::record(file, target, None, "no date found");
+                        Reporting::image_processed_unsorted();
+                        log::trace!(
+                            "Image {:?} processed (no Exif Data -> copied in unsorted dir)...",
+                            file
+                        )
+                    })
+            }
+        },
+    };
+    match copied {
+        Ok(target) => Some(target),
+        Err(e) => {
+            log::error!("Error {:?} when processing image {:?} ...", e, file);
+            Reporting::error_on_image();
+            Reporting::add_error(file.clone(), format!("{}", e));
+            eprintln!("Error {} when processing image {:?} ...", e, file);
+            None
         }
-        bar.inc(1);
-    });
+    }
+}
 
-    bar.finish_and_clear();
-    Ok(())
+/// Copy a sidecar in the directory of its primary file
+fn copy_companion(file: &Path, target_dir: &Path, file_name: &OsStr) -> Result<PathBuf> {
+    log::trace!("copy_companion file: {:?}, target_dir: {:?}", file, target_dir);
+    let dest_path = target_dir.join(file_name);
+    let checked = check_for_duplicate_and_rename(dest_path.as_path())?;
+    let target = checked.unwrap_or(dest_path);
+    copy_file_with_metrics(file, target.as_path())?;
+    Ok(target)
 }
 
 fn sort_image_from_exif_data(
     file: &std::path::Path,
-    exif_data: &ExifData,
+    file_name: &OsStr,
+    folders: &FolderNames,
     configuration: &GlobalConfiguration,
-) -> Result<()> {
+) -> Result<PathBuf> {
     log::trace!(
-        "sort_image_from_exif_data file: {:?} exif_data: {:?}",
+        "sort_image_from_exif_data file: {:?} folders: {:?}",
         file,
-        exif_data
+        folders
     );
-    let new_directory_path = std::path::Path::new(exif_data.year_month.get());
+    let new_directory_path = std::path::Path::new(folders.date.get());
     let new_directory_path_buf = directories::create_subdir(configuration.sorted_images_directory_as_path(), new_directory_path)?;
-    let new_directory_path = std::path::Path::new(exif_data.place.get());
+    let new_directory_path = std::path::Path::new(folders.event.as_ref().unwrap_or(&folders.place).get());
     let mut new_directory_path_buf =
         directories::create_subdir(new_directory_path_buf.as_path(), new_directory_path)?;
 
     if *configuration.use_device() {
-        let new_directory_path = std::path::Path::new(exif_data.device.get());
+        let new_directory_path = std::path::Path::new(folders.device.get());
         new_directory_path_buf =
             directories::create_subdir(new_directory_path_buf.as_path(), new_directory_path)?;
     }
+    if let Some(media) = &folders.media {
+        new_directory_path_buf =
+            directories::create_subdir(new_directory_path_buf.as_path(), std::path::Path::new(media.get()))?;
+    }
 
     let p = new_directory_path_buf.as_path();
-    // unwrap() is ok here, the file have been checked as a file before
-    let pb = p.join(std::path::Path::new(&file.file_name().unwrap()));
+    let pb = p.join(file_name);
     let checked = check_for_duplicate_and_rename(pb.as_path())?;
+    let target = checked.unwrap_or(pb);
+    copy_file_with_metrics(file, target.as_path())?;
 
-    if let Some(deduplicate_path_name) = checked {
-        copy_file_with_metrics(file, deduplicate_path_name.as_path())?;
-    } else {
-        copy_file_with_metrics(file, pb.as_path())?;
+    if !configuration.views().is_empty() {
+        views::create_views(target.as_path(), folders, configuration)?;
     }
 
-    Ok(())
+    Ok(target)
 }
 
 fn copy_unsorted_image_in_specific_dir(
     file: &std::path::Path,
+    file_name: &OsStr,
     unsorted_dir: &std::path::Path,
-) -> Result<()> {
+) -> Result<PathBuf> {
     log::trace!(
         "copy_unsorted_image_in_specific_dir file: {:?}, unsorted_dir: {:?}",
         file,
         unsorted_dir
     );
-    let p = unsorted_dir.join(file);
+    // keep only the normal components : joining an absolute path would replace unsorted_dir
+    let relative_file: PathBuf = file
+        .components()
+        .filter(|c| matches!(c, std::path::Component::Normal(_)))
+        .collect();
+    let p = unsorted_dir.join(relative_file.with_file_name(file_name));
     fs::DirBuilder::new()
         .recursive(true)
         .create(p.as_path().parent().unwrap())?;
@@ -187,14 +579,15 @@ fn copy_unsorted_image_in_specific_dir(
     log::debug!("file: {:?} to: {:?}", file, p.as_path());
     copy_file_with_metrics(file, p.as_path())?;
 
-    Ok(())
+    Ok(p)
 }
 
 /// Copy non-image file to Not_Images directory (flat structure, no hierarchy)
 fn copy_not_image_file(
     file: &std::path::Path,
+    file_name: &OsStr,
     not_images_dir: &std::path::Path,
-) -> Result<()> {
+) -> Result<PathBuf> {
     log::trace!(
         "copy_not_image_file file: {:?}, not_images_dir: {:?}",
         file,
@@ -202,32 +595,29 @@ fn copy_not_image_file(
     );
 
     // Flat structure: just use filename, no hierarchy
-    let filename = file.file_name().unwrap();
-    let dest_path = not_images_dir.join(filename);
+    let dest_path = not_images_dir.join(file_name);
 
     // Check for duplicates and rename if needed
     let checked = check_for_duplicate_and_rename(dest_path.as_path())?;
+    let target = checked.unwrap_or(dest_path);
+    copy_file_with_metrics(file, target.as_path())?;
 
-    if let Some(deduplicate_path) = checked {
-        copy_file_with_metrics(file, deduplicate_path.as_path())?;
-    } else {
-        copy_file_with_metrics(file, dest_path.as_path())?;
-    }
-
-    Ok(())
+    Ok(target)
 }
 
 /// Copy a file and record performance metrics (time and bytes)
-fn copy_file_with_metrics(from: &Path, to: &Path) -> Result<u64> {
+fn copy_file_with_metrics(from: &Path, to: &Path) -> Result<()> {
     let timer = Timer::new();
 
-    // Perform the copy
-    let bytes_copied = fs::copy(from, to)?;
+    // Perform the copy, or reserve it for an archive entry not in memory
+    let bytes_copied = archives::copy(from, to)?;
 
-    // Record metrics
-    PerformanceMetrics::record_file_copy(timer.elapsed(), bytes_copied);
+    // Record metrics, of the pending copies when they are written
+    if let Some(bytes_copied) = bytes_copied {
+        PerformanceMetrics::record_file_copy(timer.elapsed(), bytes_copied);
+    }
 
-    Ok(bytes_copied)
+    Ok(())
 }
 
 /// verify if there is already a file pointed by the path. If so, return a new path
@@ -284,7 +674,7 @@ fn check_for_duplicate_and_rename(file: &Path) -> Result<Option<PathBuf>> {
 
 #[cfg(test)]
 mod tests {
-    use crate::exif::Directory;
+    use crate::layout::Directory;
 
     use super::*;
 
@@ -412,7 +802,7 @@ mod tests {
         let file = std::path::Path::new("foo_test.txt");
         fs::write(file, "Lorem ipsum").unwrap();
 
-        copy_unsorted_image_in_specific_dir(file, dir).unwrap();
+        copy_unsorted_image_in_specific_dir(file, file.as_os_str(), dir).unwrap();
         let copied_file = std::path::Path::new("./test_cp_unsorted/foo_test.txt");
         assert!(copied_file.exists());
 
@@ -435,17 +825,18 @@ mod tests {
         *configuration.source_directory_mut() = PathBuf::from("./");
         *configuration.sorted_images_directory_mut() = PathBuf::from(dir_target);
 
-        let exif_data = ExifData {
-            year_month: Directory::parse(String::from("2023 10")),
-            gps_lat: 0.0,
-            gps_long: 0.0,
+        let folders = FolderNames {
+            date: Directory::parse(String::from("2023 10")),
             place: Directory::parse(String::from("Null_Island")),
             device: Directory::parse(String::from("Nikkon")),
+            event: None,
+            media: None,
         };
 
         sort_image_from_exif_data(
             Path::new("./data_4_tests/DSCN0025.jpg"),
-            &exif_data,
+            OsStr::new("DSCN0025.jpg"),
+            &folders,
             &configuration,
         )
         .unwrap();
@@ -457,7 +848,8 @@ mod tests {
 
         sort_image_from_exif_data(
             Path::new("./data_4_tests/DSCN0025.jpg"),
-            &exif_data,
+            OsStr::new("DSCN0025.jpg"),
+            &folders,
             &configuration,
         )
         .unwrap();
@@ -465,6 +857,22 @@ mod tests {
             std::path::Path::new("./test_sort_image/2023 10/Null_Island/Nikkon/DSCN0025.jpg");
         assert!(copied_file.exists());
 
+        #[cfg(unix)]
+        {
+            *configuration.views_mut() = vec![views::VirtualView::Place];
+            sort_image_from_exif_data(
+                Path::new("./data_4_tests/DSCN0026.jpg"),
+                OsStr::new("DSCN0026.jpg"),
+                &folders,
+                &configuration,
+            )
+            .unwrap();
+            let link = std::path::Path::new("./test_sort_image/By-Place/Null_Island/2023 10/DSCN0026.jpg");
+            assert!(link.symlink_metadata().unwrap().file_type().is_symlink());
+            // the relative link resolves to the copied file
+            assert!(link.exists());
+        }
+
         // ensure we are in the good directory before cleanup
         assert_eq!(current_dir, std::env::current_dir().unwrap());
         // cleanup
@@ -482,8 +890,11 @@ mod tests {
 
         let source_dir = std::path::Path::new("data_4_tests");
         *configuration.unsorted_images_directory_mut() = PathBuf::from("test_sort_images/unsorted");
+        *configuration.not_images_directory_mut() = PathBuf::from("test_sort_images/not_images");
 
-        sort_images_in_dir(source_dir, &configuration).unwrap();
+        let scanned = scan_dir(source_dir, &configuration).unwrap();
+        let plan = SortPlan::build(std::iter::once(scanned.as_slice()), &configuration);
+        sort_scanned_files(&scanned, &plan, &configuration).unwrap();
         assert_eq!(
             4,
             fs::read_dir("test_sort_images/2008 10/Arezzo")
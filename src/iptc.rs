@@ -0,0 +1,171 @@
+//! # iptc
+//!
+//! Reading of the IPTC-IIM records of JPEG files : they are stored in the Photoshop image
+//! resource 0x0404 of the APP13 segment. Only the records of the application record (2)
+//! needed to date and place the images are kept.
+
+use crate::jpeg::{Segment, JPEG_APP13};
+const PHOTOSHOP_SIGNATURE: &[u8] = b"Photoshop 3.0\0";
+const RESOURCE_SIGNATURE: &[u8] = b"8BIM";
+const IPTC_RESOURCE_ID: u16 = 0x0404;
+const IIM_TAG_MARKER: u8 = 0x1C;
+const APPLICATION_RECORD: u8 = 2;
+
+// datasets of the application record
+const KEYWORDS: u8 = 25;
+const DATE_CREATED: u8 = 55;
+const TIME_CREATED: u8 = 60;
+const CITY: u8 = 90;
+const COUNTRY_NAME: u8 = 101;
+
+/// IPTC metadata of an image, values as written in the file
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct IptcData {
+    /// `CCYYMMDD`, month and day may be `00` when unknown
+    pub date_created: Option<String>,
+    /// `HHMMSS±HHMM`
+    pub time_created: Option<String>,
+    pub city: Option<String>,
+    pub country: Option<String>,
+    pub keywords: Vec<String>,
+}
+
+/// Find the IPTC records in the APP13 segments of a JPEG file
+pub fn find_iptc(segments: &[Segment]) -> Option<IptcData> {
+    segments
+        .iter()
+        .filter(|segment| segment.marker == JPEG_APP13)
+        .find_map(|segment| find_iptc_resource(&segment.payload))
+        .map(parse_iim)
+}
+
+/// Find the IPTC resource in the Photoshop image resources of an APP13 segment
+fn find_iptc_resource(app13: &[u8]) -> Option<&[u8]> {
+    let mut resources = app13.strip_prefix(PHOTOSHOP_SIGNATURE)?;
+    while resources.len() >= 12 && resources.starts_with(RESOURCE_SIGNATURE) {
+        let id = u16::from_be_bytes([resources[4], resources[5]]);
+        // Pascal string, padded to an even size
+        let name_length = resources[6] as usize;
+        let name_size = (name_length + 1 + 1) & !1;
+        let size_offset = 6 + name_size;
+        let size_bytes = resources.get(size_offset..size_offset + 4)?;
+        let size = u32::from_be_bytes(size_bytes.try_into().ok()?) as usize;
+        let data_offset = size_offset + 4;
+        let data = resources.get(data_offset..data_offset + size)?;
+        if id == IPTC_RESOURCE_ID {
+            return Some(data);
+        }
+        // data padded to an even size
+        resources = resources.get(data_offset + ((size + 1) & !1)..)?;
+    }
+    None
+}
+
+/// Parse the IIM datasets
+fn parse_iim(mut iim: &[u8]) -> IptcData {
+    let mut iptc_data = IptcData::default();
+    while iim.len() >= 5 && iim[0] == IIM_TAG_MARKER {
+        let (record, dataset) = (iim[1], iim[2]);
+        let mut size = u16::from_be_bytes([iim[3], iim[4]]) as usize;
+        let mut offset = 5;
+        // extended dataset : the size is written on the following bytes
+        if size & 0x8000 != 0 {
+            let size_length = size & 0x7FFF;
+            let Some(size_bytes) = iim.get(5..5 + size_length) else {
+                break;
+            };
+            size = size_bytes.iter().fold(0, |s, b| (s << 8) | *b as usize);
+            offset += size_length;
+        }
+        let Some(value) = iim.get(offset..offset + size) else {
+            log::warn!("IPTC dataset {}:{} truncated", record, dataset);
+            break;
+        };
+        if record == APPLICATION_RECORD {
+            let text = decode_text(value);
+            log::debug!("IPTC 2:{} = {}", dataset, text);
+            let text = Some(text).filter(|t| !t.is_empty());
+            match dataset {
+                KEYWORDS => iptc_data.keywords.extend(text),
+                DATE_CREATED => iptc_data.date_created = text,
+                TIME_CREATED => iptc_data.time_created = text,
+                CITY => iptc_data.city = text,
+                COUNTRY_NAME => iptc_data.country = text,
+                _ => (),
+            }
+        }
+        iim = &iim[offset + size..];
+    }
+    iptc_data
+}
+
+/// IIM text is UTF-8 in recent files, Latin-1 in older ones
+fn decode_text(value: &[u8]) -> String {
+    let text = match std::str::from_utf8(value) {
+        Ok(text) => text.to_string(),
+        Err(_) => value.iter().map(|b| *b as char).collect(),
+    };
+    text.trim_matches(|c: char| c.is_whitespace() || c == '\0').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jpeg;
+    use std::path::Path;
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    fn dataset(number: u8, value: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![IIM_TAG_MARKER, APPLICATION_RECORD, number];
+        bytes.extend((value.len() as u16).to_be_bytes());
+        bytes.extend(value);
+        bytes
+    }
+
+    #[test]
+    fn test_read_iptc() {
+        init();
+        let current_dir = std::env::current_dir().unwrap();
+        let mut iim = vec![IIM_TAG_MARKER, 1, 90, 0, 3, 0x1B, b'%', b'G'];
+        for (number, value) in [
+            (DATE_CREATED, &b"19650700"[..]),
+            (TIME_CREATED, b"143000+0100"),
+            (CITY, b"Z\xfcrich"),
+            (COUNTRY_NAME, "Suisse ".as_bytes()),
+            (KEYWORDS, b"wedding"),
+            (KEYWORDS, b"family"),
+        ] {
+            iim.extend(dataset(number, value));
+        }
+        let mut app13 = PHOTOSHOP_SIGNATURE.to_vec();
+        // a first resource with an odd size, then the IPTC one
+        app13.extend(b"8BIM\x03\xed\x00\x00\x00\x00\x00\x03abc\x00");
+        app13.extend(b"8BIM\x04\x04\x00\x00");
+        app13.extend((iim.len() as u32).to_be_bytes());
+        app13.extend(&iim);
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00, 0xFF, JPEG_APP13];
+        jpeg.extend(((app13.len() + 2) as u16).to_be_bytes());
+        jpeg.extend(&app13);
+        jpeg.extend([0xFF, 0xDA, 0x00, 0x02, 0xFF, 0xD9]);
+
+        let path = Path::new("./test_read_iptc.jpg");
+        std::fs::write(path, &jpeg).unwrap();
+        let read_iptc = |path: &Path| find_iptc(&jpeg::read_segments(path, &[JPEG_APP13]).unwrap().unwrap());
+        let iptc_data = read_iptc(path).unwrap();
+        assert_eq!(iptc_data.date_created.as_deref(), Some("19650700"));
+        assert_eq!(iptc_data.time_created.as_deref(), Some("143000+0100"));
+        // not valid UTF-8 : Latin-1
+        assert_eq!(iptc_data.city.as_deref(), Some("Zürich"));
+        assert_eq!(iptc_data.country.as_deref(), Some("Suisse"));
+        assert_eq!(iptc_data.keywords, vec!["wedding", "family"]);
+        assert_eq!(read_iptc(Path::new("data_4_tests/DSCN0025.jpg")), None);
+
+        // ensure we are in the good directory before cleanup
+        assert_eq!(current_dir, std::env::current_dir().unwrap());
+        // cleanup
+        std::fs::remove_file(path).unwrap();
+    }
+}
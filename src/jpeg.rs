@@ -0,0 +1,87 @@
+//! # jpeg
+//!
+//! Walk of the segments of a JPEG file up to the start of scan : the metadata (EXIF in APP1,
+//! XMP in APP1, IPTC in APP13) are all in the segments before the compressed data, so only
+//! these few kilobytes are read.
+
+use crate::archives;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+const JPEG_SOI: [u8; 2] = [0xFF, 0xD8];
+pub const JPEG_APP1: u8 = 0xE1;
+pub const JPEG_APP13: u8 = 0xED;
+// start of scan : the metadata segments are before it
+const JPEG_SOS: u8 = 0xDA;
+const JPEG_EOI: u8 = 0xD9;
+
+/// A segment of a JPEG file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub marker: u8,
+    /// Content of the segment, without its marker and length
+    pub payload: Vec<u8>,
+}
+
+/// Read the segments with one of the markers, in the order of the file. None if the file is
+/// not a JPEG file.
+pub fn read_segments(path: &Path, markers: &[u8]) -> std::io::Result<Option<Vec<Segment>>> {
+    log::trace!("read_segments of {:?}", path);
+    let mut file = std::io::BufReader::new(archives::open(path)?);
+    let mut soi = [0u8; 2];
+    if file.read_exact(&mut soi).is_err() || soi != JPEG_SOI {
+        return Ok(None);
+    }
+    let mut segments = Vec::new();
+    loop {
+        let mut header = [0u8; 4];
+        // a truncated file keeps the segments already read
+        if file.read_exact(&mut header[..2]).is_err() || header[0] != 0xFF {
+            break;
+        }
+        let marker = header[1];
+        if marker == JPEG_SOS || marker == JPEG_EOI {
+            break;
+        }
+        // fill bytes and markers without length
+        if marker == 0xFF || marker == 0x01 || (0xD0..=0xD7).contains(&marker) {
+            continue;
+        }
+        if file.read_exact(&mut header[2..]).is_err() {
+            break;
+        }
+        // at most 64 KiB : the length is written on two bytes
+        let length = u16::from_be_bytes([header[2], header[3]]) as usize;
+        let payload_length = length.saturating_sub(2);
+        if markers.contains(&marker) {
+            let mut payload = vec![0u8; payload_length];
+            if file.read_exact(&mut payload).is_err() {
+                break;
+            }
+            segments.push(Segment { marker, payload });
+        } else {
+            file.seek(SeekFrom::Current(payload_length as i64))?;
+        }
+    }
+    Ok(Some(segments))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    #[test]
+    fn test_read_segments() {
+        init();
+        let segments = read_segments(Path::new("data_4_tests/DSCN0025.jpg"), &[JPEG_APP1])
+            .unwrap()
+            .unwrap();
+        assert!(segments[0].payload.starts_with(b"Exif\0\0"));
+        assert!(segments.iter().all(|s| s.marker == JPEG_APP1));
+        assert_eq!(read_segments(Path::new("Cargo.toml"), &[JPEG_APP1]).unwrap(), None);
+    }
+}
@@ -0,0 +1,435 @@
+//! # layout
+//!
+//! Naming of the folders of the sorted tree, computed from the metadata of an image.
+
+use crate::events::Event;
+use crate::exif::{ExifData, MediaKind};
+use crate::global_configuration::GlobalConfiguration;
+use crate::place_finder;
+use crate::trips::Trip;
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+use regex::Regex;
+
+const UNKNOWN_DATE: &str = "Unknown Date";
+const UNKNOWN_PLACE: &str = "Unknown Place";
+const UNKNOWN_DEVICE: &str = "Unknown Device";
+// https://fr.wikipedia.org/wiki/Null_Island
+const NO_GPS_PLACE: &str = "Null_Island";
+const VIDEOS: &str = "Videos";
+const RAW: &str = "RAW";
+const PANORAMAS: &str = "Panoramas";
+const PORTRAIT: &str = "Portrait";
+const LANDSCAPE: &str = "Landscape";
+const SQUARE: &str = "Square";
+
+/// Directory Struct to ensure that only authorized characters in directories names.
+///
+/// # Examples
+/// ```
+/// let dir = Directory::parse(String::from("Cool @name"));
+/// assert_eq!(dir.get(), "Cool  name");
+///
+/// ```
+#[derive(Debug, PartialEq, Clone)]
+pub struct Directory(String);
+impl Directory {
+    pub fn parse(s: String) -> Directory {
+        log::trace!("parse {}", s);
+        let re = Regex::new(r"[^\w]").unwrap();
+        let clean_string = re.replace_all(&s, " ");
+        log::debug!("clean_string = {}", clean_string);
+        Self(clean_string.to_string())
+    }
+
+    pub fn get(&self) -> &String {
+        &self.0
+    }
+}
+
+/// Time zone of the calendar date used to choose the date folder
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum FolderTimezone {
+    /// Time zone of this computer
+    Local,
+    /// Time zone of the place of capture, as shown by the clock of the device
+    #[default]
+    Capture,
+    /// Coordinated Universal Time
+    #[value(name = "UTC", alias = "utc")]
+    Utc,
+}
+
+/// Granularity of the date folders
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum DateGranularity {
+    /// `2008`
+    Year,
+    /// `2008 10`
+    #[default]
+    Month,
+    /// `2008/10`
+    NestedMonth,
+    /// `2008/10/22`
+    Day,
+    /// ISO week : `2008/W43`
+    Week,
+    /// `2008/Q4`
+    Quarter,
+}
+
+/// Language of the month names added to the month folders (`2008/10 - Octobre`)
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum MonthNames {
+    En,
+    Fr,
+    De,
+    Es,
+    It,
+    Pt,
+    Nl,
+}
+
+impl MonthNames {
+    fn name(&self, month: u32) -> &'static str {
+        let names = match self {
+            MonthNames::En => [
+                "January", "February", "March", "April", "May", "June", "July", "August",
+                "September", "October", "November", "December",
+            ],
+            MonthNames::Fr => [
+                "Janvier", "Février", "Mars", "Avril", "Mai", "Juin", "Juillet", "Août",
+                "Septembre", "Octobre", "Novembre", "Décembre",
+            ],
+            MonthNames::De => [
+                "Januar", "Februar", "März", "April", "Mai", "Juni", "Juli", "August",
+                "September", "Oktober", "November", "Dezember",
+            ],
+            MonthNames::Es => [
+                "Enero", "Febrero", "Marzo", "Abril", "Mayo", "Junio", "Julio", "Agosto",
+                "Septiembre", "Octubre", "Noviembre", "Diciembre",
+            ],
+            MonthNames::It => [
+                "Gennaio", "Febbraio", "Marzo", "Aprile", "Maggio", "Giugno", "Luglio",
+                "Agosto", "Settembre", "Ottobre", "Novembre", "Dicembre",
+            ],
+            MonthNames::Pt => [
+                "Janeiro", "Fevereiro", "Março", "Abril", "Maio", "Junho", "Julho", "Agosto",
+                "Setembro", "Outubro", "Novembro", "Dezembro",
+            ],
+            MonthNames::Nl => [
+                "Januari", "Februari", "Maart", "April", "Mei", "Juni", "Juli", "Augustus",
+                "September", "Oktober", "November", "December",
+            ],
+        };
+        names[month as usize - 1]
+    }
+}
+
+/// Names of the folders where an image is sorted
+#[derive(Debug, PartialEq, Clone)]
+pub struct FolderNames {
+    /// Relative path, made of several folders for nested granularities
+    pub date: Directory,
+    pub place: Directory,
+    pub device: Directory,
+    /// Replaces the place in the sorted tree when grouping by events
+    pub event: Option<Directory>,
+    /// Last level of the sorted tree for the media kept apart (videos, RAW files, panoramas),
+    /// or the orientation of the images
+    pub media: Option<Directory>,
+}
+
+impl FolderNames {
+    pub fn from_exif_data(exif_data: &ExifData, configuration: &GlobalConfiguration) -> FolderNames {
+        FolderNames {
+            date: date_directory(
+                folder_time(exif_data, *configuration.folder_timezone()),
+                *configuration.date_granularity(),
+                *configuration.month_names(),
+            ),
+            place: place_directory(exif_data),
+            device: device_directory(exif_data),
+            event: None,
+            media: media_directory(exif_data, configuration),
+        }
+    }
+
+    /// Sort in the folder of an event, under the date of its beginning (an event is not
+    /// split between two date folders)
+    pub fn with_event(self, event: &Event, configuration: &GlobalConfiguration) -> FolderNames {
+        FolderNames {
+            date: date_directory(
+                Some(event.start),
+                *configuration.date_granularity(),
+                *configuration.month_names(),
+            ),
+            event: Some(event.folder.clone()),
+            ..self
+        }
+    }
+
+    /// Sort in the folder of a trip, instead of the date folder
+    pub fn with_trip(self, trip: &Trip) -> FolderNames {
+        FolderNames {
+            date: trip.folder.clone(),
+            ..self
+        }
+    }
+}
+
+/// Capture time in the time zone of the folders. When the offset of the capture time is
+/// unknown, it can't be converted and is used as is.
+pub fn folder_time(exif_data: &ExifData, folder_timezone: FolderTimezone) -> Option<NaiveDateTime> {
+    let converted = match folder_timezone {
+        FolderTimezone::Capture => None,
+        FolderTimezone::Utc => exif_data.capture_time_utc(),
+        FolderTimezone::Local => exif_data
+            .capture_time_utc()
+            .map(|utc| chrono::DateTime::<chrono::Local>::from(utc.and_utc()).naive_local()),
+    };
+    if converted.is_none() && folder_timezone != FolderTimezone::Capture && exif_data.capture_time.is_some() {
+        log::debug!("offset of the capture time unknown, using it without conversion");
+    }
+    converted.or(exif_data.capture_time)
+}
+
+fn media_directory(exif_data: &ExifData, configuration: &GlobalConfiguration) -> Option<Directory> {
+    match exif_data.media_kind {
+        MediaKind::Video if *configuration.videos_subdir() => Some(Directory(String::from(VIDEOS))),
+        MediaKind::Raw if *configuration.raw_subdir() => Some(Directory(String::from(RAW))),
+        MediaKind::Image => shape_directory(exif_data, configuration),
+        _ => None,
+    }
+}
+
+/// Parse a panorama ratio : long side over short side, more than 1 (a square is not a
+/// panorama)
+pub fn parse_panorama_ratio(value: &str) -> Result<f64, String> {
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|ratio| ratio.is_finite() && *ratio > 1.0)
+        .ok_or(format!("invalid ratio '{}', a number greater than 1 is expected", value))
+}
+
+/// Panoramas, then the orientation of the images of known size
+fn shape_directory(exif_data: &ExifData, configuration: &GlobalConfiguration) -> Option<Directory> {
+    let ratio = exif_data.aspect_ratio()?;
+    if configuration.panorama_ratio().is_some_and(|panorama_ratio| ratio >= panorama_ratio) {
+        return Some(Directory(String::from(PANORAMAS)));
+    }
+    if !*configuration.orientation_subdir() {
+        return None;
+    }
+    let (width, height) = exif_data.displayed_dimensions()?;
+    let orientation = match width.cmp(&height) {
+        std::cmp::Ordering::Less => PORTRAIT,
+        std::cmp::Ordering::Greater => LANDSCAPE,
+        std::cmp::Ordering::Equal => SQUARE,
+    };
+    Some(Directory(String::from(orientation)))
+}
+
+fn date_directory(
+    capture_time: Option<NaiveDateTime>,
+    granularity: DateGranularity,
+    month_names: Option<MonthNames>,
+) -> Directory {
+    let Some(capture_time) = capture_time else {
+        return Directory::parse(String::from(UNKNOWN_DATE));
+    };
+    let (year, month) = (capture_time.year(), capture_time.month());
+    let month = match month_names {
+        Some(names) => format!("{:02} - {}", month, names.name(month)),
+        None => format!("{:02}", month),
+    };
+    let components = match granularity {
+        DateGranularity::Year => vec![year.to_string()],
+        DateGranularity::Month => vec![format!("{} {}", year, month)],
+        DateGranularity::NestedMonth => vec![year.to_string(), month],
+        DateGranularity::Day => vec![year.to_string(), month, format!("{:02}", capture_time.day())],
+        DateGranularity::Week => {
+            // the ISO year of the first days of January may be the previous one
+            let week = capture_time.iso_week();
+            vec![week.year().to_string(), format!("W{:02}", week.week())]
+        }
+        DateGranularity::Quarter => vec![year.to_string(), format!("Q{}", (capture_time.month() - 1) / 3 + 1)],
+    };
+    // built from numbers and month names only, nothing to clean up
+    Directory(components.join("/"))
+}
+
+/// Folder of an event : `2008-10-12 Arezzo`, or `2008-10-12` when the place is unknown
+pub fn event_directory(start: NaiveDateTime, place: Option<&str>) -> Directory {
+    let day = start.format("%Y-%m-%d");
+    match place {
+        // the place is cleaned, the date has only digits and dashes
+        Some(place) => Directory(format!("{} {}", day, Directory::parse(place.to_string()).get())),
+        None => Directory(day.to_string()),
+    }
+}
+
+/// Folder of a trip : `2019-07 Trip - Lisbon, Porto`
+pub fn trip_directory(first_day: NaiveDate, places: &[&str]) -> Directory {
+    let month = first_day.format("%Y-%m");
+    if places.is_empty() {
+        return Directory(format!("{} Trip", month));
+    }
+    let places: Vec<String> = places
+        .iter()
+        .map(|place| Directory::parse(place.to_string()).get().clone())
+        .collect();
+    // the places are cleaned, the date has only digits and dashes
+    Directory(format!("{} Trip - {}", month, places.join(", ")))
+}
+
+fn place_directory(exif_data: &ExifData) -> Directory {
+    match exif_data.gps {
+        // (0, 0) is written by some devices when they have no GPS fix
+        Some(gps) if gps.latitude != 0.0 || gps.longitude != 0.0 => {
+            match place_finder::find_place(gps.latitude, gps.longitude) {
+                Some(place) => {
+                    log::debug!("Place from reverse geocoding = {}", place);
+                    Directory::parse(place)
+                }
+                None => {
+                    log::warn!("no place found");
+                    Directory::parse(String::from(UNKNOWN_PLACE))
+                }
+            }
+        }
+        // places written in the metadata (IPTC) of scans
+        _ => match exif_data.city.as_ref().or(exif_data.country.as_ref()) {
+            Some(place) => Directory::parse(place.clone()),
+            None => Directory::parse(String::from(NO_GPS_PLACE)),
+        },
+    }
+}
+
+fn device_directory(exif_data: &ExifData) -> Directory {
+    match &exif_data.model {
+        Some(model) => Directory::parse(model.clone()),
+        None => Directory::parse(String::from(UNKNOWN_DEVICE)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exif::GpsPosition;
+    use chrono::{FixedOffset, NaiveDate};
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    #[test]
+    fn test_folder_names() {
+        init();
+        let exif_data = ExifData {
+            capture_time: NaiveDate::from_ymd_opt(2008, 10, 22)
+                .unwrap()
+                .and_hms_opt(16, 29, 49),
+            gps: Some(GpsPosition {
+                latitude: 43.4667,
+                longitude: 11.8833,
+                altitude: None,
+            }),
+            model: Some(String::from("COOLPIX P6000")),
+            ..Default::default()
+        };
+        let configuration = GlobalConfiguration::new();
+        let folders = FolderNames::from_exif_data(&exif_data, &configuration);
+        assert_eq!(folders.date, Directory::parse(String::from("2008 10")));
+        assert_eq!(folders.place, Directory::parse(String::from("Arezzo")));
+        assert_eq!(folders.device, Directory::parse(String::from("COOLPIX P6000")));
+
+        let folders = FolderNames::from_exif_data(&ExifData::default(), &configuration);
+        assert_eq!(folders.date.get(), "Unknown Date");
+        assert_eq!(folders.place.get(), "Null_Island");
+        assert_eq!(folders.device.get(), "Unknown Device");
+
+        let scan = ExifData {
+            country: Some(String::from("Suisse")),
+            ..Default::default()
+        };
+        assert_eq!(FolderNames::from_exif_data(&scan, &configuration).place.get(), "Suisse");
+
+        let video = ExifData {
+            media_kind: MediaKind::Video,
+            ..exif_data
+        };
+        assert_eq!(FolderNames::from_exif_data(&video, &configuration).media, None);
+        let mut configuration = configuration;
+        *configuration.videos_subdir_mut() = true;
+        assert_eq!(FolderNames::from_exif_data(&video, &configuration).media.unwrap().get(), "Videos");
+        let raw = ExifData {
+            media_kind: MediaKind::Raw,
+            ..video
+        };
+        assert_eq!(FolderNames::from_exif_data(&raw, &configuration).media, None);
+
+        // turned by a quarter : displayed in portrait
+        let image = ExifData {
+            dimensions: Some((4224, 3168)),
+            orientation: Some(6),
+            ..Default::default()
+        };
+        assert_eq!(FolderNames::from_exif_data(&image, &configuration).media, None);
+        *configuration.orientation_subdir_mut() = true;
+        assert_eq!(FolderNames::from_exif_data(&image, &configuration).media.unwrap().get(), "Portrait");
+        let panorama = ExifData {
+            dimensions: Some((12000, 3000)),
+            ..Default::default()
+        };
+        assert_eq!(FolderNames::from_exif_data(&panorama, &configuration).media.unwrap().get(), "Landscape");
+        *configuration.panorama_ratio_mut() = Some(parse_panorama_ratio("2.5").unwrap());
+        assert_eq!(FolderNames::from_exif_data(&panorama, &configuration).media.unwrap().get(), "Panoramas");
+        for invalid in ["1", "0.5", "-3", "NaN", "inf", "wide"] {
+            assert!(parse_panorama_ratio(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_folder_time() {
+        init();
+        // just after midnight in Tokyo on new year's day
+        let capture_time = NaiveDate::from_ymd_opt(2020, 1, 1).unwrap().and_hms_opt(0, 10, 0);
+        let mut exif_data = ExifData {
+            capture_time,
+            ..Default::default()
+        };
+        assert_eq!(folder_time(&exif_data, FolderTimezone::Utc), capture_time);
+        exif_data.capture_offset = FixedOffset::east_opt(9 * 3600);
+        assert_eq!(folder_time(&exif_data, FolderTimezone::Capture), capture_time);
+        assert_eq!(
+            folder_time(&exif_data, FolderTimezone::Utc),
+            NaiveDate::from_ymd_opt(2019, 12, 31).unwrap().and_hms_opt(15, 10, 0)
+        );
+        assert_eq!(
+            date_directory(folder_time(&exif_data, FolderTimezone::Utc), DateGranularity::Month, None).get(),
+            "2019 12"
+        );
+    }
+
+    #[test]
+    fn test_date_directory() {
+        init();
+        let at = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap().and_hms_opt(16, 43, 21);
+        for (granularity, names, expected) in [
+            (DateGranularity::Year, None, "2008"),
+            (DateGranularity::Month, None, "2008 10"),
+            (DateGranularity::Month, Some(MonthNames::En), "2008 10 - October"),
+            (DateGranularity::NestedMonth, Some(MonthNames::Fr), "2008/10 - Octobre"),
+            (DateGranularity::Day, None, "2008/10/22"),
+            (DateGranularity::Week, None, "2008/W43"),
+            (DateGranularity::Quarter, None, "2008/Q4"),
+        ] {
+            assert_eq!(date_directory(at(2008, 10, 22), granularity, names).get(), expected);
+        }
+        // 2010-01-01 is in the last ISO week of 2009
+        assert_eq!(date_directory(at(2010, 1, 1), DateGranularity::Week, None).get(), "2009/W53");
+        assert_eq!(date_directory(None, DateGranularity::Day, None).get(), "Unknown Date");
+    }
+}
@@ -0,0 +1,133 @@
+//! # live_photos
+//!
+//! Stills with a short video :
+//! - Apple Live Photos are a HEIC (or JPEG) and a MOV file sharing a content identifier,
+//!   written in the Apple maker note of the still and in the QuickTime keys of the video,
+//! - Google Motion Photos (`MVIMG_*.jpg`, `PXL_*.MP.jpg`) are JPEG files with the video
+//!   appended, its length written in their XMP packet.
+
+use crate::raw::ByteOrder;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::path::Path;
+
+const APPLE_MAKER_NOTE: &[u8] = b"Apple iOS\0";
+// signature, version, then the byte order of the IFD
+const APPLE_IFD_START: usize = 14;
+const CONTENT_IDENTIFIER_TAG: u16 = 0x0011;
+const TIFF_ASCII: u16 = 2;
+// brands of the MP4 videos appended to the Motion Photos
+const VIDEO_BRANDS: [&[u8]; 4] = [b"mp4", b"iso", b"avc", b"qt "];
+
+static MOTION_PHOTO_NAME_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)^(MVIMG_.*|.*\.MP)\.jpe?g$").unwrap());
+
+/// A Google Motion Photo
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MotionPhoto {
+    /// Length of the video at the end of the file, when written in the XMP packet
+    pub video_length: Option<u64>,
+}
+
+/// Motion Photos are flagged in their XMP packet (the length may be missing), or recognized
+/// by their name
+pub fn find_motion_photo(path: &Path, xmp_flag: bool, xmp_video_length: Option<u64>) -> Option<MotionPhoto> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    if xmp_flag || xmp_video_length.is_some() || MOTION_PHOTO_NAME_REGEX.is_match(&name) {
+        return Some(MotionPhoto {
+            video_length: xmp_video_length.filter(|length| *length > 0),
+        });
+    }
+    None
+}
+
+/// Read the ContentIdentifier (tag 0x0011) of an Apple maker note : an IFD whose offsets are
+/// relative to the beginning of the maker note
+pub fn apple_content_identifier(maker_note: &[u8]) -> Option<String> {
+    if !maker_note.starts_with(APPLE_MAKER_NOTE) {
+        return None;
+    }
+    let order = ByteOrder::of(maker_note.get(APPLE_IFD_START - 2..)?)?;
+    let count = order.u16(maker_note, APPLE_IFD_START)? as usize;
+    for i in 0..count {
+        let entry = APPLE_IFD_START + 2 + i * 12;
+        if order.u16(maker_note, entry)? != CONTENT_IDENTIFIER_TAG || order.u16(maker_note, entry + 2)? != TIFF_ASCII {
+            continue;
+        }
+        let length = order.u32(maker_note, entry + 4)? as usize;
+        let start = match length {
+            0..=4 => entry + 8,
+            _ => order.u32(maker_note, entry + 8)? as usize,
+        };
+        let value = String::from_utf8_lossy(maker_note.get(start..start + length)?);
+        let value = value.trim_end_matches('\0').trim();
+        log::debug!("Apple ContentIdentifier = {}", value);
+        return Some(value.to_string()).filter(|v| !v.is_empty());
+    }
+    None
+}
+
+/// Find the video appended to a Motion Photo : at the length written in XMP from the end,
+/// else at the first MP4 file type box after the beginning of the still
+pub fn find_motion_video<'a>(still: &'a [u8], motion_photo: &MotionPhoto) -> Option<&'a [u8]> {
+    let is_video_start = |start: usize| {
+        still.get(start + 4..start + 8) == Some(b"ftyp")
+            && still
+                .get(start + 8..start + 11)
+                .is_some_and(|brand| VIDEO_BRANDS.contains(&brand))
+    };
+    let from_length = motion_photo
+        .video_length
+        .and_then(|length| still.len().checked_sub(length as usize))
+        .filter(|start| is_video_start(*start));
+    let start = from_length.or_else(|| {
+        still
+            .windows(4)
+            .enumerate()
+            .skip(4)
+            .filter(|(_, window)| *window == b"ftyp")
+            .map(|(index, _)| index - 4)
+            .find(|start| is_video_start(*start))
+    })?;
+    Some(&still[start..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    #[test]
+    fn test_live_photos() {
+        init();
+        let uuid = "4B3E4E5A-6C2D-4F2B-9C1E-0A1B2C3D4E5F";
+        let mut maker_note = b"Apple iOS\0\x00\x01MM\x00\x02".to_vec();
+        // another tag, then the content identifier at offset 40
+        maker_note.extend(b"\x00\x01\x00\x09\x00\x00\x00\x01\x00\x00\x00\x0E");
+        maker_note.extend(b"\x00\x11\x00\x02\x00\x00\x00\x25\x00\x00\x00\x28");
+        maker_note.extend(uuid.as_bytes());
+        maker_note.push(0);
+        assert_eq!(apple_content_identifier(&maker_note).as_deref(), Some(uuid));
+        assert_eq!(apple_content_identifier(b"Nikon\0\x02\x10"), None);
+
+        assert!(find_motion_photo(Path::new("PXL_20210304_101112345.MP.jpg"), false, None).is_some());
+        assert!(find_motion_photo(Path::new("MVIMG_20190812_143055.jpg"), false, None).is_some());
+        assert!(find_motion_photo(Path::new("IMG_20190812_143055.jpg"), false, None).is_none());
+
+        let video = b"\x00\x00\x00\x18ftypmp42\x00\x00\x00\x00mp42isom\x00\x00\x00\x08mdat";
+        let still = [&b"\xff\xd8 jpeg ftyp \xff\xd9"[..], &video[..]].concat();
+        let with_length = MotionPhoto {
+            video_length: Some(video.len() as u64),
+        };
+        assert_eq!(find_motion_video(&still, &with_length), Some(&video[..]));
+        // a wrong length : searched
+        let wrong_length = MotionPhoto {
+            video_length: Some(3),
+        };
+        assert_eq!(find_motion_video(&still, &wrong_length), Some(&video[..]));
+        assert_eq!(find_motion_video(b"\xff\xd8\xff\xd9", &wrong_length), None);
+    }
+}
@@ -0,0 +1,140 @@
+//! # lock
+//!
+//! Advisory lock (flock) taken on a lockfile at the root of the destination directory for the
+//! duration of a run, so that two runs (e.g. a cron job and a manual run) never write into
+//! the same tree at the same time.
+
+use crate::directories::LOCK_FILENAME;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, Write};
+use std::path::Path;
+
+#[derive(thiserror::Error, Debug)]
+pub enum LockError {
+    #[error("Destination {path} is already used by another images_sort run (PID {pid})")]
+    Held { path: String, pid: String },
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+}
+
+/// The lock is released when this struct is dropped (or when the process ends)
+#[derive(Debug)]
+pub struct DestinationLock {
+    _file: File,
+}
+
+impl DestinationLock {
+    /// Take the lock of `dest_dir`. If it is held by another run, fail with the PID of
+    /// the holder or, when `wait` is true, block until it is released.
+    pub fn acquire(dest_dir: &Path, wait: bool) -> Result<DestinationLock, LockError> {
+        let path = dest_dir.join(LOCK_FILENAME);
+        log::trace!("DestinationLock::acquire {:?}", path);
+        // no truncate : the file may contain the PID of the current holder
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        if !try_lock(&file)? {
+            let pid = read_holder_pid(&mut file);
+            if !wait {
+                return Err(LockError::Held {
+                    path: dest_dir.display().to_string(),
+                    pid,
+                });
+            }
+            log::info!("Waiting for the run with PID {} to release {:?}", pid, path);
+            println!("Waiting for another images_sort run (PID {}) to finish ...", pid);
+            lock(&file)?;
+        }
+
+        file.set_len(0)?;
+        file.rewind()?;
+        writeln!(file, "{}", std::process::id())?;
+        file.flush()?;
+        log::debug!("lock {:?} acquired", path);
+
+        Ok(DestinationLock { _file: file })
+    }
+}
+
+fn read_holder_pid(file: &mut File) -> String {
+    let mut content = String::new();
+    match file.rewind().and_then(|_| file.read_to_string(&mut content)) {
+        Ok(_) if !content.trim().is_empty() => content.trim().to_string(),
+        _ => String::from("unknown"),
+    }
+}
+
+/// Try to take an exclusive lock without blocking. Return false if it is held by someone else.
+#[cfg(unix)]
+fn try_lock(file: &File) -> std::io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+    // SAFETY: the file descriptor is valid as long as `file` is alive
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let e = std::io::Error::last_os_error();
+    if e.kind() == std::io::ErrorKind::WouldBlock {
+        Ok(false)
+    } else {
+        Err(e)
+    }
+}
+
+#[cfg(unix)]
+fn lock(file: &File) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    // SAFETY: the file descriptor is valid as long as `file` is alive
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(unix))]
+fn try_lock(_file: &File) -> std::io::Result<bool> {
+    log::warn!("Locking of the destination is not supported on this platform");
+    Ok(true)
+}
+
+#[cfg(not(unix))]
+fn lock(_file: &File) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_destination_lock() {
+        init();
+        let current_dir = std::env::current_dir().unwrap();
+        let dir = Path::new("./test_lock");
+        std::fs::create_dir(dir).unwrap();
+
+        let lock = DestinationLock::acquire(dir, false).unwrap();
+        // flock locks are per open file description : a second acquisition fails, even from
+        // the same process
+        match DestinationLock::acquire(dir, false) {
+            Err(LockError::Held { pid, .. }) => assert_eq!(pid, std::process::id().to_string()),
+            r => panic!("Unexpected result {:?}", r),
+        }
+        drop(lock);
+        assert!(DestinationLock::acquire(dir, false).is_ok());
+
+        // ensure we are in the good directory before cleanup
+        assert_eq!(current_dir, std::env::current_dir().unwrap());
+        // cleanup
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
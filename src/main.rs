@@ -1,15 +1,38 @@
 use clap::Parser;
 use indicatif::{ProgressBar, ProgressStyle};
 
-use crate::{global_configuration::GlobalConfiguration, performance::PerformanceMetrics, reporting::Reporting};
+use crate::{exif::DateSource, global_configuration::GlobalConfiguration, layout::{DateGranularity, FolderTimezone, MonthNames}, manifest::Manifest, performance::PerformanceMetrics, reporting::Reporting, views::VirtualView};
 
+mod archives;
+mod calendar;
+mod classifier;
+mod clock_offsets;
+mod companions;
+mod dimensions;
 mod directories;
+mod events;
 mod exif;
 mod global_configuration;
 mod images_manager;
+mod iptc;
+mod jpeg;
+mod layout;
+mod live_photos;
+mod lock;
+mod manifest;
 mod performance;
 mod place_finder;
+mod plan;
+mod preflight;
+mod quicktime;
+mod raw;
+mod rename;
 mod reporting;
+mod shutdown;
+mod takeout;
+mod trips;
+mod views;
+mod xmp;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
@@ -23,6 +46,94 @@ struct Args {
     /// Use Device (Camera Model) as a key to sort
     #[arg(short, long)]
     use_device: Option<bool>,
+    /// Additional views of the library, made of relative symlinks to the sorted images (comma separated)
+    #[arg(long, value_enum, value_delimiter = ',')]
+    views: Vec<VirtualView>,
+    /// Start even if the preflight check finds that the destination lacks free space
+    #[arg(long)]
+    force: bool,
+    /// Wait for another run using the same destination to finish, instead of failing
+    #[arg(long)]
+    wait: bool,
+    /// Sources of the capture date, tried in this order (comma separated). mtime is opt-in
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = DateSource::DEFAULT_CHAIN)]
+    date_sources: Vec<DateSource>,
+    /// Additional regex to find a date in file names, with named groups year, month, day
+    /// and optionally hour, minute, second (can be repeated)
+    #[arg(long, value_parser = regex::Regex::new)]
+    filename_date_pattern: Vec<regex::Regex>,
+    /// Which of EXIF or XMP (embedded or sidecar) wins when both have a date or a GPS position
+    #[arg(long, value_enum, default_value_t = xmp::XmpPrecedence::Xmp)]
+    xmp_precedence: xmp::XmpPrecedence,
+    /// Sort the videos in a Videos folder, under the place (or device) folder of their date
+    #[arg(long)]
+    videos_subdir: bool,
+    /// Sort the camera RAW files in a RAW folder, apart from the processed images
+    #[arg(long)]
+    raw_subdir: bool,
+    /// Write the video embedded in the Google Motion Photos next to them, as an MP4 file
+    #[arg(long)]
+    extract_motion_video: bool,
+    /// Sort the files of the ZIP and TAR archives (.zip, .tar, .tgz, .tar.gz), read in place,
+    /// instead of copying the archives in Not_Images
+    #[arg(long)]
+    expand_archives: bool,
+    /// Rename the sorted files with this template, e.g. `{date:%Y%m%d_%H%M%S}_{device}_{seq}.{ext}`
+    /// (placeholders : date, device, seq, name, ext)
+    #[arg(long)]
+    rename: Option<rename::RenameTemplate>,
+    /// Case of the extensions of the sorted files
+    #[arg(long, value_enum, default_value_t = rename::ExtensionCase::Keep)]
+    extension_case: rename::ExtensionCase,
+    /// Copy the screenshots, messenger images, scans and edited exports in their own folders
+    /// (comma separated `class` or `class=folder`, e.g. `screenshot,messenger=WhatsApp`). The
+    /// first matching class of the list wins
+    #[arg(long, value_parser = classifier::parse_bucket, value_delimiter = ',')]
+    buckets: Vec<classifier::Bucket>,
+    /// Sort the images in Portrait, Landscape or Square folders, by their displayed orientation
+    #[arg(long)]
+    orientation_subdir: bool,
+    /// Sort the images whose long side is at least this number of times their short side in
+    /// a Panoramas folder
+    #[arg(long, value_parser = layout::parse_panorama_ratio)]
+    panorama_ratio: Option<f64>,
+    /// Skip the images smaller than this number of megapixels (thumbnails)
+    #[arg(long)]
+    min_megapixels: Option<f64>,
+    /// Time zone of the calendar date used to choose the date folder of an image
+    #[arg(long, value_enum, default_value_t = FolderTimezone::Capture)]
+    folder_timezone: FolderTimezone,
+    /// Granularity of the date folders
+    #[arg(long, value_enum, default_value_t = DateGranularity::Month)]
+    date_granularity: DateGranularity,
+    /// Add the month names, in this language, to the month folders
+    #[arg(long, value_enum)]
+    month_names: Option<MonthNames>,
+    /// Group the photos in events, separated by gaps of at least this number of hours, and
+    /// sort them in event folders (`2008-10-12 Arezzo`) instead of place folders
+    #[arg(long, value_parser = events::parse_gap_hours)]
+    event_gap_hours: Option<f64>,
+    /// Home position (latitude,longitude in decimal degrees) : photos taken farther than
+    /// --home-radius-km are grouped in trip folders (`2019-07 Trip - Lisbon, Porto`)
+    #[arg(long, value_parser = trips::parse_position, allow_hyphen_values = true)]
+    home: Option<exif::GpsPosition>,
+    /// Radius around the home position, in kilometers
+    #[arg(long, default_value_t = 50.0, requires = "home", value_parser = trips::parse_radius_km)]
+    home_radius_km: f64,
+    /// Calendar of named events (.ics, or CSV name,start,end) : photos taken during an event
+    /// are sorted in its folder (`2023-12-24 Christmas`). The shortest event wins overlaps
+    #[arg(long)]
+    calendar: Option<std::path::PathBuf>,
+    /// CSV table of corrections of device clocks (make,model,serial,from,to,offset)
+    #[arg(long)]
+    clock_offsets: Option<std::path::PathBuf>,
+    /// Only estimate the clock offset of this device (model or serial number) by comparing its
+    /// photos to the ones of --reference-device, then exit
+    #[arg(long, requires = "reference_device")]
+    estimate_offset: Option<String>,
+    /// Device with a correct clock (e.g. a phone), used by --estimate-offset
+    #[arg(long)]
+    reference_device: Option<String>,
 }
 
 fn main() {
@@ -43,9 +154,58 @@ fn main() {
     // }
     *configuration.dest_directory_mut() = std::path::PathBuf::from(&args.dest_dir);
 
+    check_directories_overlap(&configuration);
+
+    // held until the end of the run
+    let _lock = match lock::DestinationLock::acquire(configuration.dest_directory_as_path(), args.wait) {
+        Ok(lock) => lock,
+        Err(e @ lock::LockError::Held { .. }) => {
+            log::error!("{:?}, ending execution", e);
+            eprintln!("Error : {}, ending execution (use --wait to wait for it)", e);
+            std::process::exit(1)
+        }
+        Err(e) => {
+            log::error!("Error {:?} when locking the destination, ending execution", e);
+            eprintln!("Error : {} when locking the destination, ending execution", e);
+            std::process::exit(1)
+        }
+    };
+
     if let Some(d) = args.use_device {
         *configuration.use_device_mut() = d;
     }
+    *configuration.views_mut() = args.views;
+    *configuration.date_sources_mut() = args.date_sources;
+    *configuration.xmp_precedence_mut() = args.xmp_precedence;
+    *configuration.videos_subdir_mut() = args.videos_subdir;
+    *configuration.raw_subdir_mut() = args.raw_subdir;
+    *configuration.extract_motion_video_mut() = args.extract_motion_video;
+    *configuration.expand_archives_mut() = args.expand_archives;
+    *configuration.rename_mut() = args.rename;
+    *configuration.extension_case_mut() = args.extension_case;
+    *configuration.buckets_mut() = args.buckets;
+    *configuration.orientation_subdir_mut() = args.orientation_subdir;
+    *configuration.panorama_ratio_mut() = args.panorama_ratio;
+    *configuration.min_megapixels_mut() = args.min_megapixels;
+    *configuration.folder_timezone_mut() = args.folder_timezone;
+    *configuration.date_granularity_mut() = args.date_granularity;
+    *configuration.month_names_mut() = args.month_names;
+    *configuration.home_mut() = args.home;
+    *configuration.home_radius_km_mut() = args.home_radius_km;
+    *configuration.event_gap_mut() = args
+        .event_gap_hours
+        .map(|hours| chrono::Duration::seconds((hours * 3600.0) as i64));
+    for pattern in &args.filename_date_pattern {
+        let names: Vec<&str> = pattern.capture_names().flatten().collect();
+        if !["year", "month", "day"].iter().all(|n| names.contains(n)) {
+            eprintln!(
+                "Error : the filename date pattern {} must have the named groups year, month and day",
+                pattern
+            );
+            std::process::exit(1)
+        }
+    }
+    *configuration.filename_date_patterns_mut() = args.filename_date_pattern;
 
     let mut all_directories =
         match directories::get_subdirectories_recursive(configuration.source_directory_as_path()) {
@@ -67,6 +227,60 @@ fn main() {
 
     all_directories.push(configuration.source_directory().clone());
 
+    if let (Some(device), Some(reference)) = (&args.estimate_offset, &args.reference_device) {
+        estimate_clock_offset(&all_directories, device, reference, &configuration);
+        return;
+    }
+
+    if let Some(path) = &args.calendar {
+        match calendar::read_calendar(path) {
+            Ok(events) => {
+                for (first, second) in calendar::find_overlaps(&events) {
+                    println!("Calendar events {} and {} overlap : the shortest one is used for the photos taken during both", first.name, second.name);
+                }
+                *configuration.calendar_mut() = events;
+            }
+            Err(e) => {
+                log::error!("Error {:?} when reading {:?}, ending execution", e, path);
+                eprintln!("Error : {} when reading {}, ending execution", e, path.display());
+                std::process::exit(1)
+            }
+        }
+    }
+
+    if let Some(path) = &args.clock_offsets {
+        match clock_offsets::read_table(path) {
+            Ok(corrections) => *configuration.clock_corrections_mut() = corrections,
+            Err(e) => {
+                log::error!("Error {:?} when reading {:?}, ending execution", e, path);
+                eprintln!("Error : {} when reading {}, ending execution", e, path.display());
+                std::process::exit(1)
+            }
+        }
+    }
+
+    println!("Checking destination ...");
+    let source_size = match directories::sum_files_size(&all_directories, args.expand_archives) {
+        Ok(size) => size,
+        Err(e) => {
+            log::error!("Error {:?} when computing the size of source files", e);
+            eprintln!("Error : {} when computing the size of source files", e);
+            std::process::exit(1)
+        }
+    };
+    match preflight::check_destination(configuration.dest_directory_as_path(), source_size) {
+        Ok(()) => (),
+        Err(e @ preflight::PreflightError::NotEnoughSpace { .. }) if args.force => {
+            log::warn!("Preflight check failed : {:?}, continuing (--force)", e);
+            eprintln!("Warning : {} -- continuing anyway (--force)", e);
+        }
+        Err(e) => {
+            log::error!("Preflight check failed : {:?}, ending execution", e);
+            eprintln!("Error : {}, ending execution (use --force to ignore free space)", e);
+            std::process::exit(1)
+        }
+    }
+
     println!("Create target directory ...");
 
     let sorted_dir =
@@ -93,25 +307,35 @@ fn main() {
             .unwrap();
     *configuration.not_images_directory_mut() = not_images_dir;
 
-    Reporting::start_timer();
-    println!("Sorting images ...");
+    let suspicious_dates_dir =
+        directories::create_suspicious_dates_dir(configuration.sorted_images_directory_as_path())
+            .unwrap();
+    *configuration.suspicious_dates_directory_mut() = suspicious_dates_dir;
 
-    let bar = ProgressBar::new(all_directories.len().try_into().unwrap());
-    bar.set_style(
-        ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({percent}%) {msg}")
-            .unwrap()
-            .progress_chars("█▓▒░"),
-    );
-    bar.set_message("Starting...");
+    let sorted_dir = configuration.sorted_images_directory_as_path().to_path_buf();
+    for bucket in configuration.buckets_mut() {
+        bucket.directory = directories::create_subdir(&sorted_dir, &bucket.directory).unwrap();
+    }
+
+    if let Err(e) = Manifest::create(configuration.sorted_images_directory_as_path()) {
+        log::error!("Error {:?} when creating the manifest", e);
+        eprintln!("Warning : the manifest can't be created ({})", e);
+    }
 
+    Reporting::start_timer();
+    shutdown::install_handler();
+    println!("Scanning images ...");
+    let bar = directories_progress_bar(all_directories.len());
+    let mut scanned_directories = Vec::new();
+    let mut scanned_archives = Vec::new();
     for dir in &all_directories {
-        bar.set_message(format!("Processing {}", dir.display()));
+        if shutdown::is_interrupted() {
+            break;
+        }
+        bar.set_message(format!("Scanning {}", dir.display()));
         log::debug!("{:?}", dir);
-        match images_manager::sort_images_in_dir(
-            dir,
-            &configuration,
-        ) {
+        match images_manager::scan_dir(dir, &configuration) {
+            Ok(files) => scanned_directories.push(files),
             Err(e) => {
                 log::error!(
                     "Unexpected error {:?} when processing images in {:?}.",
@@ -123,13 +347,72 @@ fn main() {
                     e, dir
                 )
             }
+        }
+        if *configuration.expand_archives() {
+            for archive in images_manager::find_archives(dir).unwrap_or_default() {
+                bar.set_message(format!("Reading {}", archive.display()));
+                match images_manager::scan_archive(&archive, &configuration) {
+                    Ok(scanned) => scanned_archives.push(scanned),
+                    // copied as is, as without --expand-archives
+                    Err(e) => {
+                        log::error!("Error {:?} when reading the archive {:?}", e, archive);
+                        eprintln!("Error {} when reading the archive {:?}, copied as is", e, archive);
+                        let exif_data = exif::get_exif_data(&archive, &configuration);
+                        scanned_directories.push(vec![plan::ScannedFile { path: archive, exif_data }]);
+                    }
+                }
+            }
+        }
+        bar.inc(1);
+    }
+    bar.finish_and_clear();
+
+    let archived_directories = scanned_archives.iter().flat_map(|a| a.directories.iter().map(Vec::as_slice));
+    let plan = plan::SortPlan::build(scanned_directories.iter().map(Vec::as_slice).chain(archived_directories), &configuration);
+    Reporting::set_events_count(plan.events_count() as u32);
+    Reporting::set_trips_count(plan.trips_count() as u32);
+    Reporting::set_calendar_events_count(plan.calendar_events_count() as u32);
+
+    println!("Sorting images ...");
+    let bar = directories_progress_bar(scanned_directories.len() + scanned_archives.len());
+    for files in &scanned_directories {
+        if shutdown::is_interrupted() {
+            break;
+        }
+        if let Some(dir) = files.first().and_then(|f| f.path.parent()) {
+            bar.set_message(format!("Processing {}", dir.display()));
+        }
+        match images_manager::sort_scanned_files(files, &plan, &configuration) {
+            Err(e) => {
+                log::error!("Unexpected error {:?} when sorting images.", e);
+                eprintln!("Unexpected error {} when sorting images.", e)
+            }
+            // an interrupted directory is not fully processed
+            _ if shutdown::is_interrupted() => (),
             _ => {
                 Reporting::directory_processed();
             }
         }
         bar.inc(1);
     }
-    bar.finish_with_message("All directories processed");
+    for archive in &scanned_archives {
+        if shutdown::is_interrupted() {
+            break;
+        }
+        bar.set_message(format!("Processing {}", archive.path.display()));
+        if let Err(e) = images_manager::sort_scanned_archive(archive, &plan, &configuration) {
+            log::error!("Unexpected error {:?} when sorting the archive {:?}.", e, archive.path);
+            eprintln!("Unexpected error {} when sorting the archive {:?}.", e, archive.path)
+        }
+        bar.inc(1);
+    }
+    if shutdown::is_interrupted() {
+        bar.abandon_with_message("Interrupted");
+    } else {
+        bar.finish_with_message("All directories processed");
+    }
+
+    Manifest::flush();
 
     // Count files for integrity verification
     println!("Counting files for verification...");
@@ -154,3 +437,113 @@ fn main() {
     Reporting::print_reporting();
     PerformanceMetrics::print_report();
 }
+
+fn directories_progress_bar(len: usize) -> ProgressBar {
+    let bar = ProgressBar::new(len.try_into().unwrap());
+    bar.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({percent}%) {msg}")
+            .unwrap()
+            .progress_chars("█▓▒░"),
+    );
+    bar.set_message("Starting...");
+    bar
+}
+
+/// Print the estimated clock offset of a device compared to a reference device, read from
+/// the capture times recorded by both devices in the source directories
+fn estimate_clock_offset(
+    directories: &[std::path::PathBuf],
+    device: &str,
+    reference: &str,
+    configuration: &GlobalConfiguration,
+) {
+    use rayon::prelude::*;
+    println!("Reading the capture times of {} and {} ...", device, reference);
+    let is_device = |exif_data: &exif::ExifData, name: &str| {
+        [&exif_data.model, &exif_data.serial]
+            .iter()
+            .any(|v| v.as_ref().is_some_and(|v| v.eq_ignore_ascii_case(name)))
+    };
+    let exif_data: Vec<exif::ExifData> = directories
+        .iter()
+        .filter_map(|dir| directories::get_files_from_dir(dir).ok())
+        .flatten()
+        .collect::<Vec<_>>()
+        .par_iter()
+        .filter_map(|file| exif::get_exif_data(file, configuration).ok())
+        .filter(|e| matches!(e.date_source, Some(DateSource::Exif | DateSource::ExifDatetime)))
+        .collect();
+    let times = |name: &str| -> Vec<chrono::NaiveDateTime> {
+        exif_data
+            .iter()
+            .filter(|e| is_device(e, name))
+            .filter_map(|e| e.capture_time)
+            .collect()
+    };
+    let (device_times, reference_times) = (times(device), times(reference));
+    println!(
+        "{} photos of {}, {} photos of {}",
+        device_times.len(),
+        device,
+        reference_times.len(),
+        reference
+    );
+    match clock_offsets::estimate_offset(&device_times, &reference_times) {
+        Some(estimate) => {
+            let offset = clock_offsets::format_offset(estimate.offset);
+            println!(
+                "Estimated correction of {} : {} ({} pairs of photos taken at the same time)",
+                device, offset, estimate.pairs
+            );
+            // in the column of the model or of the serial number, as given
+            let is_model = exif_data
+                .iter()
+                .any(|e| e.model.as_ref().is_some_and(|m| m.eq_ignore_ascii_case(device)));
+            if is_model {
+                println!("Line of the clock offsets table : ,{},,,,{}", device, offset);
+            } else {
+                println!("Line of the clock offsets table : ,,{},,,{}", device, offset);
+            }
+        }
+        None => {
+            eprintln!("Error : no photos to compare, the offset can't be estimated");
+            std::process::exit(1)
+        }
+    }
+}
+
+/// Refuse to sort a source directory that is (inside) a library created by images_sort.
+/// A destination inside the source directory is accepted : the sorted images directories
+/// carry a marker file and are skipped when walking the source directory.
+fn check_directories_overlap(configuration: &GlobalConfiguration) {
+    let source = canonicalize_or_exit(configuration.source_directory_as_path());
+    let dest = canonicalize_or_exit(configuration.dest_directory_as_path());
+    log::debug!("canonicalized source {:?} and destination {:?}", source, dest);
+
+    if let Some(library) = directories::find_enclosing_sorted_images_dir(&source) {
+        log::error!("Source directory {:?} is inside the sorted images directory {:?}", source, library);
+        eprintln!(
+            "Error : source directory {} is inside {}, a directory created by images_sort. Ending execution",
+            source.display(),
+            library.display()
+        );
+        std::process::exit(1)
+    }
+
+    if dest.starts_with(&source) {
+        log::info!("Destination {:?} is inside source {:?}, sorted images directories will be skipped", dest, source);
+        println!("Destination is inside the source directory : directories created by images_sort will be skipped");
+    }
+}
+
+fn canonicalize_or_exit(path: &std::path::Path) -> std::path::PathBuf {
+    match path.canonicalize() {
+        Ok(p) => p,
+        Err(e) => {
+            log::error!("Error {:?} when resolving {:?}", e, path);
+            eprintln!("Error : {} when resolving {}", e, path.display());
+            std::process::exit(1)
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! # manifest
+//!
+//! CSV file written at the root of the sorted images directory, with one line per copied
+//! file : where it comes from, where it has been copied, and how it has been dated.
+
+use crate::exif::ExifData;
+use once_cell::sync::Lazy;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+pub const MANIFEST_FILENAME: &str = "images_sort_manifest.csv";
+const MANIFEST_HEADER: &str = "source,destination,capture_time,date_source,rating,keywords,note";
+
+static MANIFEST_WRITER: Lazy<Mutex<Option<BufWriter<File>>>> = Lazy::new(|| Mutex::new(None));
+
+pub struct Manifest;
+
+impl Manifest {
+    /// Create the manifest in the sorted images directory. Until then, records are ignored.
+    pub fn create(sorted_images_directory: &Path) -> std::io::Result<()> {
+        let path = sorted_images_directory.join(MANIFEST_FILENAME);
+        log::trace!("Manifest::create {:?}", path);
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "{}", MANIFEST_HEADER)?;
+        *MANIFEST_WRITER.lock().unwrap() = Some(writer);
+        Ok(())
+    }
+
+    /// Record the copy of `source` to `destination`
+    pub fn record(source: &Path, destination: &Path, exif_data: Option<&ExifData>, note: &str) {
+        let capture_time = exif_data
+            .and_then(|e| {
+                let time = e.capture_time?.format("%Y-%m-%d %H:%M:%S").to_string();
+                Some(match e.capture_offset {
+                    Some(offset) => format!("{}{}", time, offset),
+                    None => time,
+                })
+            })
+            .unwrap_or_default();
+        let date_source = exif_data
+            .and_then(|e| e.date_source)
+            .map(|s| s.to_string())
+            .unwrap_or_default();
+        let rating = exif_data
+            .and_then(|e| e.rating)
+            .map(|r| r.to_string())
+            .unwrap_or_default();
+        let keywords = exif_data.map(|e| e.keywords.join(";")).unwrap_or_default();
+        let line = [
+            source.display().to_string(),
+            destination.display().to_string(),
+            capture_time,
+            date_source,
+            rating,
+            keywords,
+            note.to_string(),
+        ]
+        .iter()
+        .map(|field| csv_field(field))
+        .collect::<Vec<String>>()
+        .join(",");
+
+        if let Some(writer) = MANIFEST_WRITER.lock().unwrap().as_mut() {
+            if let Err(e) = writeln!(writer, "{}", line) {
+                log::error!("Error {:?} when writing the manifest", e);
+            }
+        }
+    }
+
+    /// Write the buffered records on disk
+    pub fn flush() {
+        Self::flush_writer(&mut MANIFEST_WRITER.lock().unwrap());
+    }
+
+    /// Same as flush(), but gives up if the manifest is being written (usable from a
+    /// signal handler)
+    pub fn try_flush() {
+        if let Ok(mut writer) = MANIFEST_WRITER.try_lock() {
+            Self::flush_writer(&mut writer);
+        }
+    }
+
+    fn flush_writer(writer: &mut Option<BufWriter<File>>) {
+        if let Some(writer) = writer.as_mut() {
+            if let Err(e) = writer.flush() {
+                log::error!("Error {:?} when flushing the manifest", e);
+            }
+        }
+    }
+}
+
+/// Quote a CSV field when needed (RFC 4180)
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field("2008 10/Arezzo/DSCN0025.jpg"), "2008 10/Arezzo/DSCN0025.jpg");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"cheese\""), "\"say \"\"cheese\"\"\"");
+    }
+}
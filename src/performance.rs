@@ -3,6 +3,7 @@
 //! This module provides tools to measure and report performance metrics
 //! for image processing operations.
 
+use crate::shutdown;
 use once_cell::sync::Lazy;
 use std::sync::RwLock;
 use std::time::{Duration, Instant};
@@ -77,6 +78,9 @@ impl PerformanceMetrics {
         println!("╔═══════════════════════════════════════════════════════════╗");
         println!("║              ⚡ Performance Report                        ║");
         println!("╠═══════════════════════════════════════════════════════════╣");
+        if shutdown::is_interrupted() {
+            println!("║ ⛔ INCOMPLETE : the run has been interrupted              ║");
+        }
 
         // EXIF operations
         if metrics.exif_reads > 0 {
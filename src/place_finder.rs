@@ -1,11 +1,14 @@
 //! reverse_gps
 //! leverage reverse_geocoder crate to get the nearest place (town) of the GPS data we got from an image.
 use crate::performance::{PerformanceMetrics, Timer};
+use chrono::FixedOffset;
+use chrono_tz::Tz;
 use exif::Rational;
 use lru::LruCache;
 use once_cell::sync::{Lazy, OnceCell};
 use reverse_geocoder::ReverseGeocoder;
 use std::num::NonZeroUsize;
+use std::collections::HashSet;
 use std::sync::Mutex;
 
 #[derive(Debug)]
@@ -16,12 +19,25 @@ pub enum PlaceFinderError {
 // static variable to avoid loading data for each image we are dealing with.
 static REVERSE_GEOCODER_WRAPPER: OnceCell<ReverseGeocoderWrapper> = OnceCell::new();
 
-// LRU cache for geocoding results (coordinates -> place name)
+// LRU cache for geocoding results (coordinates -> nearest place)
 // Cache up to 1000 locations (precision ~11m)
-static GEOCODING_CACHE: Lazy<Mutex<LruCache<(i32, i32), String>>> = Lazy::new(|| {
+static GEOCODING_CACHE: Lazy<Mutex<LruCache<(i32, i32), Place>>> = Lazy::new(|| {
     Mutex::new(LruCache::new(NonZeroUsize::new(1000).unwrap()))
 });
 
+// Countries without a time zone in the tables, already reported in the log
+static COUNTRIES_WITHOUT_TIMEZONE: Lazy<Mutex<HashSet<String>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+/// Nearest place known by the geocoder
+#[derive(Clone, Debug)]
+struct Place {
+    name: String,
+    /// ISO 3166 code of the country
+    cc: String,
+    /// State, province or region
+    admin1: String,
+}
+
 pub struct ReverseGeocoderWrapper {
     pub reverse_geocoder: ReverseGeocoder,
 }
@@ -36,9 +52,13 @@ impl ReverseGeocoderWrapper {
 }
 
 pub fn find_place(lat: f64, long: f64) -> Option<String> {
-    let timer = Timer::new();
-
     log::trace!("find_place {} {}", lat, long);
+    Some(lookup(lat, long).name)
+}
+
+/// Nearest place, from the cache or the geocoder
+fn lookup(lat: f64, long: f64) -> Place {
+    let timer = Timer::new();
 
     // Round coordinates to ~11m precision (4 decimal places)
     // This allows cache hits for photos taken near each other
@@ -53,29 +73,74 @@ pub fn find_place(lat: f64, long: f64) -> Option<String> {
         if let Some(place) = cache.get(&cache_key) {
             log::debug!("Cache hit for coordinates ({}, {})", lat, long);
             PerformanceMetrics::record_geocoding(timer.elapsed(), true);
-            return Some(place.clone());
+            return place.clone();
         }
     }
 
     // Cache miss - perform actual geocoding
-    let coords = (lat, long);
     let search_result = ReverseGeocoderWrapper::get_geocoder_wrapper()
         .reverse_geocoder
-        .search(coords);
+        .search((lat, long));
     log::debug!("Distance {}", search_result.distance);
     log::debug!("Record {}", search_result.record);
 
-    let place_name = String::from(&search_result.record.name);
+    let place = Place {
+        name: search_result.record.name.clone(),
+        cc: search_result.record.cc.clone(),
+        admin1: search_result.record.admin1.clone(),
+    };
 
     // Store in cache
     {
         let mut cache = GEOCODING_CACHE.lock().unwrap();
-        cache.put(cache_key, place_name.clone());
+        cache.put(cache_key, place.clone());
     }
 
     PerformanceMetrics::record_geocoding(timer.elapsed(), false);
 
-    Some(place_name)
+    place
+}
+
+/// Time zone at a GPS position : the IANA zone of the country (or region) of the nearest
+/// place known by the geocoder. None for countries spanning several zones and not in the
+/// tables (see nautical_offset() for a rough fallback).
+pub fn find_timezone(lat: f64, long: f64) -> Option<Tz> {
+    log::trace!("find_timezone {} {}", lat, long);
+    let place = lookup(lat, long);
+    let region = REGION_TIMEZONES
+        .iter()
+        .find(|(cc, regions, _)| *cc == place.cc && regions.contains(&place.admin1.as_str()))
+        .map(|(_, _, tz)| *tz);
+    let country = || {
+        COUNTRY_TIMEZONES
+            .iter()
+            .find(|(country, _)| *country == place.cc)
+            .map(|(_, tz)| *tz)
+    };
+    let Some(name) = region.or_else(country) else {
+        if COUNTRIES_WITHOUT_TIMEZONE.lock().unwrap().insert(place.cc.clone()) {
+            log::warn!(
+                "no time zone known for {} ({}, {}), the offsets in this country are approximated by the longitude",
+                place.name,
+                place.cc,
+                place.admin1
+            );
+        }
+        return None;
+    };
+    if place.cc == "US" && SPLIT_US_STATES.contains(&place.admin1.as_str()) {
+        log::debug!("{} spans several time zones, {} may be wrong for {}", place.admin1, name, place.name);
+    }
+    log::debug!("time zone of {} ({}, {}) : {}", place.name, place.cc, place.admin1, name);
+    name.parse().ok()
+}
+
+/// Offset of the nautical time zone of a longitude (15° per hour). Only a rough approximation
+/// of the legal time.
+pub fn nautical_offset(long: f64) -> FixedOffset {
+    let hours = (long / 15.0).round().clamp(-12.0, 12.0) as i32;
+    // unwrap() is ok here, |hours| <= 12
+    FixedOffset::east_opt(hours * 3600).unwrap()
 }
 
 /// Conversion from deg / min / sec format to decimal degrees
@@ -85,7 +150,7 @@ pub fn convert_deg_min_sec_to_decimal_deg(coord: &Vec<Rational>) -> Result<f64,
     log::trace!("convert_deg_min_sec_to_decimal_deg {:?}", coord);
     let display = format!("{:?}", coord);
     let deg = coord
-        .get(0)
+        .first()
         .ok_or(PlaceFinderError::Decode(display.clone()))?;
     let min = coord
         .get(1)
@@ -98,6 +163,106 @@ pub fn convert_deg_min_sec_to_decimal_deg(coord: &Vec<Rational>) -> Result<f64,
     Ok(deg.to_f64() + m + s)
 }
 
+// Countries (ISO 3166 codes used by the geocoder) with a single time zone, or one used by
+// nearly all the population
+const COUNTRY_TIMEZONES: &[(&str, &str)] = &[
+    // Europe
+    ("AD", "Europe/Andorra"), ("AL", "Europe/Tirane"), ("AT", "Europe/Vienna"),
+    ("BA", "Europe/Sarajevo"), ("BE", "Europe/Brussels"), ("BG", "Europe/Sofia"),
+    ("BY", "Europe/Minsk"), ("CH", "Europe/Zurich"), ("CY", "Asia/Nicosia"),
+    ("CZ", "Europe/Prague"), ("DE", "Europe/Berlin"), ("DK", "Europe/Copenhagen"),
+    ("EE", "Europe/Tallinn"), ("ES", "Europe/Madrid"), ("FI", "Europe/Helsinki"),
+    ("FR", "Europe/Paris"), ("GB", "Europe/London"), ("GR", "Europe/Athens"),
+    ("HR", "Europe/Zagreb"), ("HU", "Europe/Budapest"), ("IE", "Europe/Dublin"),
+    ("IS", "Atlantic/Reykjavik"), ("IT", "Europe/Rome"), ("LI", "Europe/Vaduz"),
+    ("LT", "Europe/Vilnius"), ("LU", "Europe/Luxembourg"), ("LV", "Europe/Riga"),
+    ("MC", "Europe/Monaco"), ("MD", "Europe/Chisinau"), ("ME", "Europe/Podgorica"),
+    ("MK", "Europe/Skopje"), ("MT", "Europe/Malta"), ("NL", "Europe/Amsterdam"),
+    ("NO", "Europe/Oslo"), ("PL", "Europe/Warsaw"), ("PT", "Europe/Lisbon"),
+    ("RO", "Europe/Bucharest"), ("RS", "Europe/Belgrade"), ("SE", "Europe/Stockholm"),
+    ("SI", "Europe/Ljubljana"), ("SK", "Europe/Bratislava"), ("SM", "Europe/San_Marino"),
+    ("TR", "Europe/Istanbul"), ("UA", "Europe/Kyiv"), ("VA", "Europe/Vatican"),
+    // Africa
+    ("DZ", "Africa/Algiers"), ("EG", "Africa/Cairo"), ("ET", "Africa/Addis_Ababa"),
+    ("GH", "Africa/Accra"), ("KE", "Africa/Nairobi"), ("MA", "Africa/Casablanca"),
+    ("MG", "Indian/Antananarivo"), ("MU", "Indian/Mauritius"), ("NG", "Africa/Lagos"),
+    ("RE", "Indian/Reunion"), ("SN", "Africa/Dakar"), ("TN", "Africa/Tunis"),
+    ("TZ", "Africa/Dar_es_Salaam"), ("ZA", "Africa/Johannesburg"),
+    // Middle East and Asia
+    ("AE", "Asia/Dubai"), ("BD", "Asia/Dhaka"), ("CN", "Asia/Shanghai"),
+    ("HK", "Asia/Hong_Kong"), ("IL", "Asia/Jerusalem"), ("IN", "Asia/Kolkata"),
+    ("IR", "Asia/Tehran"), ("JO", "Asia/Amman"), ("JP", "Asia/Tokyo"),
+    ("KH", "Asia/Phnom_Penh"), ("KR", "Asia/Seoul"), ("LA", "Asia/Vientiane"),
+    ("LB", "Asia/Beirut"), ("LK", "Asia/Colombo"), ("MM", "Asia/Yangon"),
+    ("MV", "Indian/Maldives"), ("MY", "Asia/Kuala_Lumpur"), ("NP", "Asia/Kathmandu"),
+    ("OM", "Asia/Muscat"), ("PH", "Asia/Manila"), ("PK", "Asia/Karachi"),
+    ("QA", "Asia/Qatar"), ("SA", "Asia/Riyadh"), ("SG", "Asia/Singapore"),
+    ("TH", "Asia/Bangkok"), ("TW", "Asia/Taipei"), ("VN", "Asia/Ho_Chi_Minh"),
+    // Americas and Oceania
+    ("AR", "America/Argentina/Buenos_Aires"), ("BO", "America/La_Paz"),
+    ("CL", "America/Santiago"), ("CO", "America/Bogota"), ("CR", "America/Costa_Rica"),
+    ("CU", "America/Havana"), ("DO", "America/Santo_Domingo"), ("GF", "America/Cayenne"),
+    ("GP", "America/Guadeloupe"), ("GT", "America/Guatemala"), ("MQ", "America/Martinique"),
+    ("NC", "Pacific/Noumea"), ("NZ", "Pacific/Auckland"), ("PA", "America/Panama"),
+    ("PE", "America/Lima"), ("PF", "Pacific/Tahiti"), ("PR", "America/Puerto_Rico"),
+    ("PY", "America/Asuncion"), ("UY", "America/Montevideo"), ("VE", "America/Caracas"),
+];
+
+// Regions (admin1 names used by the geocoder) by time zone, in the countries spanning
+// several zones
+const REGION_TIMEZONES: &[(&str, &[&str], &str)] = &[
+    (
+        "US",
+        &[
+            "Connecticut", "Delaware", "District of Columbia", "Florida", "Georgia", "Indiana",
+            "Maine", "Maryland", "Massachusetts", "Michigan", "New Hampshire", "New Jersey",
+            "New York", "North Carolina", "Ohio", "Pennsylvania", "Rhode Island",
+            "South Carolina", "Vermont", "Virginia", "West Virginia", "Kentucky",
+        ],
+        "America/New_York",
+    ),
+    (
+        "US",
+        &[
+            "Alabama", "Arkansas", "Illinois", "Iowa", "Kansas", "Louisiana", "Minnesota",
+            "Mississippi", "Missouri", "Nebraska", "North Dakota", "Oklahoma", "South Dakota",
+            "Tennessee", "Texas", "Wisconsin",
+        ],
+        "America/Chicago",
+    ),
+    ("US", &["Colorado", "Idaho", "Montana", "New Mexico", "Utah", "Wyoming"], "America/Denver"),
+    ("US", &["Arizona"], "America/Phoenix"),
+    ("US", &["California", "Nevada", "Oregon", "Washington"], "America/Los_Angeles"),
+    ("US", &["Alaska"], "America/Anchorage"),
+    ("US", &["Hawaii"], "Pacific/Honolulu"),
+    ("ES", &["Canary Islands"], "Atlantic/Canary"),
+    ("PT", &["Azores"], "Atlantic/Azores"),
+    ("CA", &["British Columbia"], "America/Vancouver"),
+    ("CA", &["Alberta"], "America/Edmonton"),
+    ("CA", &["Saskatchewan"], "America/Regina"),
+    ("CA", &["Manitoba"], "America/Winnipeg"),
+    ("CA", &["Ontario", "Quebec"], "America/Toronto"),
+    ("CA", &["Nova Scotia", "Prince Edward Island"], "America/Halifax"),
+    ("CA", &["New Brunswick"], "America/Moncton"),
+    ("CA", &["Newfoundland and Labrador"], "America/St_Johns"),
+    ("CA", &["Yukon"], "America/Whitehorse"),
+    ("CA", &["Northwest Territories"], "America/Yellowknife"),
+    ("CA", &["Nunavut"], "America/Iqaluit"),
+    ("AU", &["New South Wales", "Australian Capital Territory"], "Australia/Sydney"),
+    ("AU", &["Victoria"], "Australia/Melbourne"),
+    ("AU", &["Queensland"], "Australia/Brisbane"),
+    ("AU", &["South Australia"], "Australia/Adelaide"),
+    ("AU", &["Western Australia"], "Australia/Perth"),
+    ("AU", &["Tasmania"], "Australia/Hobart"),
+    ("AU", &["Northern Territory"], "Australia/Darwin"),
+];
+
+// US states split between two time zones : the one of most of the population is used
+const SPLIT_US_STATES: &[&str] = &[
+    "Florida", "Idaho", "Indiana", "Kansas", "Kentucky", "Michigan", "Nebraska", "North Dakota",
+    "Oregon", "South Dakota", "Tennessee", "Texas",
+];
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -137,4 +302,20 @@ mod tests {
         let saint_denis = find_place(lat, long);
         assert_eq!(saint_denis.unwrap(), String::from("Saint-Denis"));
     }
+
+    #[test]
+    fn test_find_timezone() {
+        init();
+        assert_eq!(find_timezone(48.083328, -1.68333), Some(chrono_tz::Europe::Paris));
+        assert_eq!(find_timezone(-20.8798761, 55.4440519), Some(chrono_tz::Indian::Reunion));
+        // San Francisco
+        assert_eq!(find_timezone(37.7749, -122.4194), Some(chrono_tz::America::Los_Angeles));
+        // Toronto, Perth
+        assert_eq!(find_timezone(43.6532, -79.3832), Some(chrono_tz::America::Toronto));
+        assert_eq!(find_timezone(-31.9523, 115.8613), Some(chrono_tz::Australia::Perth));
+        // Moscow : Russia spans several zones and is not in the tables
+        assert_eq!(find_timezone(55.7558, 37.6173), None);
+        assert_eq!(nautical_offset(37.6173), FixedOffset::east_opt(3 * 3600).unwrap());
+        assert_eq!(nautical_offset(-122.4194), FixedOffset::west_opt(8 * 3600).unwrap());
+    }
 }
@@ -0,0 +1,255 @@
+//! # plan
+//!
+//! Decisions needing the metadata of all the files, taken after the scan of the source
+//! directories and before copying anything.
+
+use crate::calendar::{self, CalendarEvent};
+use crate::companions;
+use crate::events::{self, Event};
+use crate::exif::{ExifData, ExifError, GpsPosition};
+use crate::global_configuration::GlobalConfiguration;
+use crate::layout;
+use crate::place_finder;
+use crate::rename::{ExtensionCase, RenameTemplate};
+use crate::reporting::Reporting;
+use crate::trips::{self, Trip};
+use chrono::{NaiveDate, NaiveDateTime};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+
+/// A file of the source directories and its metadata
+#[derive(Debug)]
+pub struct ScannedFile {
+    pub path: PathBuf,
+    pub exif_data: Result<ExifData, ExifError>,
+}
+
+/// An archive of the source directories and its entries, by directory
+#[derive(Debug)]
+pub struct ScannedArchive {
+    pub path: PathBuf,
+    pub directories: Vec<Vec<ScannedFile>>,
+}
+
+#[derive(Debug, Default)]
+pub struct SortPlan {
+    events: Vec<Event>,
+    event_of_file: HashMap<PathBuf, usize>,
+    trips: Vec<Trip>,
+    trip_of_file: HashMap<PathBuf, usize>,
+    calendar_events_count: usize,
+    /// New names of the files sorted in the dated tree
+    names: HashMap<PathBuf, OsString>,
+}
+
+/// A dated photo, with its capture time in the time zone of the folders
+type DatedPhoto<'a> = (NaiveDateTime, &'a ScannedFile);
+
+impl SortPlan {
+    /// Plan the sort of the scanned files, given directory by directory
+    pub fn build<'a>(
+        directories: impl Iterator<Item = &'a [ScannedFile]>,
+        configuration: &GlobalConfiguration,
+    ) -> SortPlan {
+        let directories: Vec<&[ScannedFile]> = directories.collect();
+        // the twins of a file are copied with it, and renamed after it
+        let twins: HashSet<&Path> = directories
+            .iter()
+            .flat_map(|files| companions::group_companions(files))
+            .flat_map(|group| group.twins)
+            .map(|file| file.path.as_path())
+            .collect();
+        let mut photos: Vec<DatedPhoto> = directories
+            .iter()
+            .flat_map(|files| files.iter())
+            .filter_map(|file| {
+                let exif_data = file.exif_data.as_ref().ok()?;
+                // the thumbnails are not sorted
+                if exif_data.is_below_megapixels(*configuration.min_megapixels()) {
+                    return None;
+                }
+                let time = layout::folder_time(exif_data, *configuration.folder_timezone())?;
+                Some((time, file))
+            })
+            .collect();
+        photos.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.path.cmp(&b.1.path)));
+
+        let mut plan = SortPlan::default();
+        if let Some(gap) = configuration.event_gap() {
+            plan.plan_events(&photos, *gap);
+        }
+        if !configuration.calendar().is_empty() {
+            plan.plan_calendar(&photos, configuration.calendar());
+        }
+        if let Some(home) = configuration.home() {
+            plan.plan_trips(&photos, home, *configuration.home_radius_km());
+        }
+        if configuration.rename().is_some() || *configuration.extension_case() != ExtensionCase::Keep {
+            let primaries: Vec<DatedPhoto> =
+                photos.iter().filter(|(_, file)| !twins.contains(file.path.as_path())).copied().collect();
+            plan.plan_names(&primaries, configuration.rename().as_ref(), *configuration.extension_case());
+        }
+        plan
+    }
+
+    /// Photos taken during an event of the calendar go in its folder, whether or not they
+    /// are grouped by time gaps
+    fn plan_calendar(&mut self, photos: &[DatedPhoto], calendar: &[CalendarEvent]) {
+        // recurring events (birthdays...) have the same name every year
+        let mut used: HashMap<(&str, NaiveDateTime), usize> = HashMap::new();
+        for (time, file) in photos {
+            let Some(calendar_event) = calendar::find_event(calendar, *time) else {
+                continue;
+            };
+            let key = (calendar_event.name.as_str(), calendar_event.start);
+            let index = *used.entry(key).or_insert_with(|| {
+                self.events.push(Event {
+                    start: calendar_event.start,
+                    folder: layout::event_directory(calendar_event.start, Some(&calendar_event.name)),
+                });
+                self.events.len() - 1
+            });
+            self.event_of_file.insert(file.path.clone(), index);
+        }
+        self.calendar_events_count = used.len();
+
+        for (first, second) in calendar::find_overlaps(calendar) {
+            let photos_in_both = photos
+                .iter()
+                .filter(|(time, _)| first.contains(*time) && second.contains(*time))
+                .count();
+            log::warn!("calendar events {} and {} overlap ({} photos)", first.name, second.name, photos_in_both);
+            if photos_in_both > 0 {
+                Reporting::add_calendar_overlap(first.name.clone(), second.name.clone(), photos_in_both as u32);
+            }
+        }
+    }
+
+    /// Names of the dated files, from the template or with the case of the extension only.
+    /// The files whose names would be the same are numbered in the order of capture (to the
+    /// fraction of second for bursts).
+    fn plan_names(&mut self, photos: &[DatedPhoto], template: Option<&RenameTemplate>, case: ExtensionCase) {
+        let Some(template) = template else {
+            for (_, file) in photos {
+                let stem = file.path.file_stem().unwrap_or_default().to_string_lossy();
+                let extension = file.path.extension().map(|e| case.apply(&e.to_string_lossy()));
+                let name = match extension {
+                    Some(extension) => format!("{}.{}", stem, extension),
+                    None => stem.to_string(),
+                };
+                self.names.insert(file.path.clone(), OsString::from(name));
+            }
+            return;
+        };
+        let mut same_name: HashMap<String, Vec<(&ExifData, &ScannedFile)>> = HashMap::new();
+        for (_, file) in photos {
+            let Ok(exif_data) = &file.exif_data else {
+                continue;
+            };
+            if let Some(name) = template.render(exif_data, &file.path, None, case) {
+                same_name.entry(name).or_default().push((exif_data, file));
+            }
+        }
+        for (name, mut files) in same_name {
+            if !template.has_seq() {
+                for (_, file) in files {
+                    self.names.insert(file.path.clone(), OsString::from(&name));
+                }
+                continue;
+            }
+            files.sort_by(|a, b| {
+                (a.0.capture_time_precise(), &a.1.path).cmp(&(b.0.capture_time_precise(), &b.1.path))
+            });
+            for (seq, (exif_data, file)) in files.into_iter().enumerate() {
+                // unwrap() is ok here, rendered without the sequence number
+                let name = template.render(exif_data, &file.path, Some(seq + 1), case).unwrap();
+                self.names.insert(file.path.clone(), OsString::from(name));
+            }
+        }
+        log::info!("{} files renamed", self.names.len());
+    }
+
+    fn plan_events(&mut self, photos: &[DatedPhoto], gap: chrono::Duration) {
+        let times: Vec<NaiveDateTime> = photos.iter().map(|(time, _)| *time).collect();
+
+        for range in events::split_in_events(&times, gap) {
+            let positions: Vec<_> = photos[range.clone()]
+                .iter()
+                .filter_map(|(_, file)| file.exif_data.as_ref().ok()?.gps)
+                .collect();
+            let event = events::name_event(times[range.start], &positions);
+            for (_, file) in &photos[range] {
+                self.event_of_file.insert(file.path.clone(), self.events.len());
+            }
+            self.events.push(event);
+        }
+        log::info!("{} photos grouped in {} events", photos.len(), self.events.len());
+    }
+
+    /// Days with photos taken out of the home radius are trip days. The photos of a trip are
+    /// the ones taken away from home, and the ones without GPS position taken during it.
+    fn plan_trips(&mut self, photos: &[DatedPhoto], home: &GpsPosition, radius_km: f64) {
+        let gps = |file: &ScannedFile| {
+            file.exif_data
+                .as_ref()
+                .ok()?
+                .gps
+                .filter(|gps| gps.latitude != 0.0 || gps.longitude != 0.0)
+        };
+        let is_away = |file: &ScannedFile| gps(file).is_some_and(|gps| trips::distance_km(home, &gps) > radius_km);
+        let away_days: BTreeSet<NaiveDate> = photos
+            .iter()
+            .filter(|(_, file)| is_away(file))
+            .map(|(time, _)| time.date())
+            .collect();
+
+        for (first_day, last_day) in trips::group_consecutive_days(&away_days) {
+            let members: Vec<&DatedPhoto> = photos
+                .iter()
+                .filter(|(time, file)| {
+                    (first_day..=last_day).contains(&time.date()) && (gps(file).is_none() || is_away(file))
+                })
+                .collect();
+            let places: Vec<String> = members
+                .iter()
+                .filter_map(|(_, file)| gps(file))
+                .filter_map(|gps| place_finder::find_place(gps.latitude, gps.longitude))
+                .collect();
+            let trip = trips::name_trip(first_day, last_day, &places);
+            for (_, file) in members {
+                self.trip_of_file.insert(file.path.clone(), self.trips.len());
+            }
+            self.trips.push(trip);
+        }
+        log::info!("{} trips found", self.trips.len());
+    }
+
+    /// Event of a file, when grouping by events
+    pub fn event(&self, file: &Path) -> Option<&Event> {
+        self.event_of_file.get(file).map(|i| &self.events[*i])
+    }
+
+    /// New name of a file sorted in the dated tree, if it is renamed
+    pub fn name(&self, file: &Path) -> Option<&OsStr> {
+        self.names.get(file).map(OsString::as_os_str)
+    }
+
+    /// Events found by time gaps (the calendar events are counted apart)
+    pub fn events_count(&self) -> usize {
+        self.events.len() - self.calendar_events_count
+    }
+
+    pub fn calendar_events_count(&self) -> usize {
+        self.calendar_events_count
+    }
+
+    /// Trip of a file, when a home position is configured
+    pub fn trip(&self, file: &Path) -> Option<&Trip> {
+        self.trip_of_file.get(file).map(|i| &self.trips[*i])
+    }
+
+    pub fn trips_count(&self) -> usize {
+        self.trips.len()
+    }
+}
@@ -0,0 +1,126 @@
+//! # preflight
+//!
+//! Checks done before copying anything : the destination directory must be writable and its
+//! filesystem must have enough free space to receive all the files of the source directory.
+
+use std::fs;
+use std::path::Path;
+
+const WRITE_PROBE_FILENAME: &str = ".images_sort_write_probe";
+
+#[derive(thiserror::Error, Debug)]
+pub enum PreflightError {
+    #[error("Destination directory {0} is not writable ({1})")]
+    NotWritable(String, std::io::Error),
+    #[error("Not enough free space on destination : {} needed, {} available", human_size(*.needed), human_size(*.available))]
+    NotEnoughSpace { needed: u64, available: u64 },
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+}
+
+/// Verify that `dest_dir` is writable and that its filesystem can hold `needed_bytes`.
+pub fn check_destination(dest_dir: &Path, needed_bytes: u64) -> Result<(), PreflightError> {
+    log::trace!("check_destination {:?} for {} bytes", dest_dir, needed_bytes);
+    check_writable(dest_dir)?;
+
+    match available_space(dest_dir)? {
+        Some(available) => {
+            log::info!(
+                "Preflight : {} needed, {} available on destination",
+                human_size(needed_bytes),
+                human_size(available)
+            );
+            if needed_bytes > available {
+                return Err(PreflightError::NotEnoughSpace {
+                    needed: needed_bytes,
+                    available,
+                });
+            }
+        }
+        None => log::warn!("Free space on destination can't be checked on this platform"),
+    }
+
+    Ok(())
+}
+
+/// Create and remove a probe file in `dir`
+fn check_writable(dir: &Path) -> Result<(), PreflightError> {
+    let probe = dir.join(WRITE_PROBE_FILENAME);
+    fs::write(&probe, b"")
+        .and_then(|_| fs::remove_file(&probe))
+        .map_err(|e| PreflightError::NotWritable(dir.display().to_string(), e))
+}
+
+/// Free space (in bytes) available to an unprivileged user on the filesystem holding `path`
+#[cfg(unix)]
+fn available_space(path: &Path) -> std::io::Result<Option<u64>> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: c_path is a valid NUL terminated string and stat a valid statvfs struct
+    let r = unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) };
+    if r != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    #[allow(clippy::unnecessary_cast)] // types of statvfs fields differ between platforms
+    Ok(Some(stat.f_bavail as u64 * stat.f_frsize as u64))
+}
+
+#[cfg(not(unix))]
+fn available_space(_path: &Path) -> std::io::Result<Option<u64>> {
+    Ok(None)
+}
+
+/// Format a number of bytes for humans (MB or GB)
+pub fn human_size(bytes: u64) -> String {
+    let mb = bytes as f64 / (1024.0 * 1024.0);
+    if mb < 1024.0 {
+        format!("{:.2} MB", mb)
+    } else {
+        format!("{:.2} GB", mb / 1024.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    #[test]
+    fn test_check_destination() {
+        init();
+        let current_dir = std::env::current_dir().unwrap();
+        let dir = Path::new("./test_preflight");
+        std::fs::create_dir(dir).unwrap();
+
+        assert!(check_destination(dir, 1024).is_ok());
+        assert!(!dir.join(WRITE_PROBE_FILENAME).exists());
+
+        #[cfg(unix)]
+        match check_destination(dir, u64::MAX) {
+            Err(PreflightError::NotEnoughSpace { needed, .. }) => assert_eq!(needed, u64::MAX),
+            r => panic!("Unexpected result {:?}", r),
+        }
+
+        assert!(matches!(
+            check_destination(Path::new("./test_preflight/missing"), 0),
+            Err(PreflightError::NotWritable(_, _))
+        ));
+
+        // ensure we are in the good directory before cleanup
+        assert_eq!(current_dir, std::env::current_dir().unwrap());
+        // cleanup
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_human_size() {
+        assert_eq!(human_size(5 * 1024 * 1024), "5.00 MB");
+        assert_eq!(human_size(3 * 1024 * 1024 * 1024), "3.00 GB");
+    }
+}
@@ -0,0 +1,315 @@
+//! # quicktime
+//!
+//! Metadata of QuickTime / MP4 videos (ISO base media file format) : creation time of the
+//! `mvhd` atom, location of the `©xyz` atom (Android...) and Apple `com.apple.quicktime.*`
+//! keys of the `meta` atom (iPhone).
+
+use crate::archives;
+use crate::exif::{self, GpsPosition};
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+// the movie atom holds only metadata and sample tables, bigger ones are not plausible
+const MAX_MOOV_SIZE: u64 = 64 * 1024 * 1024;
+// first atoms of QuickTime files without ftyp
+const TOP_LEVEL_ATOMS: [&[u8; 4]; 6] = [b"ftyp", b"moov", b"mdat", b"wide", b"free", b"skip"];
+const APPLE_CREATION_DATE: &str = "com.apple.quicktime.creationdate";
+const APPLE_LOCATION: &str = "com.apple.quicktime.location.ISO6709";
+const APPLE_MAKE: &str = "com.apple.quicktime.make";
+const APPLE_MODEL: &str = "com.apple.quicktime.model";
+// shared by the still and the video of a Live Photo
+const APPLE_CONTENT_IDENTIFIER: &str = "com.apple.quicktime.content.identifier";
+
+// +43.4667+011.8833+100.000/
+static ISO6709_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^([+\-]\d+(?:\.\d+)?)([+\-]\d+(?:\.\d+)?)([+\-]\d+(?:\.\d+)?)?").unwrap()
+});
+
+/// Metadata of a video
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct VideoData {
+    /// Local date and time with offset, as written by Apple devices
+    /// (`2019-08-12T14:30:55+0200`)
+    pub creation_date: Option<String>,
+    /// Creation time of the movie header, in UTC
+    pub creation_time_utc: Option<NaiveDateTime>,
+    pub gps: Option<GpsPosition>,
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub content_identifier: Option<String>,
+}
+
+impl VideoData {
+    /// Offset written with the Apple creation date
+    pub fn date_offset(&self) -> Option<&str> {
+        let date = self.creation_date.as_deref()?;
+        exif::written_offset(date)
+    }
+}
+
+/// Is the file a QuickTime / MP4 video ?
+pub fn is_quicktime(path: &Path) -> std::io::Result<bool> {
+    let mut header = [0u8; 8];
+    let mut file = archives::open(path)?;
+    if file.read_exact(&mut header).is_err() {
+        return Ok(false);
+    }
+    Ok(TOP_LEVEL_ATOMS.iter().any(|atom| header[4..8] == atom[..]))
+}
+
+/// Read the metadata of the movie atom, which may be at the end of the file
+pub fn read_video_metadata(path: &Path) -> std::io::Result<Option<VideoData>> {
+    log::trace!("read_video_metadata of {:?}", path);
+    Ok(read_moov(path)?.map(|moov| parse_moov(&moov)))
+}
+
+/// Content of the movie atom, found by skipping the other top level atoms
+pub fn read_moov(path: &Path) -> std::io::Result<Option<Vec<u8>>> {
+    let mut file = std::io::BufReader::new(archives::open(path)?);
+    let file_size = archives::len(path)?;
+    let mut position = 0;
+    while position + 8 <= file_size {
+        let mut header = [0u8; 8];
+        file.read_exact(&mut header)?;
+        let mut header_size = 8;
+        let size = match u32::from_be_bytes(header[..4].try_into().unwrap()) as u64 {
+            // 64 bits size
+            1 => {
+                let mut large_size = [0u8; 8];
+                file.read_exact(&mut large_size)?;
+                header_size = 16;
+                u64::from_be_bytes(large_size)
+            }
+            // up to the end of the file
+            0 => file_size - position,
+            size => size,
+        };
+        if size < header_size {
+            log::warn!("invalid atom size {} in {:?}", size, path);
+            return Ok(None);
+        }
+        if &header[4..8] == b"moov" {
+            if size > MAX_MOOV_SIZE {
+                log::warn!("movie atom of {:?} too big ({} bytes)", path, size);
+                return Ok(None);
+            }
+            let mut moov = vec![0u8; (size - header_size) as usize];
+            file.read_exact(&mut moov)?;
+            return Ok(Some(moov));
+        }
+        position = match position.checked_add(size) {
+            Some(next) if next <= file_size => next,
+            _ => {
+                log::warn!("atom of {} bytes past the end of {:?}", size, path);
+                return Ok(None);
+            }
+        };
+        file.seek(SeekFrom::Start(position))?;
+    }
+    Ok(None)
+}
+
+/// Split a buffer in atoms : (type, content)
+pub fn atoms(mut data: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut atoms = Vec::new();
+    while data.len() >= 8 {
+        let size = u32::from_be_bytes(data[..4].try_into().unwrap()) as usize;
+        let size = if size == 0 { data.len() } else { size };
+        if size < 8 || size > data.len() {
+            break;
+        }
+        atoms.push((&data[4..8], &data[8..size]));
+        data = &data[size..];
+    }
+    atoms
+}
+
+fn parse_moov(moov: &[u8]) -> VideoData {
+    let mut video_data = VideoData::default();
+    for (atom, content) in atoms(moov) {
+        match atom {
+            b"mvhd" => video_data.creation_time_utc = parse_mvhd(content),
+            b"udta" => {
+                for (atom, content) in atoms(content) {
+                    if atom == b"\xa9xyz" && content.len() > 4 {
+                        // 16 bits size and language, then the text
+                        video_data.gps = parse_iso6709(&String::from_utf8_lossy(&content[4..]));
+                    }
+                }
+            }
+            b"meta" => {
+                for (key, value) in parse_meta(content) {
+                    log::debug!("QuickTime {} = {}", key, value);
+                    match key.as_str() {
+                        APPLE_CREATION_DATE => video_data.creation_date = Some(value),
+                        APPLE_LOCATION => video_data.gps = parse_iso6709(&value).or(video_data.gps),
+                        APPLE_MAKE => video_data.make = Some(value),
+                        APPLE_MODEL => video_data.model = Some(value),
+                        APPLE_CONTENT_IDENTIFIER => video_data.content_identifier = Some(value),
+                        _ => (),
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    video_data
+}
+
+/// Creation time of the movie header : seconds since 1904-01-01 UTC, 0 if unknown
+fn parse_mvhd(mvhd: &[u8]) -> Option<NaiveDateTime> {
+    let seconds = match mvhd.first()? {
+        0 => u32::from_be_bytes(mvhd.get(4..8)?.try_into().ok()?) as i64,
+        1 => i64::try_from(u64::from_be_bytes(mvhd.get(4..12)?.try_into().ok()?)).ok()?,
+        _ => return None,
+    };
+    if seconds == 0 {
+        return None;
+    }
+    let epoch = NaiveDate::from_ymd_opt(1904, 1, 1)?.and_hms_opt(0, 0, 0)?;
+    // a corrupt 64 bits time may be out of the range of the dates
+    epoch.checked_add_signed(Duration::try_seconds(seconds)?)
+}
+
+/// Text values of the `keys` / `ilst` pairs of a meta atom
+fn parse_meta(meta: &[u8]) -> Vec<(String, String)> {
+    // a full atom (version and flags) in MP4 files, but not in QuickTime ones
+    let meta = match meta.get(4..8) {
+        Some(b"hdlr") => meta,
+        _ => meta.get(4..).unwrap_or_default(),
+    };
+    let mut keys = Vec::new();
+    let mut values = Vec::new();
+    for (atom, content) in atoms(meta) {
+        match atom {
+            // version, flags, count, then (size, namespace, name) entries
+            b"keys" if content.len() >= 8 => {
+                let mut entries = &content[8..];
+                while entries.len() >= 8 {
+                    let size = u32::from_be_bytes(entries[..4].try_into().unwrap()) as usize;
+                    if size < 8 || size > entries.len() {
+                        break;
+                    }
+                    keys.push(String::from_utf8_lossy(&entries[8..size]).to_string());
+                    entries = &entries[size..];
+                }
+            }
+            // items are numbered by the (1 based) index of their key
+            b"ilst" => {
+                for (index, item) in atoms(content) {
+                    let index = u32::from_be_bytes(index.try_into().unwrap()) as usize;
+                    for (atom, data) in atoms(item) {
+                        // type (1 : UTF-8), locale, value
+                        if atom == b"data" && data.get(..4) == Some(&[0, 0, 0, 1]) && data.len() >= 8 {
+                            values.push((index, String::from_utf8_lossy(&data[8..]).to_string()));
+                        }
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    values
+        .into_iter()
+        .filter_map(|(index, value)| Some((keys.get(index.checked_sub(1)?)?.clone(), value)))
+        .collect()
+}
+
+/// Parse a location written `+43.4667+011.8833+100.000/` (ISO 6709)
+fn parse_iso6709(value: &str) -> Option<GpsPosition> {
+    let captures = ISO6709_REGEX.captures(value.trim())?;
+    let number = |i: usize| captures.get(i)?.as_str().parse::<f64>().ok();
+    Some(GpsPosition {
+        latitude: number(1)?,
+        longitude: number(2)?,
+        altitude: number(3),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    fn atom(name: &[u8], content: &[u8]) -> Vec<u8> {
+        let mut bytes = ((content.len() + 8) as u32).to_be_bytes().to_vec();
+        bytes.extend(name);
+        bytes.extend(content);
+        bytes
+    }
+
+    #[test]
+    fn test_read_video_metadata() {
+        init();
+        let current_dir = std::env::current_dir().unwrap();
+        // 2008-10-22 14:43:21 UTC
+        let mut mvhd = vec![0, 0, 0, 0];
+        mvhd.extend(3307531401u32.to_be_bytes());
+        mvhd.extend([0; 92]);
+        let mut xyz = vec![0, 26, 0x15, 0xc7];
+        xyz.extend(b"+43.4667+011.8833+100.000/");
+        let mut keys = vec![0, 0, 0, 0, 0, 0, 0, 2];
+        for key in [APPLE_MODEL, APPLE_CREATION_DATE] {
+            keys.extend(((key.len() + 8) as u32).to_be_bytes());
+            keys.extend(b"mdta");
+            keys.extend(key.as_bytes());
+        }
+        let data = |value: &str| atom(b"data", &[&[0, 0, 0, 1, 0, 0, 0, 0], value.as_bytes()].concat());
+        let ilst = [
+            atom(&1u32.to_be_bytes(), &data("iPhone 12")),
+            atom(&2u32.to_be_bytes(), &data("2008-10-22T16:43:21+0200")),
+        ]
+        .concat();
+        let meta = [atom(b"hdlr", &[0; 24]), atom(b"keys", &keys), atom(b"ilst", &ilst)].concat();
+        let moov = [atom(b"mvhd", &mvhd), atom(b"udta", &atom(b"\xa9xyz", &xyz)), atom(b"meta", &meta)].concat();
+        // the movie atom after the media data
+        let video = [
+            atom(b"ftyp", b"qt  \0\0\0\0qt  "),
+            atom(b"mdat", &[0; 1000]),
+            atom(b"moov", &moov),
+        ]
+        .concat();
+
+        let path = Path::new("./test_read_video_metadata.mov");
+        std::fs::write(path, &video).unwrap();
+        assert!(is_quicktime(path).unwrap());
+        assert!(!is_quicktime(Path::new("data_4_tests/DSCN0025.jpg")).unwrap());
+        let video_data = read_video_metadata(path).unwrap().unwrap();
+        assert_eq!(
+            video_data.creation_time_utc,
+            NaiveDate::from_ymd_opt(2008, 10, 22).unwrap().and_hms_opt(14, 43, 21)
+        );
+        assert_eq!(video_data.creation_date.as_deref(), Some("2008-10-22T16:43:21+0200"));
+        assert_eq!(video_data.date_offset(), Some("+0200"));
+        assert_eq!(video_data.model.as_deref(), Some("iPhone 12"));
+        let gps = video_data.gps.unwrap();
+        assert_eq!((gps.latitude, gps.longitude, gps.altitude), (43.4667, 11.8833, Some(100.0)));
+
+        // a version 1 creation time out of range
+        let mut mvhd_v1 = vec![1, 0, 0, 0];
+        mvhd_v1.extend((1u64 << 60).to_be_bytes());
+        assert_eq!(parse_mvhd(&mvhd_v1), None);
+        mvhd_v1[4..12].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert_eq!(parse_mvhd(&mvhd_v1), None);
+
+        // a 64 bits size overflowing the position
+        let mut video = atom(b"ftyp", b"qt  \0\0\0\0qt  ");
+        video.extend([0, 0, 0, 1]);
+        video.extend(b"mdat");
+        video.extend(u64::MAX.to_be_bytes());
+        video.extend(atom(b"moov", &moov));
+        std::fs::write(path, &video).unwrap();
+        assert_eq!(read_moov(path).unwrap(), None);
+
+        // ensure we are in the good directory before cleanup
+        assert_eq!(current_dir, std::env::current_dir().unwrap());
+        // cleanup
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,326 @@
+//! # raw
+//!
+//! Camera RAW files. Most of them (NEF, CR2, ARW, DNG, PEF...) are TIFF files read as is ;
+//! the other ones need some help :
+//! - ORF and RW2 are TIFF files with another magic number,
+//! - RAF files embed a JPEG preview holding the EXIF data,
+//! - CR3 files store the IFDs in separate boxes (`CMT1` to `CMT4`) of the movie atom, merged
+//!   here into one TIFF structure.
+
+use crate::archives;
+use crate::quicktime;
+use exif::Exif;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+pub const RAW_EXTENSIONS: [&str; 17] = [
+    "3fr", "arw", "cr2", "cr3", "dng", "erf", "iiq", "nef", "nrw", "orf", "pef", "raf", "rw2",
+    "rwl", "sr2", "srf", "srw",
+];
+
+// the IFDs of ORF and RW2 files are at their beginning
+const TIFF_PREFIX_SIZE: u64 = 1024 * 1024;
+const RAF_MAGIC: &[u8] = b"FUJIFILMCCD-RAW ";
+// offset and length of the JPEG preview in the RAF header
+const RAF_JPEG_POINTER: u64 = 84;
+const CR3_BRAND: &[u8] = b"crx ";
+const CANON_UUID: [u8; 16] = [
+    0x85, 0xc0, 0xb6, 0x87, 0x82, 0x0f, 0x11, 0xe0, 0x81, 0x11, 0xf4, 0xce, 0x46, 0x2b, 0x6a, 0x48,
+];
+
+const EXIF_IFD_POINTER: u16 = 0x8769;
+const GPS_IFD_POINTER: u16 = 0x8825;
+const INTEROP_IFD_POINTER: u16 = 0xA005;
+const TIFF_LONG: u16 = 4;
+// IFD0, EXIF IFD, Interoperability IFD
+const MAX_IFD_DEPTH: usize = 3;
+
+/// Is the file a camera RAW file (by its extension) ?
+pub fn is_raw(path: &Path) -> bool {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .is_some_and(|e| RAW_EXTENSIONS.contains(&e.as_str()))
+}
+
+/// Read the EXIF data of the RAW files not readable as TIFF files. None for other files.
+pub fn read_raw_exif(path: &Path) -> Result<Option<Exif>, exif::Error> {
+    let mut file = archives::open(path)?;
+    let mut head = [0u8; 16];
+    if file.read_exact(&mut head).is_err() {
+        return Ok(None);
+    }
+    let magic = match &head[..4] {
+        b"IIRO" | b"IIRS" | b"IIU\0" => Some([0x2A, 0x00]),
+        b"MMOR" => Some([0x00, 0x2A]),
+        _ => None,
+    };
+    if let Some(magic) = magic {
+        log::debug!("ORF / RW2 file {:?}", path);
+        return read_patched_tiff(&mut file, magic).map(Some);
+    }
+    if head == RAF_MAGIC {
+        log::debug!("RAF file {:?}", path);
+        return read_raf(&mut file).map(Some);
+    }
+    if &head[4..8] == b"ftyp" && &head[8..12] == CR3_BRAND {
+        log::debug!("CR3 file {:?}", path);
+        let moov = quicktime::read_moov(path)?.ok_or(exif::Error::NotFound("CR3 movie atom"))?;
+        let tiff = merge_cr3_ifds(&moov).ok_or(exif::Error::NotFound("CR3 metadata boxes"))?;
+        return exif::Reader::new().read_raw(tiff).map(Some);
+    }
+    Ok(None)
+}
+
+/// Read the TIFF structure with the standard magic number. Only the beginning of the file
+/// is read, unless some values are beyond it.
+fn read_patched_tiff(file: &mut (impl Read + Seek), magic: [u8; 2]) -> Result<Exif, exif::Error> {
+    let mut tiff = Vec::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.by_ref().take(TIFF_PREFIX_SIZE).read_to_end(&mut tiff)?;
+    tiff[2..4].copy_from_slice(&magic);
+    match exif::Reader::new().read_raw(tiff.clone()) {
+        Err(exif::Error::InvalidFormat(_)) if tiff.len() as u64 == TIFF_PREFIX_SIZE => {
+            file.read_to_end(&mut tiff)?;
+            exif::Reader::new().read_raw(tiff)
+        }
+        result => result,
+    }
+}
+
+/// Read the EXIF data of the JPEG preview of a RAF file
+fn read_raf(file: &mut (impl Read + Seek)) -> Result<Exif, exif::Error> {
+    let mut pointer = [0u8; 8];
+    file.seek(SeekFrom::Start(RAF_JPEG_POINTER))?;
+    file.read_exact(&mut pointer)?;
+    let offset = u32::from_be_bytes(pointer[..4].try_into().unwrap());
+    let length = u32::from_be_bytes(pointer[4..].try_into().unwrap());
+    let mut jpeg = Vec::new();
+    file.seek(SeekFrom::Start(offset as u64))?;
+    file.by_ref().take(length as u64).read_to_end(&mut jpeg)?;
+    exif::Reader::new().read_from_container(&mut std::io::Cursor::new(jpeg))
+}
+
+/// Build one TIFF structure from the boxes of a CR3 file : CMT1 (IFD0) followed by CMT2
+/// (EXIF IFD) and CMT4 (GPS IFD), with a new IFD0 pointing to them
+fn merge_cr3_ifds(moov: &[u8]) -> Option<Vec<u8>> {
+    let (_, canon) = quicktime::atoms(moov)
+        .into_iter()
+        .find(|(atom, content)| *atom == b"uuid" && content.starts_with(&CANON_UUID))?;
+    let boxes = quicktime::atoms(&canon[CANON_UUID.len()..]);
+    let find = |name: &[u8]| boxes.iter().find(|(atom, _)| *atom == name).map(|(_, content)| *content);
+    let cmt1 = find(b"CMT1")?;
+    let order = ByteOrder::of(cmt1)?;
+
+    let mut tiff = cmt1.to_vec();
+    let mut entries = order.ifd_entries(cmt1, order.u32(cmt1, 4)? as usize)?;
+    for (pointer, name) in [(EXIF_IFD_POINTER, b"CMT2"), (GPS_IFD_POINTER, b"CMT4")] {
+        let Some(block) = find(name).filter(|b| ByteOrder::of(b) == Some(order)) else {
+            continue;
+        };
+        // IFDs start on a word boundary
+        if tiff.len() % 2 == 1 {
+            tiff.push(0);
+        }
+        let base = tiff.len() as u32;
+        let mut block = block.to_vec();
+        let ifd = order.u32(&block, 4)?;
+        order.relocate_ifd(&mut block, ifd as usize, base, &mut Vec::new())?;
+        tiff.extend(block);
+        entries.retain(|entry| order.u16(entry, 0) != Some(pointer));
+        entries.push(order.long_entry(pointer, base + ifd));
+    }
+    entries.sort_by_key(|entry| order.u16(entry, 0));
+
+    if tiff.len() % 2 == 1 {
+        tiff.push(0);
+    }
+    let ifd0 = tiff.len() as u32;
+    tiff.extend(order.u16_bytes(entries.len() as u16));
+    tiff.extend(entries.concat());
+    tiff.extend([0; 4]);
+    tiff[4..8].copy_from_slice(&order.u32_bytes(ifd0));
+    Some(tiff)
+}
+
+/// Byte order of a TIFF structure
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ByteOrder {
+    LittleEndian,
+    BigEndian,
+}
+
+impl ByteOrder {
+    pub fn of(tiff: &[u8]) -> Option<ByteOrder> {
+        match tiff.get(..2)? {
+            b"II" => Some(ByteOrder::LittleEndian),
+            b"MM" => Some(ByteOrder::BigEndian),
+            _ => None,
+        }
+    }
+
+    pub fn u16(&self, data: &[u8], at: usize) -> Option<u16> {
+        let bytes = data.get(at..at + 2)?.try_into().ok()?;
+        Some(match self {
+            ByteOrder::LittleEndian => u16::from_le_bytes(bytes),
+            ByteOrder::BigEndian => u16::from_be_bytes(bytes),
+        })
+    }
+
+    pub fn u32(&self, data: &[u8], at: usize) -> Option<u32> {
+        let bytes = data.get(at..at + 4)?.try_into().ok()?;
+        Some(match self {
+            ByteOrder::LittleEndian => u32::from_le_bytes(bytes),
+            ByteOrder::BigEndian => u32::from_be_bytes(bytes),
+        })
+    }
+
+    fn u16_bytes(&self, value: u16) -> [u8; 2] {
+        match self {
+            ByteOrder::LittleEndian => value.to_le_bytes(),
+            ByteOrder::BigEndian => value.to_be_bytes(),
+        }
+    }
+
+    fn u32_bytes(&self, value: u32) -> [u8; 4] {
+        match self {
+            ByteOrder::LittleEndian => value.to_le_bytes(),
+            ByteOrder::BigEndian => value.to_be_bytes(),
+        }
+    }
+
+    fn long_entry(&self, tag: u16, value: u32) -> Vec<u8> {
+        [&self.u16_bytes(tag)[..], &self.u16_bytes(TIFF_LONG), &self.u32_bytes(1), &self.u32_bytes(value)].concat()
+    }
+
+    /// The 12 bytes entries of an IFD
+    fn ifd_entries(&self, tiff: &[u8], ifd: usize) -> Option<Vec<Vec<u8>>> {
+        let count = self.u16(tiff, ifd)? as usize;
+        (0..count)
+            .map(|i| tiff.get(ifd + 2 + i * 12..ifd + 14 + i * 12).map(|e| e.to_vec()))
+            .collect()
+    }
+
+    /// Add `base` to the offsets of an IFD (and of its sub-IFDs) moved `base` bytes further.
+    /// `visited` are the IFDs already relocated : None on loops, or on sub-IFDs nested too deep.
+    fn relocate_ifd(&self, tiff: &mut [u8], ifd: usize, base: u32, visited: &mut Vec<usize>) -> Option<()> {
+        if visited.contains(&ifd) || visited.len() >= MAX_IFD_DEPTH {
+            log::warn!("loop or too many nested IFDs at offset {}", ifd);
+            return None;
+        }
+        visited.push(ifd);
+        let count = self.u16(tiff, ifd)? as usize;
+        for i in 0..count {
+            let entry = ifd + 2 + i * 12;
+            let (tag, kind, count) = (self.u16(tiff, entry)?, self.u16(tiff, entry + 2)?, self.u32(tiff, entry + 4)?);
+            let value = self.u32(tiff, entry + 8)?;
+            let is_sub_ifd = matches!(tag, EXIF_IFD_POINTER | GPS_IFD_POINTER | INTEROP_IFD_POINTER);
+            if is_sub_ifd {
+                self.relocate_ifd(tiff, value as usize, base, visited)?;
+            }
+            // values bigger than 4 bytes are stored at an offset
+            if is_sub_ifd || type_size(kind) * count as u64 > 4 {
+                tiff.get_mut(entry + 8..entry + 12)?.copy_from_slice(&self.u32_bytes(value.checked_add(base)?));
+            }
+        }
+        // the next IFDs (thumbnails) are not needed
+        tiff.get_mut(ifd + 2 + count * 12..ifd + 6 + count * 12)?.copy_from_slice(&[0; 4]);
+        Some(())
+    }
+}
+
+/// Size in bytes of a TIFF field type
+fn type_size(kind: u16) -> u64 {
+    match kind {
+        3 | 8 => 2,
+        4 | 9 | 11 | 13 => 4,
+        5 | 10 | 12 => 8,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exif::{In, Tag};
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    /// Little endian TIFF with one IFD0 of ASCII entries
+    fn tiff(entries: &[(u16, &str)]) -> Vec<u8> {
+        let mut tiff = b"II\x2A\x00\x08\x00\x00\x00".to_vec();
+        let values_offset = 8 + 2 + entries.len() * 12 + 4;
+        let mut values = Vec::new();
+        tiff.extend((entries.len() as u16).to_le_bytes());
+        for (tag, value) in entries {
+            tiff.extend(tag.to_le_bytes());
+            tiff.extend(2u16.to_le_bytes());
+            tiff.extend((value.len() as u32 + 1).to_le_bytes());
+            tiff.extend(((values_offset + values.len()) as u32).to_le_bytes());
+            values.extend(value.as_bytes());
+            values.push(0);
+        }
+        tiff.extend([0; 4]);
+        tiff.extend(values);
+        tiff
+    }
+
+    fn atom(name: &[u8], content: &[u8]) -> Vec<u8> {
+        let mut bytes = ((content.len() + 8) as u32).to_be_bytes().to_vec();
+        bytes.extend(name);
+        bytes.extend(content);
+        bytes
+    }
+
+    #[test]
+    fn test_read_raw_exif() {
+        init();
+        let current_dir = std::env::current_dir().unwrap();
+        assert!(is_raw(Path::new("photos/DSC_0001.NEF")));
+        assert!(!is_raw(Path::new("photos/DSC_0001.jpg")));
+
+        let dir = Path::new("./test_read_raw_exif");
+        std::fs::create_dir(dir).unwrap();
+        // Panasonic RW2 : TIFF with the magic number 0x55
+        let mut rw2 = tiff(&[(0x010F, "Panasonic"), (0x0110, "DC-G9")]);
+        rw2[2] = 0x55;
+        std::fs::write(dir.join("P1000001.RW2"), &rw2).unwrap();
+        let exif = read_raw_exif(&dir.join("P1000001.RW2")).unwrap().unwrap();
+        assert_eq!(exif.get_field(Tag::Model, In::PRIMARY).unwrap().display_value().to_string(), "\"DC-G9\"");
+
+        // Canon CR3 : IFD0 and EXIF IFD in separate boxes
+        let cmt1 = tiff(&[(0x010F, "Canon"), (0x0110, "Canon EOS R5")]);
+        let cmt2 = tiff(&[(0x9003, "2021:03:04 10:11:12")]);
+        let canon = [&CANON_UUID[..], &atom(b"CMT1", &cmt1), &atom(b"CMT2", &cmt2)].concat();
+        let cr3 = [atom(b"ftyp", b"crx \0\0\0\x01crx isom"), atom(b"moov", &atom(b"uuid", &canon))].concat();
+        std::fs::write(dir.join("IMG_0001.CR3"), &cr3).unwrap();
+        let exif = read_raw_exif(&dir.join("IMG_0001.CR3")).unwrap().unwrap();
+        assert_eq!(
+            exif.get_field(Tag::DateTimeOriginal, In::PRIMARY).unwrap().display_value().to_string(),
+            "2021-03-04 10:11:12"
+        );
+        assert_eq!(exif.get_field(Tag::Make, In::PRIMARY).unwrap().display_value().to_string(), "\"Canon\"");
+
+        assert!(read_raw_exif(Path::new("data_4_tests/DSCN0025.jpg")).unwrap().is_none());
+
+        // an EXIF IFD pointing back to IFD0
+        let mut looping = tiff(&[]);
+        looping[8] = 1;
+        looping.extend([0; 12]);
+        looping[10..22].copy_from_slice(&[0x69, 0x87, 4, 0, 1, 0, 0, 0, 8, 0, 0, 0]);
+        let order = ByteOrder::LittleEndian;
+        assert_eq!(order.relocate_ifd(&mut looping, 8, 100, &mut Vec::new()), None);
+        // an offset overflowing once moved
+        looping[18..22].copy_from_slice(&u32::MAX.to_le_bytes());
+        looping[10..12].copy_from_slice(&0x010Fu16.to_le_bytes());
+        looping[12] = 2;
+        looping[14] = 5;
+        assert_eq!(order.relocate_ifd(&mut looping, 8, 100, &mut Vec::new()), None);
+
+        // ensure we are in the good directory before cleanup
+        assert_eq!(current_dir, std::env::current_dir().unwrap());
+        // cleanup
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,204 @@
+//! # rename
+//!
+//! Names of the sorted files, from a template : `{date:%Y%m%d_%H%M%S}_{device}_{seq}.{ext}`.
+//! Placeholders :
+//! - `{date}` or `{date:FORMAT}` : capture time, with the chrono format (`%Y%m%d_%H%M%S`
+//!   by default, `%.3f` for the milliseconds of bursts),
+//! - `{device}` : model of the device,
+//! - `{seq}` or `{seq:WIDTH}` : number of the file among the ones with the same name, in
+//!   the order of capture (3 digits by default, from 1 to 9),
+//! - `{name}` : original name, without the extension,
+//! - `{ext}` : original extension.
+
+use crate::exif::ExifData;
+use chrono::NaiveDateTime;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::fmt::Write;
+use std::path::Path;
+
+const DEFAULT_DATE_FORMAT: &str = "%Y%m%d_%H%M%S";
+const DEFAULT_SEQ_WIDTH: usize = 3;
+const MAX_SEQ_WIDTH: usize = 9;
+const UNKNOWN_DEVICE: &str = "Unknown";
+
+// characters not allowed in a device name
+static DEVICE_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"[^\w\-]+").unwrap());
+
+/// Case of the extensions of the sorted files
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum ExtensionCase {
+    /// As in the original name
+    #[default]
+    Keep,
+    Lower,
+    Upper,
+}
+
+impl ExtensionCase {
+    pub fn apply(&self, extension: &str) -> String {
+        match self {
+            ExtensionCase::Keep => extension.to_string(),
+            ExtensionCase::Lower => extension.to_lowercase(),
+            ExtensionCase::Upper => extension.to_uppercase(),
+        }
+    }
+
+    /// Apply to the extensions of a suffix added to a base name (`.CR2.xmp`, `_1.MOV`)
+    pub fn apply_to_suffix(&self, suffix: &str) -> String {
+        match suffix.find('.') {
+            Some(dot) => format!("{}{}", &suffix[..dot], self.apply(&suffix[dot..])),
+            None => suffix.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Text(String),
+    Date(String),
+    Device,
+    Seq(usize),
+    Name,
+    Ext,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenameTemplate(Vec<Token>);
+
+impl std::str::FromStr for RenameTemplate {
+    type Err = String;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let mut tokens = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                tokens.push(Token::Text(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .ok_or(format!("unclosed placeholder in '{}'", template))?
+                + start;
+            let placeholder = &rest[start + 1..end];
+            let (name, argument) = match placeholder.split_once(':') {
+                Some((name, argument)) => (name, Some(argument)),
+                None => (placeholder, None),
+            };
+            tokens.push(match (name, argument) {
+                ("date", format) => {
+                    let format = format.unwrap_or(DEFAULT_DATE_FORMAT);
+                    // a capture time has no offset : %z, %Z... fail only when formatted
+                    let mut probe = String::new();
+                    if write!(probe, "{}", NaiveDateTime::default().format(format)).is_err() {
+                        return Err(format!("invalid date format '{}'", format));
+                    }
+                    Token::Date(format.to_string())
+                }
+                ("device", None) => Token::Device,
+                ("seq", width) => Token::Seq(match width {
+                    Some(width) => width
+                        .parse()
+                        .ok()
+                        .filter(|width| (1..=MAX_SEQ_WIDTH).contains(width))
+                        .ok_or_else(|| format!("invalid sequence width '{}', from 1 to {}", width, MAX_SEQ_WIDTH))?,
+                    None => DEFAULT_SEQ_WIDTH,
+                }),
+                ("name", None) => Token::Name,
+                ("ext", None) => Token::Ext,
+                _ => return Err(format!("unknown placeholder '{{{}}}'", placeholder)),
+            });
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            tokens.push(Token::Text(rest.to_string()));
+        }
+        if !tokens.contains(&Token::Ext) {
+            return Err(String::from("the template must keep the extension with {ext}"));
+        }
+        Ok(RenameTemplate(tokens))
+    }
+}
+
+impl RenameTemplate {
+    pub fn has_seq(&self) -> bool {
+        self.0.iter().any(|token| matches!(token, Token::Seq(_)))
+    }
+
+    /// Name of a file copied in the sorted tree. The sequence number is left out when it is
+    /// not given. None if the file has no capture time.
+    pub fn render(&self, exif_data: &ExifData, original: &Path, seq: Option<usize>, case: ExtensionCase) -> Option<String> {
+        let capture_time = exif_data.capture_time_precise()?;
+        let name: String = self
+            .0
+            .iter()
+            .map(|token| match token {
+                Token::Text(text) => text.clone(),
+                Token::Date(format) => capture_time.format(format).to_string(),
+                Token::Device => device_name(exif_data),
+                Token::Seq(width) => seq.map(|seq| format!("{:0width$}", seq, width = width)).unwrap_or_default(),
+                Token::Name => original.file_stem().unwrap_or_default().to_string_lossy().to_string(),
+                Token::Ext => case.apply(&original.extension().unwrap_or_default().to_string_lossy()),
+            })
+            .collect();
+        // dates formatted with separators of paths or times
+        Some(name.replace(['/', '\\', ':'], "-"))
+    }
+}
+
+/// Model of the device, usable in a file name
+fn device_name(exif_data: &ExifData) -> String {
+    let model = exif_data.model.as_deref().unwrap_or(UNKNOWN_DEVICE);
+    DEVICE_REGEX.replace_all(model.trim(), "-").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    #[test]
+    fn test_rename_template() {
+        init();
+        let template: RenameTemplate = "{date:%Y%m%d_%H%M%S}_{device}_{seq}.{ext}".parse().unwrap();
+        assert!(template.has_seq());
+        let exif_data = ExifData {
+            capture_time: NaiveDate::from_ymd_opt(2008, 10, 22).unwrap().and_hms_opt(16, 43, 21),
+            capture_subsec: Some(450_000_000),
+            model: Some(String::from("COOLPIX P6000")),
+            ..Default::default()
+        };
+        let original = Path::new("photos/DSCN0025.JPG");
+        assert_eq!(
+            template.render(&exif_data, original, Some(2), ExtensionCase::Lower).as_deref(),
+            Some("20081022_164321_COOLPIX-P6000_002.jpg")
+        );
+        assert_eq!(
+            template.render(&exif_data, original, None, ExtensionCase::Keep).as_deref(),
+            Some("20081022_164321_COOLPIX-P6000_.JPG")
+        );
+        let template: RenameTemplate = "{date:%H:%M:%S%.3f} {name}.{ext}".parse().unwrap();
+        assert_eq!(
+            template.render(&exif_data, original, None, ExtensionCase::Upper).as_deref(),
+            Some("16-43-21.450 DSCN0025.JPG")
+        );
+        assert_eq!(template.render(&ExifData::default(), original, None, ExtensionCase::Keep), None);
+
+        assert!("{date}_{seq:x}.{ext}".parse::<RenameTemplate>().is_err());
+        assert!("{date}_{seq:0}.{ext}".parse::<RenameTemplate>().is_err());
+        assert!("{date}_{seq:10}.{ext}".parse::<RenameTemplate>().is_err());
+        assert!("{date}_{seq:9}.{ext}".parse::<RenameTemplate>().is_ok());
+        for format in ["%Q", "%z", "%:z", "%Z", "%#z"] {
+            assert!(format!("{{date:{}}}.{{ext}}", format).parse::<RenameTemplate>().is_err(), "{}", format);
+        }
+        assert!("{date}_{camera}.{ext}".parse::<RenameTemplate>().is_err());
+        assert!("{date}_{seq".parse::<RenameTemplate>().is_err());
+        assert!("{date}_{seq}".parse::<RenameTemplate>().is_err());
+        assert_eq!(ExtensionCase::Lower.apply_to_suffix(".CR2.XMP"), ".cr2.xmp");
+        assert_eq!(ExtensionCase::Upper.apply_to_suffix("_edit.jpg"), "_edit.JPG");
+    }
+}
@@ -1,3 +1,4 @@
+use crate::shutdown;
 use once_cell::sync::Lazy;
 use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
@@ -13,34 +14,38 @@ static NB_UNSORTED_IMAGES: AtomicU32 = AtomicU32::new(0);
 static NB_ERROR_ON_IMAGES: AtomicU32 = AtomicU32::new(0);
 static NB_DUPLICATES_RENAMED: AtomicU32 = AtomicU32::new(0);
 static NB_NOT_IMAGES: AtomicU32 = AtomicU32::new(0);
+static NB_VIEW_LINKS: AtomicU32 = AtomicU32::new(0);
+static NB_SUSPICIOUS_DATES: AtomicU32 = AtomicU32::new(0);
+static NB_EVENTS: AtomicU32 = AtomicU32::new(0);
+static NB_TRIPS: AtomicU32 = AtomicU32::new(0);
+static NB_CALENDAR_EVENTS: AtomicU32 = AtomicU32::new(0);
+static NB_SORTED_VIDEOS: AtomicU32 = AtomicU32::new(0);
+static NB_SORTED_RAW: AtomicU32 = AtomicU32::new(0);
+static NB_SIDECARS: AtomicU32 = AtomicU32::new(0);
+static NB_LIVE_PHOTOS: AtomicU32 = AtomicU32::new(0);
+static NB_MOTION_VIDEOS: AtomicU32 = AtomicU32::new(0);
+static NB_TAKEOUT_SIDECARS: AtomicU32 = AtomicU32::new(0);
+static NB_ARCHIVES: AtomicU32 = AtomicU32::new(0);
+static NB_ARCHIVE_ENTRIES: AtomicU32 = AtomicU32::new(0);
+static NB_CLASSIFIED: AtomicU32 = AtomicU32::new(0);
+static NB_THUMBNAILS: AtomicU32 = AtomicU32::new(0);
 
 // Complex data structures that still need RwLock
+#[derive(Default)]
 pub struct Reporting {
     start_time: Option<Instant>,
     places_found: HashMap<String, u32>,
     devices_found: HashSet<String>,
     errors_details: Vec<(PathBuf, String)>,
+    suspicious_dates_details: Vec<(PathBuf, String)>,
+    /// Overlapping calendar events, and the number of photos taken during both
+    calendar_overlaps: Vec<(String, String, u32)>,
     oldest_date: Option<String>,
     newest_date: Option<String>,
     source_files_count: Option<u64>,
     target_files_count: Option<u64>,
 }
 
-impl Default for Reporting {
-    fn default() -> Self {
-        Self {
-            start_time: None,
-            places_found: HashMap::new(),
-            devices_found: HashSet::new(),
-            errors_details: Vec::new(),
-            oldest_date: None,
-            newest_date: None,
-            source_files_count: None,
-            target_files_count: None,
-        }
-    }
-}
-
 // TODO anti-pattern to have a static variable?
 static REPORTING_WRAPPER: Lazy<RwLock<Reporting>> = Lazy::new(|| RwLock::new(Reporting::default()));
 
@@ -55,15 +60,72 @@ impl Reporting {
         NB_SORTED_IMAGES.fetch_add(1, Ordering::Relaxed);
     }
 
+    /// A sorted file is a video (counted in the sorted images too)
+    pub fn video_sorted() {
+        NB_SORTED_VIDEOS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A sorted file is a camera RAW file (counted in the sorted images too)
+    pub fn raw_sorted() {
+        NB_SORTED_RAW.fetch_add(1, Ordering::Relaxed);
+    }
+
     pub fn image_processed_unsorted() {
         NB_IMAGES.fetch_add(1, Ordering::Relaxed);
         NB_UNSORTED_IMAGES.fetch_add(1, Ordering::Relaxed);
     }
 
+    /// An image has been copied in the bucket of its class (screenshots...)
+    pub fn image_processed_classified() {
+        NB_IMAGES.fetch_add(1, Ordering::Relaxed);
+        NB_CLASSIFIED.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// An image smaller than the minimum number of megapixels has not been copied : a file
+    /// of the source directory without a target file
+    pub fn thumbnail_skipped() {
+        NB_THUMBNAILS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn image_processed_suspicious_date(file: PathBuf, reason: String) {
+        NB_IMAGES.fetch_add(1, Ordering::Relaxed);
+        NB_SUSPICIOUS_DATES.fetch_add(1, Ordering::Relaxed);
+        let mut r = REPORTING_WRAPPER.write().unwrap();
+        r.suspicious_dates_details.push((file, reason));
+    }
+
     pub fn not_image_processed() {
         NB_NOT_IMAGES.fetch_add(1, Ordering::Relaxed);
     }
 
+    pub fn sidecar_processed() {
+        NB_SIDECARS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A Live Photo video has been copied next to its still
+    pub fn live_photo_paired() {
+        NB_LIVE_PHOTOS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The video of a Motion Photo has been written next to it : a file of the target
+    /// directory without a source file
+    pub fn motion_video_extracted() {
+        NB_MOTION_VIDEOS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A Google Takeout JSON sidecar has not been copied : a file of the source directory
+    /// without a target file
+    pub fn takeout_sidecar_skipped() {
+        NB_TAKEOUT_SIDECARS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The files of an archive have been sorted : a file of the source directory for
+    /// `entries` files of the target directory
+    pub fn archive_expanded(entries: u32) {
+        NB_ARCHIVES.fetch_add(1, Ordering::Relaxed);
+        NB_ARCHIVE_ENTRIES.fetch_add(entries, Ordering::Relaxed);
+    }
+
     pub fn directory_processed() {
         NB_DIRECTORIES.fetch_add(1, Ordering::Relaxed);
     }
@@ -76,6 +138,27 @@ impl Reporting {
         NB_DUPLICATES_RENAMED.fetch_add(1, Ordering::Relaxed);
     }
 
+    pub fn view_link_created() {
+        NB_VIEW_LINKS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_events_count(count: u32) {
+        NB_EVENTS.store(count, Ordering::Relaxed);
+    }
+
+    pub fn set_trips_count(count: u32) {
+        NB_TRIPS.store(count, Ordering::Relaxed);
+    }
+
+    pub fn set_calendar_events_count(count: u32) {
+        NB_CALENDAR_EVENTS.store(count, Ordering::Relaxed);
+    }
+
+    pub fn add_calendar_overlap(first: String, second: String, photos: u32) {
+        let mut r = REPORTING_WRAPPER.write().unwrap();
+        r.calendar_overlaps.push((first, second, photos));
+    }
+
     pub fn add_place(place: String) {
         let mut r = REPORTING_WRAPPER.write().unwrap();
         *r.places_found.entry(place).or_insert(0) += 1;
@@ -95,12 +178,12 @@ impl Reporting {
         let mut r = REPORTING_WRAPPER.write().unwrap();
 
         // Update oldest
-        if r.oldest_date.is_none() || r.oldest_date.as_ref().map(|s| s.as_str()) > Some(date) {
+        if r.oldest_date.is_none() || r.oldest_date.as_deref() > Some(date) {
             r.oldest_date = Some(date.to_string());
         }
 
         // Update newest
-        if r.newest_date.is_none() || r.newest_date.as_ref().map(|s| s.as_str()) < Some(date) {
+        if r.newest_date.is_none() || r.newest_date.as_deref() < Some(date) {
             r.newest_date = Some(date.to_string());
         }
     }
@@ -124,6 +207,21 @@ impl Reporting {
         NB_ERROR_ON_IMAGES.store(0, Ordering::Relaxed);
         NB_DUPLICATES_RENAMED.store(0, Ordering::Relaxed);
         NB_NOT_IMAGES.store(0, Ordering::Relaxed);
+        NB_VIEW_LINKS.store(0, Ordering::Relaxed);
+        NB_SUSPICIOUS_DATES.store(0, Ordering::Relaxed);
+        NB_EVENTS.store(0, Ordering::Relaxed);
+        NB_TRIPS.store(0, Ordering::Relaxed);
+        NB_CALENDAR_EVENTS.store(0, Ordering::Relaxed);
+        NB_SORTED_VIDEOS.store(0, Ordering::Relaxed);
+        NB_SORTED_RAW.store(0, Ordering::Relaxed);
+        NB_SIDECARS.store(0, Ordering::Relaxed);
+        NB_LIVE_PHOTOS.store(0, Ordering::Relaxed);
+        NB_MOTION_VIDEOS.store(0, Ordering::Relaxed);
+        NB_TAKEOUT_SIDECARS.store(0, Ordering::Relaxed);
+        NB_ARCHIVES.store(0, Ordering::Relaxed);
+        NB_ARCHIVE_ENTRIES.store(0, Ordering::Relaxed);
+        NB_CLASSIFIED.store(0, Ordering::Relaxed);
+        NB_THUMBNAILS.store(0, Ordering::Relaxed);
 
         // Reset complex structures
         let mut r = REPORTING_WRAPPER.write().unwrap();
@@ -131,6 +229,8 @@ impl Reporting {
         r.places_found.clear();
         r.devices_found.clear();
         r.errors_details.clear();
+        r.suspicious_dates_details.clear();
+        r.calendar_overlaps.clear();
         r.oldest_date = None;
         r.newest_date = None;
         r.source_files_count = None;
@@ -148,6 +248,21 @@ impl Reporting {
         let nb_error_on_images = NB_ERROR_ON_IMAGES.load(Ordering::Relaxed);
         let nb_duplicates_renamed = NB_DUPLICATES_RENAMED.load(Ordering::Relaxed);
         let nb_not_images = NB_NOT_IMAGES.load(Ordering::Relaxed);
+        let nb_view_links = NB_VIEW_LINKS.load(Ordering::Relaxed);
+        let nb_suspicious_dates = NB_SUSPICIOUS_DATES.load(Ordering::Relaxed);
+        let nb_events = NB_EVENTS.load(Ordering::Relaxed);
+        let nb_trips = NB_TRIPS.load(Ordering::Relaxed);
+        let nb_calendar_events = NB_CALENDAR_EVENTS.load(Ordering::Relaxed);
+        let nb_sorted_videos = NB_SORTED_VIDEOS.load(Ordering::Relaxed);
+        let nb_sorted_raw = NB_SORTED_RAW.load(Ordering::Relaxed);
+        let nb_sidecars = NB_SIDECARS.load(Ordering::Relaxed);
+        let nb_live_photos = NB_LIVE_PHOTOS.load(Ordering::Relaxed);
+        let nb_motion_videos = NB_MOTION_VIDEOS.load(Ordering::Relaxed);
+        let nb_takeout_sidecars = NB_TAKEOUT_SIDECARS.load(Ordering::Relaxed);
+        let nb_archives = NB_ARCHIVES.load(Ordering::Relaxed);
+        let nb_archive_entries = NB_ARCHIVE_ENTRIES.load(Ordering::Relaxed);
+        let nb_classified = NB_CLASSIFIED.load(Ordering::Relaxed);
+        let nb_thumbnails = NB_THUMBNAILS.load(Ordering::Relaxed);
 
         // Calculate execution time
         let duration = r.start_time.map(|start| start.elapsed());
@@ -183,18 +298,63 @@ impl Reporting {
         println!("╔═══════════════════════════════════════════════════════════╗");
         println!("║              📸 Image Sorting Report                      ║");
         println!("╠═══════════════════════════════════════════════════════════╣");
+        if shutdown::is_interrupted() {
+            println!("║ ⛔ INCOMPLETE : the run has been interrupted              ║");
+        }
         println!("║ ⏱️  Execution time         : {:<29}║", duration_str);
         println!("║ 📁 Directories processed   : {:<29}║", nb_directories);
         println!("║ 🖼️  Images processed        : {:<29}║", nb_images);
         println!("║                                                            ║");
         println!("║ ✅ Successfully sorted     : {} ({:.1}%){:>17}║",
             nb_sorted_images, sorted_pct, "");
+        if nb_sorted_videos > 0 {
+            println!("║    🎬 of which videos      : {:<29}║", nb_sorted_videos);
+        }
+        if nb_sorted_raw > 0 {
+            println!("║    🎞️  of which RAW         : {:<29}║", nb_sorted_raw);
+        }
         println!("║ ⚠️  Unsorted (no EXIF)     : {} ({:.1}%){:>17}║",
             nb_unsorted_images, unsorted_pct, "");
+        if nb_classified > 0 {
+            println!("║ 🗃️  Classified in buckets   : {:<29}║", nb_classified);
+        }
+        if nb_thumbnails > 0 {
+            println!("║ 🔍 Thumbnails skipped      : {:<29}║", nb_thumbnails);
+        }
+        if nb_suspicious_dates > 0 {
+            println!("║ 🕰️  Suspicious dates        : {:<29}║", nb_suspicious_dates);
+        }
         println!("║ 🔁 Duplicates renamed      : {:<29}║", nb_duplicates_renamed);
         println!("║ ❌ Errors                  : {} ({:.1}%){:>17}║",
             nb_error_on_images, error_pct, "");
         println!("║ 📄 Non-image files         : {:<29}║", nb_not_images);
+        if nb_sidecars > 0 {
+            println!("║ 📎 Sidecars with images    : {:<29}║", nb_sidecars);
+        }
+        if nb_live_photos > 0 {
+            println!("║ 📱 Live Photos paired      : {:<29}║", nb_live_photos);
+        }
+        if nb_motion_videos > 0 {
+            println!("║ 🎥 Motion videos extracted : {:<29}║", nb_motion_videos);
+        }
+        if nb_archives > 0 {
+            println!("║ 🗜️  Archives expanded       : {} ({} files){:>17}║", nb_archives, nb_archive_entries, "");
+        }
+        if nb_takeout_sidecars > 0 {
+            println!("║ 🗂️  Takeout JSON read       : {:<29}║", nb_takeout_sidecars);
+        }
+        if nb_view_links > 0 {
+            println!("║ 🔗 View links created      : {:<29}║", nb_view_links);
+        }
+        if nb_events > 0 {
+            println!("║ 🎉 Events                  : {:<29}║", nb_events);
+        }
+        if nb_trips > 0 {
+            println!("║ 🧳 Trips                   : {:<29}║", nb_trips);
+        }
+        if nb_calendar_events > 0 {
+            println!("║ 📆 Calendar events         : {:<29}║", nb_calendar_events);
+        }
 
         // Display file counts and integrity check
         if let (Some(source), Some(target)) = (r.source_files_count, r.target_files_count) {
@@ -203,14 +363,16 @@ impl Reporting {
             println!("║    Source directory        : {:<29}║", source);
             println!("║    Target directory        : {:<29}║", target);
 
-            if source == target {
+            // the extracted Motion Photo videos have no source file, the Takeout sidecars and
+            // the skipped thumbnails have no target file, and an archive has the files it holds
+            let expected = source + nb_motion_videos as u64 + nb_archive_entries as u64
+                - nb_takeout_sidecars as u64
+                - nb_thumbnails as u64
+                - nb_archives as u64;
+            if expected == target {
                 println!("║    ✅ Integrity check       : All files accounted for     ║");
             } else {
-                let diff = if source > target {
-                    source - target
-                } else {
-                    target - source
-                };
+                let diff = expected.abs_diff(target);
                 println!("║    ⚠️  Integrity check      : {} file(s) difference{:>11}║",
                     diff, "");
             }
@@ -252,18 +414,35 @@ impl Reporting {
         }
 
         // Display date range
-        if r.oldest_date.is_some() && r.newest_date.is_some() {
+        if let (Some(oldest_date), Some(newest_date)) = (&r.oldest_date, &r.newest_date) {
             println!("║                                                            ║");
-            let date_range = format!(
-                "{} → {}",
-                r.oldest_date.as_ref().unwrap(),
-                r.newest_date.as_ref().unwrap()
-            );
+            let date_range = format!("{} → {}", oldest_date, newest_date);
             println!("║ 📅 Date range              : {:<29}║", date_range);
         }
 
         println!("╚═══════════════════════════════════════════════════════════╝");
 
+        // Display rejected dates if any
+        if !r.suspicious_dates_details.is_empty() {
+            println!();
+            println!(
+                "🕰️  {} image(s) with a suspicious date (showing first 10):",
+                r.suspicious_dates_details.len()
+            );
+            for (file, reason) in r.suspicious_dates_details.iter().take(10) {
+                println!("  • {}: {}", file.display(), reason);
+            }
+        }
+
+        // Display overlapping calendar events if any
+        if !r.calendar_overlaps.is_empty() {
+            println!();
+            println!("📆 Overlapping calendar events (the shortest one is used):");
+            for (first, second, photos) in &r.calendar_overlaps {
+                println!("  • {} / {}: {} photo(s) taken during both", first, second, photos);
+            }
+        }
+
         // Display error details if any
         if !r.errors_details.is_empty() && r.errors_details.len() <= 10 {
             println!();
@@ -0,0 +1,30 @@
+//! # shutdown
+//!
+//! Graceful handling of SIGINT / SIGTERM : the first signal only asks the run to stop
+//! scheduling new files (files being copied are finished), a second one ends the process.
+
+use crate::manifest::Manifest;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Install the SIGINT / SIGTERM handler
+pub fn install_handler() {
+    let r = ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            eprintln!("\nSecond interruption, exiting immediately");
+            Manifest::try_flush();
+            std::process::exit(130);
+        }
+        log::warn!("Interruption requested, finishing the files being copied");
+        eprintln!("\nInterrupted : finishing the files being copied (interrupt again to exit immediately) ...");
+    });
+    if let Err(e) = r {
+        log::warn!("Unable to install the interruption handler : {:?}", e);
+    }
+}
+
+/// Has an interruption been requested ?
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
@@ -0,0 +1,217 @@
+//! # takeout
+//!
+//! Google Photos Takeout exports strip the metadata of many files, but write them in a JSON
+//! sidecar next to each file : `IMG_0001.JPG.json`, or `IMG_0001.JPG.supplemental-metadata.json`
+//! in the recent exports. Takeout cuts the long names of these sidecars, moves the counter of
+//! duplicated names (`IMG_0001(1).JPG` has `IMG_0001.JPG(1).json`), and the edited copies
+//! (`IMG_0001-edited.JPG`) share the sidecar of the original.
+
+use crate::archives;
+use crate::exif::GpsPosition;
+use chrono::{DateTime, NaiveDateTime};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const SUPPLEMENTAL_METADATA: &str = ".supplemental-metadata";
+const EDITED_SUFFIX: &str = "-edited";
+// Takeout cuts the names of the sidecars at about 51 characters with the `.json` extension,
+// the exact length varies between exports
+const TRUNCATED_LENGTHS: std::ops::RangeInclusive<usize> = 43..=47;
+// sidecars are small, the larger JSON files are not sidecars
+const MAX_SIDECAR_SIZE: u64 = 1024 * 1024;
+
+// counter added by Takeout to duplicated names : `IMG_0001(1).JPG`
+static COUNTER_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(.*)(\(\d+\))(\.[^.]*)?$").unwrap());
+
+// names of the JSON files of each directory, listed once for all its files
+static JSON_FILES_CACHE: Lazy<Mutex<HashMap<PathBuf, Arc<HashSet<String>>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Metadata found in a Takeout sidecar
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TakeoutData {
+    /// photoTakenTime, in UTC
+    pub photo_taken_time: Option<NaiveDateTime>,
+    /// geoData, or geoDataExif
+    pub gps: Option<GpsPosition>,
+}
+
+/// Read the Takeout sidecar of a file, if any
+pub fn read_takeout(path: &Path) -> std::io::Result<Option<TakeoutData>> {
+    let Some(sidecar) = find_sidecar(path) else {
+        return Ok(None);
+    };
+    log::debug!("Takeout sidecar {:?} found for {:?}", sidecar, path);
+    Ok(parse_sidecar(&String::from_utf8_lossy(&archives::read(&sidecar)?)))
+}
+
+/// A JSON file written by Takeout for a photo or a video, which is not copied : its
+/// metadata are read with the file it describes
+pub fn is_takeout_sidecar(path: &Path) -> bool {
+    let is_json = path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("json"));
+    let is_small = archives::len(path).is_ok_and(|len| len <= MAX_SIDECAR_SIZE);
+    is_json
+        && is_small
+        && archives::read(path)
+            .ok()
+            .and_then(|content| serde_json::from_slice::<Value>(&content).ok())
+            .is_some_and(|json| json.get("photoTakenTime").is_some())
+}
+
+/// Find the sidecar of a file, trying the names Takeout may have given it
+pub fn find_sidecar(path: &Path) -> Option<PathBuf> {
+    let parent = path.parent()?;
+    let json_files = json_files(parent);
+    if json_files.is_empty() {
+        return None;
+    }
+    sidecar_names(&path.file_name()?.to_string_lossy())
+        .into_iter()
+        .find(|name| json_files.contains(name))
+        .map(|name| parent.join(name))
+}
+
+/// Names of the JSON files of a directory, listed on the first call for this directory
+fn json_files(dir: &Path) -> Arc<HashSet<String>> {
+    if let Some(names) = JSON_FILES_CACHE.lock().unwrap().get(dir) {
+        return names.clone();
+    }
+    let names: HashSet<String> = archives::file_names(dir)
+        .into_iter()
+        .filter(|name| Path::new(name).extension().is_some_and(|e| e.eq_ignore_ascii_case("json")))
+        .collect();
+    log::trace!("{} JSON files in {:?}", names.len(), dir);
+    let names = Arc::new(names);
+    JSON_FILES_CACHE.lock().unwrap().insert(dir.to_path_buf(), names.clone());
+    names
+}
+
+fn sidecar_names(file_name: &str) -> Vec<String> {
+    let (name, counter) = match COUNTER_REGEX.captures(file_name) {
+        Some(captures) => (
+            format!("{}{}", &captures[1], captures.get(3).map_or("", |m| m.as_str())),
+            captures[2].to_string(),
+        ),
+        None => (file_name.to_string(), String::new()),
+    };
+    let mut names = vec![name.clone()];
+    let path = Path::new(&name);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    if let Some(original) = stem.strip_suffix(EDITED_SUFFIX) {
+        let extension = path.extension().map(|e| format!(".{}", e.to_string_lossy()));
+        names.push(format!("{}{}", original, extension.unwrap_or_default()));
+    }
+    let mut candidates = Vec::new();
+    for name in names {
+        for full in [format!("{}{}", name, SUPPLEMENTAL_METADATA), name.clone()] {
+            for truncated in truncations(&full) {
+                candidates.push(format!("{}{}.json", truncated, counter));
+            }
+        }
+    }
+    // older exports : `IMG_0001.json`
+    candidates.push(format!("{}{}.json", stem, counter));
+    candidates.dedup();
+    candidates
+}
+
+/// The name, and its cuts by Takeout when it is long
+fn truncations(name: &str) -> Vec<String> {
+    let length = name.chars().count();
+    let mut truncations = vec![name.to_string()];
+    truncations.extend(
+        TRUNCATED_LENGTHS
+            .filter(|cut| *cut < length)
+            .map(|cut| name.chars().take(cut).collect()),
+    );
+    truncations
+}
+
+fn parse_sidecar(content: &str) -> Option<TakeoutData> {
+    let json: Value = serde_json::from_str(content)
+        .inspect_err(|e| log::warn!("Takeout sidecar can't be parsed : {}", e))
+        .ok()?;
+    // the timestamp is a string of seconds since the Unix epoch
+    let photo_taken_time = json
+        .pointer("/photoTakenTime/timestamp")
+        .and_then(|t| t.as_str().and_then(|t| t.parse().ok()).or_else(|| t.as_i64()))
+        .and_then(|t| DateTime::from_timestamp(t, 0))
+        .map(|t| t.naive_utc());
+    let gps = ["geoData", "geoDataExif"].iter().find_map(|key| {
+        let geo = json.get(key)?;
+        let latitude = geo.get("latitude")?.as_f64()?;
+        let longitude = geo.get("longitude")?.as_f64()?;
+        // 0.0, 0.0 : no position
+        if latitude == 0.0 && longitude == 0.0 {
+            return None;
+        }
+        Some(GpsPosition {
+            latitude,
+            longitude,
+            altitude: geo.get("altitude").and_then(|a| a.as_f64()).filter(|a| *a != 0.0),
+        })
+    });
+    log::debug!("Takeout photoTakenTime = {:?}, geoData = {:?}", photo_taken_time, gps);
+    Some(TakeoutData { photo_taken_time, gps })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    #[test]
+    fn test_read_takeout() {
+        init();
+        let current_dir = std::env::current_dir().unwrap();
+        let dir = Path::new("./test_read_takeout");
+        std::fs::create_dir(dir).unwrap();
+        let sidecar = r#"{
+            "title": "IMG_0001.JPG",
+            "photoTakenTime": { "timestamp": "1224686601", "formatted": "22 oct. 2008, 14:43:21 UTC" },
+            "geoData": { "latitude": 0.0, "longitude": 0.0, "altitude": 0.0 },
+            "geoDataExif": { "latitude": 43.467, "longitude": 11.885, "altitude": 275.5 }
+        }"#;
+        let sidecars = [
+            ("IMG_0001.JPG", "IMG_0001.JPG.json"),
+            ("IMG_0002.JPG", "IMG_0002.JPG.supplemental-metadata.json"),
+            ("IMG_0003(1).JPG", "IMG_0003.JPG.supplemental-metadata(1).json"),
+            ("IMG_0004-edited.JPG", "IMG_0004.JPG.json"),
+            ("PXL_20210304_101112345.MP.jpg", "PXL_20210304_101112345.MP.jpg.supplemental-met.json"),
+            ("Screenshot_2021-03-04-10-11-12-345_com.android.chrome.jpg", "Screenshot_2021-03-04-10-11-12-345_com.android.json"),
+        ];
+        // the directory is listed once
+        for (_, json) in sidecars {
+            std::fs::write(dir.join(json), sidecar).unwrap();
+        }
+        for (image, json) in sidecars {
+            assert_eq!(find_sidecar(&dir.join(image)), Some(dir.join(json)), "{}", image);
+            assert!(is_takeout_sidecar(&dir.join(json)));
+        }
+        assert_eq!(find_sidecar(&dir.join("IMG_0005.JPG")), None);
+
+        let takeout_data = read_takeout(&dir.join("IMG_0001.JPG")).unwrap().unwrap();
+        assert_eq!(
+            takeout_data.photo_taken_time,
+            NaiveDate::from_ymd_opt(2008, 10, 22).unwrap().and_hms_opt(14, 43, 21)
+        );
+        // no position in geoData : the one of geoDataExif
+        let gps = takeout_data.gps.unwrap();
+        assert_eq!((gps.latitude, gps.longitude, gps.altitude), (43.467, 11.885, Some(275.5)));
+
+        // ensure we are in the good directory before cleanup
+        assert_eq!(current_dir, std::env::current_dir().unwrap());
+        // cleanup
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
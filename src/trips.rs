@@ -0,0 +1,131 @@
+//! # trips
+//!
+//! Detection of the trips : days with photos taken farther than a radius around the home
+//! position. Consecutive days away from home make one trip, named after its month and its
+//! main places (`2019-07 Trip - Lisbon, Porto`).
+
+use crate::exif::GpsPosition;
+use crate::layout::{self, Directory};
+use chrono::NaiveDate;
+use std::collections::{BTreeSet, HashMap};
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+// places listed in the name of a trip
+const MAX_TRIP_PLACES: usize = 3;
+
+/// Consecutive days away from home
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trip {
+    pub first_day: NaiveDate,
+    pub last_day: NaiveDate,
+    pub folder: Directory,
+}
+
+/// Parse a position written `latitude,longitude` in decimal degrees
+pub fn parse_position(value: &str) -> Result<GpsPosition, String> {
+    let (latitude, longitude) = value
+        .split_once(',')
+        .ok_or(format!("'{}' is not written latitude,longitude", value))?;
+    let parse = |v: &str, max: f64| -> Result<f64, String> {
+        v.trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|v| v.abs() <= max)
+            .ok_or(format!("invalid coordinate '{}'", v))
+    };
+    Ok(GpsPosition {
+        latitude: parse(latitude, 90.0)?,
+        longitude: parse(longitude, 180.0)?,
+        altitude: None,
+    })
+}
+
+/// Parse a radius around the home position, in kilometers
+pub fn parse_radius_km(value: &str) -> Result<f64, String> {
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|radius| radius.is_finite() && *radius > 0.0)
+        .ok_or(format!("invalid radius '{}', a positive number of kilometers is expected", value))
+}
+
+/// Great-circle distance (haversine formula)
+pub fn distance_km(a: &GpsPosition, b: &GpsPosition) -> f64 {
+    let (lat_a, lat_b) = (a.latitude.to_radians(), b.latitude.to_radians());
+    let d_lat = lat_b - lat_a;
+    let d_long = (b.longitude - a.longitude).to_radians();
+    let h = (d_lat / 2.0).sin().powi(2) + lat_a.cos() * lat_b.cos() * (d_long / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * h.sqrt().asin()
+}
+
+/// Group days away from home into runs of consecutive days
+pub fn group_consecutive_days(days: &BTreeSet<NaiveDate>) -> Vec<(NaiveDate, NaiveDate)> {
+    let mut runs: Vec<(NaiveDate, NaiveDate)> = Vec::new();
+    for day in days {
+        match runs.last_mut() {
+            Some((_, last)) if *day - *last <= chrono::Duration::days(1) => *last = *day,
+            _ => runs.push((*day, *day)),
+        }
+    }
+    runs
+}
+
+/// Build a trip, named after the most frequent places found during it
+pub fn name_trip(first_day: NaiveDate, last_day: NaiveDate, places: &[String]) -> Trip {
+    let mut counts: HashMap<&str, (usize, usize)> = HashMap::new();
+    for (index, place) in places.iter().enumerate() {
+        counts.entry(place).or_insert((0, index)).0 += 1;
+    }
+    let mut main_places: Vec<(&str, (usize, usize))> = counts.into_iter().collect();
+    main_places.sort_by_key(|(_, (count, first_seen))| (std::cmp::Reverse(*count), *first_seen));
+    main_places.truncate(MAX_TRIP_PLACES);
+    // in the order of the visits
+    main_places.sort_by_key(|(_, (_, first_seen))| *first_seen);
+    let main_places: Vec<&str> = main_places.into_iter().map(|(place, _)| place).collect();
+    log::debug!("trip from {} to {} : {:?}", first_day, last_day, main_places);
+    Trip {
+        first_day,
+        last_day,
+        folder: layout::trip_directory(first_day, &main_places),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    #[test]
+    fn test_trips() {
+        init();
+        let lisbon = parse_position("38.7223,-9.1393").unwrap();
+        let porto = parse_position(" 41.1579, -8.6291").unwrap();
+        assert!((distance_km(&lisbon, &porto) - 274.0).abs() < 5.0);
+        assert!(parse_position("38.7223").is_err());
+        assert!(parse_position("98.7,-9.1").is_err());
+        assert_eq!(parse_radius_km("50"), Ok(50.0));
+        for invalid in ["0", "-5", "NaN", "inf", "far"] {
+            assert!(parse_radius_km(invalid).is_err(), "{}", invalid);
+        }
+
+        let day = |d| NaiveDate::from_ymd_opt(2019, 7, d).unwrap();
+        let days: BTreeSet<NaiveDate> = [day(3), day(4), day(5), day(9), day(12), day(13)].into();
+        assert_eq!(
+            group_consecutive_days(&days),
+            vec![(day(3), day(5)), (day(9), day(9)), (day(12), day(13))]
+        );
+
+        let places: Vec<String> = ["Lisbon", "Lisbon", "Sintra", "Porto", "Porto", "Porto", "Braga"]
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        let trip = name_trip(day(3), day(5), &places);
+        assert_eq!(trip.folder.get(), "2019-07 Trip - Lisbon, Sintra, Porto");
+        assert_eq!((trip.first_day, trip.last_day), (day(3), day(5)));
+        assert_eq!(name_trip(day(9), day(9), &[]).folder.get(), "2019-07 Trip");
+    }
+}
@@ -0,0 +1,157 @@
+//! # views
+//!
+//! "Virtual views" of the sorted library : additional trees of relative symlinks
+//! (`By-Place/<place>/<year_month>/...`, `By-Device/<device>/<year_month>/...`) pointing
+//! into the primary sorted tree, so that one physical copy can be browsed along several axes.
+
+use crate::directories;
+use crate::global_configuration::GlobalConfiguration;
+use crate::layout::FolderNames;
+use crate::reporting::Reporting;
+use eyre::Result;
+use std::path::{Component, Path, PathBuf};
+
+const BY_PLACE_DIRNAME: &str = "By-Place";
+const BY_DEVICE_DIRNAME: &str = "By-Device";
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum VirtualView {
+    /// By-Place/<place>/<year_month>/
+    Place,
+    /// By-Device/<device>/<year_month>/
+    Device,
+}
+
+impl VirtualView {
+    fn dirname(&self) -> &'static str {
+        match self {
+            VirtualView::Place => BY_PLACE_DIRNAME,
+            VirtualView::Device => BY_DEVICE_DIRNAME,
+        }
+    }
+
+    /// Path, relative to the sorted images directory, of the directory holding the link
+    fn link_directory(&self, folders: &FolderNames) -> PathBuf {
+        let key = match self {
+            VirtualView::Place => folders.place.get(),
+            VirtualView::Device => folders.device.get(),
+        };
+        Path::new(self.dirname()).join(key).join(folders.date.get())
+    }
+}
+
+/// Create, for each configured view, a relative symlink to `target` (a file already copied
+/// in the primary sorted tree).
+pub fn create_views(
+    target: &Path,
+    folders: &FolderNames,
+    configuration: &GlobalConfiguration,
+) -> Result<()> {
+    log::trace!("create_views for {:?}", target);
+    let sorted_dir = configuration.sorted_images_directory_as_path();
+    let relative_target = target.strip_prefix(sorted_dir)?;
+    // unwrap() is ok here, target is a copied file
+    let filename = target.file_name().unwrap();
+
+    for view in configuration.views() {
+        let link_directory = view.link_directory(folders);
+        let link_parent = directories::create_subdir(sorted_dir, link_directory.as_path())?;
+        let link_target = relative_link_target(link_directory.as_path(), relative_target);
+        let link = link_parent.join(filename);
+        if let Some(link) = find_free_link_path(link.as_path(), link_target.as_path())? {
+            log::debug!("link {:?} -> {:?}", link, link_target);
+            directories::create_symlink(link_target.as_path(), link.as_path())?;
+            Reporting::view_link_created();
+        }
+    }
+
+    Ok(())
+}
+
+/// Build the relative path leading from `link_directory` to `target`, both being relative to
+/// the sorted images directory.
+fn relative_link_target(link_directory: &Path, target: &Path) -> PathBuf {
+    let depth = link_directory
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .count();
+    let mut link_target = PathBuf::new();
+    for _ in 0..depth {
+        link_target.push("..");
+    }
+    link_target.join(target)
+}
+
+/// Return the path where the link can be created, or None if an identical link already exists.
+/// Links with the same name pointing elsewhere get a numeric suffix.
+fn find_free_link_path(link: &Path, link_target: &Path) -> Result<Option<PathBuf>> {
+    let parent = link.parent().unwrap();
+    let filename = link.file_name().unwrap().to_string_lossy();
+    let (name, ext) = if let Some(dot_pos) = filename.rfind('.') {
+        (&filename[..dot_pos], &filename[dot_pos..])
+    } else {
+        (filename.as_ref(), "")
+    };
+
+    let mut candidate = link.to_path_buf();
+    for n in 2..1000 {
+        // symlink_metadata() does not follow the link : a dangling link is still "taken"
+        if candidate.symlink_metadata().is_err() {
+            return Ok(Some(candidate));
+        }
+        if std::fs::read_link(&candidate).is_ok_and(|existing| existing == link_target) {
+            return Ok(None);
+        }
+        candidate = parent.join(format!("{}_{}{}", name, n, ext));
+    }
+
+    Err(eyre::eyre!("Unable to find a free link name for {:?}", link))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    #[test]
+    fn test_relative_link_target() {
+        init();
+        let link_directory = Path::new("By-Place/Arezzo/2008 10");
+        let target = Path::new("2008 10/Arezzo/COOLPIX/DSCN0025.jpg");
+        assert_eq!(
+            relative_link_target(link_directory, target),
+            PathBuf::from("../../../2008 10/Arezzo/COOLPIX/DSCN0025.jpg")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_free_link_path() {
+        init();
+        let current_dir = std::env::current_dir().unwrap();
+        let dir = Path::new("./test_views_links");
+        std::fs::create_dir(dir).unwrap();
+        let link = dir.join("foo.jpg");
+
+        // No link yet
+        let free = find_free_link_path(&link, Path::new("../a/foo.jpg")).unwrap();
+        assert_eq!(free, Some(link.clone()));
+        directories::create_symlink(Path::new("../a/foo.jpg"), &link).unwrap();
+
+        // Same target : nothing to do
+        let free = find_free_link_path(&link, Path::new("../a/foo.jpg")).unwrap();
+        assert_eq!(free, None);
+
+        // Other target : suffixed name
+        let free = find_free_link_path(&link, Path::new("../b/foo.jpg")).unwrap();
+        assert_eq!(free, Some(dir.join("foo_2.jpg")));
+
+        // ensure we are in the good directory before cleanup
+        assert_eq!(current_dir, std::env::current_dir().unwrap());
+        // cleanup
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
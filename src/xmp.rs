@@ -0,0 +1,314 @@
+//! # xmp
+//!
+//! Minimal reading of the XMP packet embedded in a file (JPEG APP1, TIFF, HEIF...) or
+//! written in a sidecar file next to it (`IMG_0001.xmp` or `IMG_0001.JPG.xmp`) : the packet
+//! is plain XML, so it is searched directly in the bytes of the file.
+
+use crate::archives;
+use crate::exif::{self, GpsPosition};
+use crate::jpeg::{Segment, JPEG_APP1};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::io::Read;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+static KEYWORDS_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?s)<dc:subject>(.*?)</dc:subject>").unwrap());
+static LIST_ITEM_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?s)<rdf:li[^>]*>(.*?)</rdf:li>").unwrap());
+// item of the container directory of a Motion Photo holding the video
+static MOTION_PHOTO_ITEM_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"<Container:Item\b[^>]*Item:Semantic\s*=\s*["']MotionPhoto["'][^>]*>"#).unwrap());
+// regex of each property read, built on its first use
+static PROPERTY_REGEXES: Lazy<RwLock<HashMap<String, Regex>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+
+// The packet is at the beginning of JPEG files, but may be further in other containers
+const MAX_SCANNED_BYTES: u64 = 4 * 1024 * 1024;
+const XMP_PACKET_START: &str = "<x:xmpmeta";
+const XMP_PACKET_END: &str = "</x:xmpmeta>";
+// namespace starting the APP1 segment of the packet in a JPEG file
+const XMP_APP1_SIGNATURE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+/// Which of EXIF or XMP wins when both have a value (XMP is where Lightroom, darktable...
+/// write the corrections)
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum XmpPrecedence {
+    Exif,
+    #[default]
+    Xmp,
+}
+
+/// Metadata found in XMP
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct XmpData {
+    /// Raw value of the capture date (exif:DateTimeOriginal, xmp:CreateDate or
+    /// photoshop:DateCreated)
+    pub date: Option<String>,
+    pub gps: Option<GpsPosition>,
+    pub rating: Option<i32>,
+    pub keywords: Vec<String>,
+    /// GCamera:MotionPhoto or GCamera:MicroVideo flag of Google Motion Photos
+    pub motion_photo: bool,
+    /// Length of the video appended to a Motion Photo (GCamera:MicroVideoOffset, or length
+    /// of the MotionPhoto item of the container directory)
+    pub motion_video_length: Option<u64>,
+}
+
+impl XmpData {
+    /// Offset from UTC written with the date, if any
+    pub fn date_offset(&self) -> Option<&str> {
+        let date = self.date.as_deref()?;
+        exif::written_offset(date)
+    }
+}
+
+/// Read the XMP metadata of a file : the sidecar file wins over the `embedded` packet, already
+/// read from the file, as it is the one updated by the photo editors
+pub fn read_xmp(path: &Path, embedded: Option<String>) -> std::io::Result<Option<XmpData>> {
+    let sidecar = match find_sidecar(path) {
+        Some(sidecar) => {
+            log::debug!("XMP sidecar {:?} found for {:?}", sidecar, path);
+            read_xmp_packet(&sidecar)?
+        }
+        None => None,
+    };
+    let mut packets = sidecar.iter().chain(embedded.iter()).map(|p| parse_packet(p));
+    let Some(mut xmp_data) = packets.next() else {
+        return Ok(None);
+    };
+    for fallback in packets {
+        xmp_data.date = xmp_data.date.or(fallback.date);
+        xmp_data.gps = xmp_data.gps.or(fallback.gps);
+        xmp_data.rating = xmp_data.rating.or(fallback.rating);
+        if xmp_data.keywords.is_empty() {
+            xmp_data.keywords = fallback.keywords;
+        }
+        xmp_data.motion_photo |= fallback.motion_photo;
+        xmp_data.motion_video_length = xmp_data.motion_video_length.or(fallback.motion_video_length);
+    }
+    Ok(Some(xmp_data))
+}
+
+/// Find the sidecar of a file : `IMG_0001.xmp` (darktable writes `IMG_0001.JPG.xmp`)
+pub fn find_sidecar(path: &Path) -> Option<PathBuf> {
+    let filename = path.file_name()?.to_string_lossy();
+    let stem = path.file_stem()?.to_string_lossy();
+    let parent = path.parent()?;
+    [
+        format!("{}.xmp", filename),
+        format!("{}.XMP", filename),
+        format!("{}.xmp", stem),
+        format!("{}.XMP", stem),
+    ]
+    .iter()
+    .map(|name| parent.join(name))
+    .find(|candidate| candidate.as_path() != path && archives::is_file(candidate))
+}
+
+fn parse_packet(packet: &str) -> XmpData {
+    let gps = |name: &str| get_property(packet, name).and_then(|v| parse_gps_coordinate(&v));
+    let gps = match (gps("exif:GPSLatitude"), gps("exif:GPSLongitude")) {
+        (Some(latitude), Some(longitude)) => Some(GpsPosition {
+            latitude,
+            longitude,
+            altitude: get_property(packet, "exif:GPSAltitude").and_then(|v| parse_rational(&v)),
+        }),
+        _ => None,
+    };
+    let keywords = KEYWORDS_REGEX
+        .captures(packet)
+        .map(|bag| {
+            LIST_ITEM_REGEX
+                .captures_iter(&bag[1])
+                .map(|li| unescape(li[1].trim()))
+                .filter(|k| !k.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+    XmpData {
+        date: ["exif:DateTimeOriginal", "xmp:CreateDate", "photoshop:DateCreated"]
+            .iter()
+            .find_map(|name| get_property(packet, name)),
+        gps,
+        rating: get_property(packet, "xmp:Rating").and_then(|v| v.parse().ok()),
+        keywords,
+        motion_photo: ["GCamera:MotionPhoto", "GCamera:MicroVideo"]
+            .iter()
+            .any(|name| get_property(packet, name).as_deref() == Some("1")),
+        motion_video_length: get_property(packet, "GCamera:MicroVideoOffset")
+            .or_else(|| get_property(MOTION_PHOTO_ITEM_REGEX.find(packet)?.as_str(), "Item:Length"))
+            .and_then(|v| v.parse().ok()),
+    }
+}
+
+/// Parse a GPS coordinate written `43,28.0N` or `43,28,1.5N` (degrees, minutes and
+/// optionally seconds, then the direction) as decimal degrees
+fn parse_gps_coordinate(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let direction = value.chars().last()?;
+    let sign = match direction.to_ascii_uppercase() {
+        'N' | 'E' => 1.0,
+        'S' | 'W' => -1.0,
+        _ => return None,
+    };
+    let numbers: Vec<f64> = value[..value.len() - 1]
+        .split(',')
+        .map(|n| n.trim().parse::<f64>())
+        .collect::<Result<_, _>>()
+        .ok()?;
+    let degrees = match numbers[..] {
+        [degrees, minutes] => degrees + minutes / 60.0,
+        [degrees, minutes, seconds] => degrees + minutes / 60.0 + seconds / 3600.0,
+        _ => return None,
+    };
+    Some(sign * degrees)
+}
+
+/// Parse a rational written `1234/10`
+fn parse_rational(value: &str) -> Option<f64> {
+    let (numerator, denominator) = value.split_once('/').unwrap_or((value, "1"));
+    let denominator: f64 = denominator.trim().parse().ok()?;
+    Some(numerator.trim().parse::<f64>().ok()? / denominator).filter(|v| v.is_finite())
+}
+
+fn unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Return the XMP packet embedded in the file, if any
+pub fn read_xmp_packet(path: &Path) -> std::io::Result<Option<String>> {
+    log::trace!("read_xmp_packet of {:?}", path);
+    let mut bytes = Vec::new();
+    archives::open(path)?
+        .take(MAX_SCANNED_BYTES)
+        .read_to_end(&mut bytes)?;
+    Ok(find_xmp_packet(&bytes))
+}
+
+/// Find the XMP packet in the APP1 segments of a JPEG file
+pub fn find_segments_packet(segments: &[Segment]) -> Option<String> {
+    segments
+        .iter()
+        .filter(|segment| segment.marker == JPEG_APP1)
+        .find_map(|segment| find_xmp_packet(segment.payload.strip_prefix(XMP_APP1_SIGNATURE)?))
+}
+
+/// Find the XMP packet in a buffer
+pub fn find_xmp_packet(bytes: &[u8]) -> Option<String> {
+    let start = find(bytes, XMP_PACKET_START.as_bytes())?;
+    let end = find(&bytes[start..], XMP_PACKET_END.as_bytes())? + start + XMP_PACKET_END.len();
+    Some(String::from_utf8_lossy(&bytes[start..end]).to_string())
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Get the value of a simple property (e.g. `xmp:CreateDate`), written either as an
+/// attribute (`xmp:CreateDate="..."`) or as an element (`<xmp:CreateDate>...</xmp:CreateDate>`)
+pub fn get_property(packet: &str, name: &str) -> Option<String> {
+    if let Some(re) = PROPERTY_REGEXES.read().unwrap().get(name) {
+        return find_property(re, packet);
+    }
+    let escaped = regex::escape(name);
+    let re = Regex::new(&format!(
+        r#"{escaped}\s*=\s*["']([^"']*)["']|<{escaped}>([^<]*)</{escaped}>"#
+    ))
+    .unwrap();
+    let value = find_property(&re, packet);
+    PROPERTY_REGEXES.write().unwrap().insert(name.to_string(), re);
+    value
+}
+
+fn find_property(re: &Regex, packet: &str) -> Option<String> {
+    let captures = re.captures(packet)?;
+    captures
+        .get(1)
+        .or(captures.get(2))
+        .map(|m| m.as_str().trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    #[test]
+    fn test_get_property() {
+        init();
+        let bytes = b"\xff\xd8\xff\xe1garbage<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"><rdf:RDF>\
+            <rdf:Description xmp:CreateDate=\"2019-08-12T14:30:55\">\
+            <photoshop:DateCreated>2019-08-11</photoshop:DateCreated>\
+            </rdf:Description></rdf:RDF></x:xmpmeta>\xff\xd9";
+        let packet = find_xmp_packet(bytes).unwrap();
+        assert!(packet.starts_with("<x:xmpmeta") && packet.ends_with("</x:xmpmeta>"));
+        assert_eq!(
+            get_property(&packet, "xmp:CreateDate"),
+            Some(String::from("2019-08-12T14:30:55"))
+        );
+        assert_eq!(
+            get_property(&packet, "photoshop:DateCreated"),
+            Some(String::from("2019-08-11"))
+        );
+        assert_eq!(get_property(&packet, "xmp:ModifyDate"), None);
+        assert_eq!(find_xmp_packet(b"no packet here"), None);
+
+        let motion_photo = parse_packet(
+            "<rdf:Description GCamera:MotionPhoto=\"1\"><Container:Directory><rdf:Seq>\
+            <rdf:li><Container:Item Item:Mime=\"image/jpeg\" Item:Semantic=\"Primary\" Item:Length=\"0\"/></rdf:li>\
+            <rdf:li><Container:Item Item:Mime=\"video/mp4\" Item:Semantic=\"MotionPhoto\" Item:Length=\"1843211\"/></rdf:li>\
+            </rdf:Seq></Container:Directory></rdf:Description>",
+        );
+        assert!(motion_photo.motion_photo);
+        assert_eq!(motion_photo.motion_video_length, Some(1843211));
+    }
+
+    #[test]
+    fn test_read_xmp() {
+        init();
+        let current_dir = std::env::current_dir().unwrap();
+        let dir = Path::new("./test_read_xmp");
+        std::fs::create_dir(dir).unwrap();
+        let image = dir.join("DSCN0025.jpg");
+        std::fs::copy("data_4_tests/DSCN0025.jpg", &image).unwrap();
+        let segments = crate::jpeg::read_segments(&image, &[JPEG_APP1]).unwrap().unwrap();
+        let embedded = find_segments_packet(&segments);
+        // the embedded packet has no date
+        assert_eq!(read_xmp(&image, embedded.clone()).unwrap().and_then(|x| x.date), None);
+
+        std::fs::write(
+            dir.join("DSCN0025.jpg.xmp"),
+            "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"><rdf:RDF><rdf:Description \
+            exif:DateTimeOriginal=\"2008-10-22T17:43:21+02:00\" xmp:Rating=\"4\" \
+            exif:GPSLatitude=\"43,28.02N\" exif:GPSLongitude=\"11,53,0.5E\" exif:GPSAltitude=\"2755/10\">\
+            <dc:subject><rdf:Bag><rdf:li>Tuscany</rdf:li><rdf:li>Family &amp; friends</rdf:li></rdf:Bag></dc:subject>\
+            </rdf:Description></rdf:RDF></x:xmpmeta>",
+        )
+        .unwrap();
+        let xmp_data = read_xmp(&image, embedded).unwrap().unwrap();
+        assert_eq!(xmp_data.date.as_deref(), Some("2008-10-22T17:43:21+02:00"));
+        assert_eq!(xmp_data.date_offset(), Some("+02:00"));
+        assert_eq!(xmp_data.rating, Some(4));
+        assert_eq!(xmp_data.keywords, vec!["Tuscany", "Family & friends"]);
+        let gps = xmp_data.gps.unwrap();
+        assert!((gps.latitude - 43.467).abs() < 0.001);
+        assert!((gps.longitude - 11.8835).abs() < 0.001);
+        assert_eq!(gps.altitude, Some(275.5));
+        assert_eq!(parse_gps_coordinate("22,54.5S"), Some(-22.908333333333335));
+
+        // ensure we are in the good directory before cleanup
+        assert_eq!(current_dir, std::env::current_dir().unwrap());
+        // cleanup
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}